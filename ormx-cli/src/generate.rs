@@ -0,0 +1,204 @@
+use std::fmt::Write;
+
+use anyhow::{bail, Result};
+use heck::{ToSnakeCase, ToUpperCamelCase};
+
+use crate::schema::{RustType, Table};
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while", "abstract", "become", "box", "do", "final", "macro",
+    "override", "priv", "try", "typeof", "unsized", "virtual", "yield",
+];
+
+/// Formats a struct deriving `ormx::Table` for the table.
+pub fn table_struct(table: &Table) -> Result<String> {
+    let id = match &table.primary_key {
+        Some(id) => field_name(id)?,
+        None => bail!(
+            "table \"{}\" has no primary key consisting of a single column, which ormx requires",
+            table.name
+        ),
+    };
+
+    let mut out = String::new();
+    writeln!(out, "#[derive(Debug, ormx::Table)]")?;
+    writeln!(
+        out,
+        "#[ormx(table = {:?}, id = {}, insertable)]",
+        table.name, id
+    )?;
+    writeln!(out, "pub struct {} {{", struct_name(&table.name)?)?;
+
+    for column in &table.columns {
+        let field = field_name(&column.name)?;
+        let is_id = table.primary_key.as_ref() == Some(&column.name);
+
+        let mut attrs = vec![];
+        if field != column.name {
+            attrs.push(format!("column = \"{}\"", column.name));
+        }
+        if column.has_default {
+            attrs.push("default".to_owned());
+        }
+        let ty = match &column.ty {
+            RustType::Builtin(ty) => ty.clone(),
+            RustType::Custom(ty) => {
+                attrs.push("custom_type".to_owned());
+                ty.clone()
+            }
+            RustType::Unknown(sql_type) => {
+                eprintln!(
+                    "warning: unknown type \"{}\" of column {}.{}",
+                    sql_type, table.name, column.name
+                );
+                writeln!(out, "    // TODO: unknown SQL type \"{}\"", sql_type)?;
+                "String".to_owned()
+            }
+        };
+        if !is_id && table.unique.contains(&column.name) {
+            match &*ty {
+                "String" => attrs.push("get_optional(&str)".to_owned()),
+                _ => attrs.push("get_optional".to_owned()),
+            }
+        }
+
+        if !attrs.is_empty() {
+            writeln!(out, "    #[ormx({})]", attrs.join(", "))?;
+        }
+        match column.nullable {
+            true => writeln!(out, "    pub {}: Option<{}>,", field, ty)?,
+            false => writeln!(out, "    pub {}: {},", field, ty)?,
+        }
+    }
+
+    writeln!(out, "}}")?;
+    Ok(out)
+}
+
+/// Name of the struct for a table, e.g. `User` for `users`.
+fn struct_name(table: &str) -> Result<String> {
+    let name = table.rsplit('.').next().unwrap_or(table);
+    let name = name.trim_matches(|c| c == '"' || c == '`');
+    let singular = if let Some(stem) = name.strip_suffix("ies") {
+        format!("{}y", stem)
+    } else if name.ends_with("ss") || name.ends_with("us") {
+        name.to_owned()
+    } else if let Some(stem) = name.strip_suffix('s') {
+        stem.to_owned()
+    } else {
+        name.to_owned()
+    };
+    match escape(singular.to_upper_camel_case()) {
+        Some(name) => Ok(name),
+        None => bail!(
+            "the name of table \"{}\" can't be turned into a struct name",
+            table
+        ),
+    }
+}
+
+/// Name of the field for a column.
+fn field_name(column: &str) -> Result<String> {
+    match escape(column.to_snake_case()) {
+        Some(field) => Ok(field),
+        None => bail!(
+            "the name of column \"{}\" can't be turned into a field name",
+            column
+        ),
+    }
+}
+
+/// Turns `name` into an identifier, prefixing it with `_` if it starts with a digit, and giving
+/// keywords a trailing underscore. Returns `None` if `name` is empty.
+fn escape(name: String) -> Option<String> {
+    match name.chars().next()? {
+        c if c.is_ascii_digit() => Some(format!("_{}", name)),
+        _ if KEYWORDS.contains(&&*name) => Some(format!("{}_", name)),
+        _ => Some(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::Column;
+
+    fn column(name: &str, ty: &str, nullable: bool) -> Column {
+        Column {
+            name: name.to_owned(),
+            sql_type: ty.to_owned(),
+            ty: RustType::Builtin(ty.to_owned()),
+            nullable,
+            has_default: false,
+            definition: None,
+        }
+    }
+
+    #[test]
+    fn struct_names_are_singular() {
+        assert_eq!(struct_name("users").unwrap(), "User");
+        assert_eq!(struct_name("public.categories").unwrap(), "Category");
+        assert_eq!(struct_name("\"orders\"").unwrap(), "Order");
+        assert_eq!(struct_name("`status`").unwrap(), "Status");
+        assert_eq!(struct_name("user_settings").unwrap(), "UserSetting");
+        assert_eq!(struct_name("selfs").unwrap(), "Self_");
+        assert_eq!(struct_name("2fa_codes").unwrap(), "_2faCode");
+        assert!(struct_name("\"__\"").is_err());
+    }
+
+    #[test]
+    fn field_names_are_identifiers() {
+        assert_eq!(field_name("firstName").unwrap(), "first_name");
+        assert_eq!(field_name("type").unwrap(), "type_");
+        assert_eq!(field_name("2fa_enabled").unwrap(), "_2fa_enabled");
+        assert_eq!(field_name("1").unwrap(), "_1");
+        assert!(field_name("").is_err());
+        assert!(field_name("?!").is_err());
+    }
+
+    #[test]
+    fn table_struct_renames_columns() {
+        let mut id = column("id", "i32", false);
+        id.has_default = true;
+        let table = Table {
+            name: "users".to_owned(),
+            columns: vec![
+                id,
+                column("email", "String", false),
+                column("type", "String", true),
+                column("2fa", "bool", false),
+            ],
+            primary_key: Some("id".to_owned()),
+            unique: vec!["email".to_owned()],
+        };
+        assert_eq!(
+            table_struct(&table).unwrap(),
+            "#[derive(Debug, ormx::Table)]\n\
+             #[ormx(table = \"users\", id = id, insertable)]\n\
+             pub struct User {\n\
+             \x20   #[ormx(default)]\n\
+             \x20   pub id: i32,\n\
+             \x20   #[ormx(get_optional(&str))]\n\
+             \x20   pub email: String,\n\
+             \x20   #[ormx(column = \"type\")]\n\
+             \x20   pub type_: Option<String>,\n\
+             \x20   #[ormx(column = \"2fa\")]\n\
+             \x20   pub _2fa: bool,\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn table_struct_requires_a_primary_key() {
+        let table = Table {
+            name: "logs".to_owned(),
+            columns: vec![column("message", "String", false)],
+            primary_key: None,
+            unique: vec![],
+        };
+        assert!(table_struct(&table).is_err());
+    }
+}
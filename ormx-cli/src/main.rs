@@ -0,0 +1,142 @@
+//! Command line tool for ormx.
+//!
+//! ```text
+//! ormx-cli generate --table users --table posts
+//! ormx-cli migrate --source src --migrations migrations
+//! ```
+
+use std::{fs, path::PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand};
+use schema::Dialect;
+
+mod generate;
+mod migrate;
+mod schema;
+mod source;
+mod sql;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// URL of the database, e.g. `postgres://user@localhost/db`.
+    #[arg(long, env = "DATABASE_URL", global = true)]
+    database_url: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Prints a struct deriving `ormx::Table` for each of the given tables.
+    Generate {
+        /// Name of a table, optionally prefixed with its schema.
+        #[arg(long, short, required = true)]
+        table: Vec<String>,
+    },
+    /// Compares the tables defined in a crate with the database, or with a schema dump, and
+    /// writes a migration containing the necessary changes.
+    Migrate {
+        /// Directory containing the source code of the crate.
+        #[arg(long, default_value = "src")]
+        source: PathBuf,
+        /// SQL schema dump to compare against instead of the database.
+        #[arg(long)]
+        schema: Option<PathBuf>,
+        /// SQL dialect of the schema dump. Defaults to the dialect of the database url.
+        #[arg(long, value_enum)]
+        dialect: Option<Dialect>,
+        /// Directory into which the migration is written.
+        #[arg(long, default_value = "migrations")]
+        migrations: PathBuf,
+        /// Description of the migration, which is part of its file name.
+        #[arg(long, default_value = "ormx")]
+        description: String,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let url = cli.database_url.as_deref();
+
+    match cli.command {
+        Command::Generate { table } => {
+            let mut structs = vec![];
+            for table in table {
+                let table = schema::read_table(database_url(url)?, &table).await?;
+                structs.push(generate::table_struct(&table)?);
+            }
+            println!("{}", structs.join("\n"));
+        }
+        Command::Migrate {
+            source,
+            schema,
+            dialect,
+            migrations,
+            description,
+        } => {
+            let dialect = match (dialect, url) {
+                (Some(dialect), _) => dialect,
+                (None, Some(url)) => Dialect::from_url(url)?,
+                (None, None) => bail!("no dialect given, use --dialect or --database-url"),
+            };
+            if dialect == Dialect::Sqlite {
+                bail!("tables on sqlite are not supported by ormx");
+            }
+
+            let models = source::read_models(&source, dialect)?;
+            let dump = match &schema {
+                Some(schema) => Some(schema::dump::read_tables(&fs::read_to_string(schema)?)?),
+                None => None,
+            };
+
+            let mut statements = vec![];
+            for model in &models {
+                let fetched;
+                let table = match &dump {
+                    Some(dump) => dump
+                        .iter()
+                        .find(|table| same_table(&table.name, &model.table)),
+                    None => {
+                        fetched = schema::find_table(database_url(url)?, &model.table).await?;
+                        fetched.as_ref()
+                    }
+                };
+                statements.extend(migrate::migrate_table(dialect, model, table));
+            }
+
+            if statements.is_empty() {
+                println!("the schema is up to date");
+                return Ok(());
+            }
+
+            fs::create_dir_all(&migrations)?;
+            let version = chrono::Utc::now().format("%Y%m%d%H%M%S");
+            let path = migrations.join(format!("{}_{}.sql", version, description));
+            let sql = statements
+                .iter()
+                .map(|statement| format!("{};\n", statement))
+                .collect::<Vec<_>>()
+                .join("\n");
+            fs::write(&path, sql)?;
+            println!("wrote {}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn database_url(url: Option<&str>) -> Result<&str> {
+    url.ok_or_else(|| anyhow!("no database url given, use --database-url or DATABASE_URL"))
+}
+
+/// Whether two table names refer to the same table. A name without a schema matches the table in
+/// any schema.
+fn same_table(a: &str, b: &str) -> bool {
+    match (schema::split_name(a), schema::split_name(b)) {
+        ((Some(a_schema), a), (Some(b_schema), b)) => a_schema == b_schema && a == b,
+        ((_, a), (_, b)) => a == b,
+    }
+}
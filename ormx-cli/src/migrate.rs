@@ -0,0 +1,330 @@
+use crate::{
+    schema::{Column, Dialect, Table},
+    source::{Model, ModelColumn},
+    sql,
+};
+
+/// Returns the statements migrating `table` to `model`. If `table` is `None`, it is created.
+pub fn migrate_table(dialect: Dialect, model: &Model, table: Option<&Table>) -> Vec<String> {
+    let table = match table {
+        Some(table) => table,
+        None => return vec![create_table(dialect, model)],
+    };
+
+    let quote = |ident: &str| dialect.quote(ident);
+    let alter = |action: String| format!("ALTER TABLE {} {}", model.table, action);
+    let mut statements = vec![];
+
+    for column in &model.columns {
+        let existing = match table.columns.iter().find(|c| c.name == column.name) {
+            Some(existing) => existing,
+            None => {
+                let mut statement = String::new();
+                if !column.nullable && !column.generated {
+                    statement.push_str("-- existing rows need a value for this column\n");
+                }
+                statement.push_str(&alter(format!(
+                    "ADD COLUMN {}",
+                    column_definition(dialect, column)
+                )));
+                statements.push(statement);
+                continue;
+            }
+        };
+
+        let retyped = column.known_type
+            && sql::type_family(&column.sql_type) != sql::type_family(&existing.sql_type);
+        let nullability_changed = column.nullable != existing.nullable;
+        if !retyped && !nullability_changed {
+            continue;
+        }
+
+        match dialect {
+            Dialect::Mysql => match modify_definition(column, existing, retyped) {
+                Some(definition) => statements.push(alter(format!(
+                    "MODIFY COLUMN {} {}",
+                    quote(&column.name),
+                    definition
+                ))),
+                None => statements.push(format!(
+                    "-- the definition of the column {} of {} is unknown, so it can't be modified \
+                     without dropping its default value, comment, charset and collation",
+                    column.name, model.table
+                )),
+            },
+            Dialect::Postgres => {
+                if retyped {
+                    statements.push(alter(format!(
+                        "ALTER COLUMN {} TYPE {}",
+                        quote(&column.name),
+                        sql::strip_constraints(&column.sql_type)
+                    )));
+                }
+                if nullability_changed {
+                    let action = if column.nullable { "DROP" } else { "SET" };
+                    statements.push(alter(format!(
+                        "ALTER COLUMN {} {} NOT NULL",
+                        quote(&column.name),
+                        action
+                    )));
+                }
+            }
+            Dialect::Sqlite => statements.push(format!(
+                "-- sqlite can't alter the column {} of {}, the table has to be recreated",
+                column.name, model.table
+            )),
+        }
+    }
+
+    for existing in &table.columns {
+        if !model.columns.iter().any(|c| c.name == existing.name) {
+            statements.push(format!(
+                "-- the column isn't part of the model, dropping it deletes its data\n{}",
+                alter(format!("DROP COLUMN {}", quote(&existing.name)))
+            ));
+        }
+    }
+
+    statements
+}
+
+/// Formats the definition of `column` for mysql's `MODIFY COLUMN`, which replaces the whole
+/// definition of the `existing` column. Its other attributes, like the default value and comment,
+/// are kept, as is the charset and collation unless the column is `retyped`.
+/// Returns `None` if the definition of the existing column is unknown.
+fn modify_definition(column: &ModelColumn, existing: &Column, retyped: bool) -> Option<String> {
+    let definition = existing.definition.as_deref()?;
+    let existing_type = sql::strip_constraints(definition);
+    let words = split_words(&definition[existing_type.len()..]);
+
+    let mut charset: Vec<&str> = vec![];
+    let mut attributes: Vec<&str> = vec![];
+    let mut idx = 0;
+    while idx < words.len() {
+        let upper = words[idx].to_uppercase();
+        let next = words.get(idx + 1).map(|word| word.to_uppercase());
+        match (&*upper, next.as_deref()) {
+            // the type is followed by its charset and collation
+            ("CHARACTER", Some("SET")) => {
+                charset.extend(&words[idx..(idx + 3).min(words.len())]);
+                idx += 3;
+            }
+            ("CHARSET", _) | ("COLLATE", _) => {
+                charset.extend(&words[idx..(idx + 2).min(words.len())]);
+                idx += 2;
+            }
+            // nullability is replaced, and keys are kept by MODIFY COLUMN
+            ("NOT", Some("NULL")) | ("PRIMARY", Some("KEY")) | ("UNIQUE", Some("KEY")) => idx += 2,
+            ("NULL", _) | ("UNIQUE", _) => idx += 1,
+            ("DEFAULT", Some("NULL")) if !column.nullable => idx += 2,
+            ("DEFAULT", Some(_)) | ("COMMENT", Some(_)) => {
+                attributes.extend(&words[idx..idx + 2]);
+                idx += 2;
+            }
+            _ => {
+                attributes.push(words[idx]);
+                idx += 1;
+            }
+        }
+    }
+
+    let mut out = match retyped {
+        true => vec![sql::strip_constraints(&column.sql_type)],
+        false => vec![existing_type],
+    };
+    if !retyped {
+        out.extend(charset);
+    }
+    out.push(if column.nullable { "NULL" } else { "NOT NULL" });
+    out.extend(attributes);
+    Some(out.join(" "))
+}
+
+/// Splits the attributes of a column definition at whitespace, keeping quoted strings and
+/// parenthesized expressions, like `DEFAULT (now())`, together.
+fn split_words(attributes: &str) -> Vec<&str> {
+    let mut words = vec![];
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = None;
+    for (idx, c) in attributes.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '\'') | (None, '"') | (None, '`') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, c) if c.is_whitespace() && depth == 0 => {
+                if let Some(start) = start.take() {
+                    words.push(&attributes[start..idx]);
+                }
+                continue;
+            }
+            _ => (),
+        }
+        start.get_or_insert(idx);
+    }
+    if let Some(start) = start {
+        words.push(&attributes[start..]);
+    }
+    words
+}
+
+/// Formats a `CREATE TABLE` statement, like `TableMeta::create_table_sql`.
+fn create_table(dialect: Dialect, model: &Model) -> String {
+    let columns = model
+        .columns
+        .iter()
+        .map(|column| format!("    {}", column_definition(dialect, column)))
+        .collect::<Vec<_>>()
+        .join(",\n");
+    format!("CREATE TABLE {} (\n{}\n)", model.table, columns)
+}
+
+fn column_definition(dialect: Dialect, column: &ModelColumn) -> String {
+    let mut sql_type = column.sql_type.clone();
+    if column.generated {
+        sql_type = sql::serial_type(dialect, &sql_type);
+    }
+
+    let mut definition = format!("{} {}", dialect.quote(&column.name), sql_type);
+    if !column.nullable {
+        definition.push_str(" NOT NULL");
+    }
+    if column.id {
+        definition.push_str(" PRIMARY KEY");
+    } else if column.unique {
+        definition.push_str(" UNIQUE");
+    }
+    definition
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::dump;
+
+    fn column(name: &str, sql_type: &str, nullable: bool) -> ModelColumn {
+        ModelColumn {
+            name: name.to_owned(),
+            sql_type: sql_type.to_owned(),
+            known_type: true,
+            nullable,
+            id: name == "id",
+            generated: false,
+            unique: false,
+        }
+    }
+
+    fn migrate(dialect: Dialect, columns: Vec<ModelColumn>, existing: &str) -> Vec<String> {
+        let model = Model {
+            table: "users".to_owned(),
+            columns,
+        };
+        let table = dump::read_tables(existing).unwrap().pop();
+        migrate_table(dialect, &model, table.as_ref())
+    }
+
+    #[test]
+    fn creates_missing_table() {
+        let columns = vec![column("id", "INTEGER", false), column("name", "TEXT", true)];
+        assert_eq!(
+            migrate(Dialect::Postgres, columns, ""),
+            ["CREATE TABLE users (\n    id INTEGER NOT NULL PRIMARY KEY,\n    name TEXT\n)"]
+        );
+    }
+
+    #[test]
+    fn adds_and_drops_columns() {
+        let columns = vec![
+            column("id", "INTEGER", false),
+            column("age", "INTEGER", false),
+        ];
+        let existing = "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)";
+        assert_eq!(
+            migrate(Dialect::Postgres, columns, existing),
+            [
+                "-- existing rows need a value for this column\n\
+                 ALTER TABLE users ADD COLUMN age INTEGER NOT NULL",
+                "-- the column isn't part of the model, dropping it deletes its data\n\
+                 ALTER TABLE users DROP COLUMN name",
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_type_aliases() {
+        let columns = vec![column("id", "SERIAL", false), column("name", "TEXT", false)];
+        let existing = "CREATE TABLE users (id int4 PRIMARY KEY, name varchar(128) NOT NULL)";
+        assert!(migrate(Dialect::Postgres, columns, existing).is_empty());
+    }
+
+    #[test]
+    fn alters_postgres_columns() {
+        let columns = vec![column("id", "BIGINT", false), column("name", "TEXT", false)];
+        let existing = "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT DEFAULT 'x')";
+        assert_eq!(
+            migrate(Dialect::Postgres, columns, existing),
+            [
+                "ALTER TABLE users ALTER COLUMN id TYPE BIGINT",
+                "ALTER TABLE users ALTER COLUMN name SET NOT NULL",
+            ]
+        );
+    }
+
+    #[test]
+    fn mysql_keeps_attributes() {
+        let columns = vec![column("name", "VARCHAR(255)", false)];
+        let existing = "CREATE TABLE `users` (\n  `name` varchar(128) COLLATE utf8mb4_bin \
+                        DEFAULT 'a b' COMMENT 'the name'\n) ENGINE=InnoDB";
+        assert_eq!(
+            migrate(Dialect::Mysql, columns, existing),
+            [
+                "ALTER TABLE users MODIFY COLUMN name varchar(128) COLLATE utf8mb4_bin NOT NULL \
+              DEFAULT 'a b' COMMENT 'the name'"
+            ]
+        );
+    }
+
+    #[test]
+    fn mysql_retypes_without_charset() {
+        let columns = vec![column("age", "INT", true)];
+        let existing = "CREATE TABLE `users` (\n  `age` varchar(10) CHARACTER SET utf8mb4 \
+                        NOT NULL DEFAULT '0' AUTO_INCREMENT\n)";
+        assert_eq!(
+            migrate(Dialect::Mysql, columns, existing),
+            ["ALTER TABLE users MODIFY COLUMN age INT NULL DEFAULT '0' AUTO_INCREMENT"]
+        );
+    }
+
+    #[test]
+    fn mysql_drops_null_default() {
+        let columns = vec![column("login", "DATETIME", false)];
+        let existing = "CREATE TABLE `users` (`login` datetime DEFAULT NULL COMMENT 'last login')";
+        assert_eq!(
+            migrate(Dialect::Mysql, columns, existing),
+            ["ALTER TABLE users MODIFY COLUMN login datetime NOT NULL COMMENT 'last login'"]
+        );
+    }
+
+    #[test]
+    fn mysql_refuses_unknown_definitions() {
+        let model = Model {
+            table: "users".to_owned(),
+            columns: vec![column("name", "TEXT", false)],
+        };
+        let mut table = dump::read_tables("CREATE TABLE users (name TEXT)").unwrap();
+        table[0].columns[0].definition = None;
+        let statements = migrate_table(Dialect::Mysql, &model, table.first());
+        assert_eq!(statements.len(), 1);
+        assert!(statements[0].starts_with("-- the definition of the column name of users"));
+    }
+
+    #[test]
+    fn splits_words() {
+        assert_eq!(
+            split_words(" DEFAULT (now() + 1)  COMMENT 'a (b' x"),
+            ["DEFAULT", "(now() + 1)", "COMMENT", "'a (b'", "x"]
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Reads tables from the `CREATE TABLE` statements of a SQL schema dump, as written by `pg_dump
+//! --schema-only` or `mysqldump --no-data`.
+
+use anyhow::{bail, Result};
+
+use super::{Column, RustType, Table};
+use crate::sql::strip_constraints;
+
+/// Keywords starting a table constraint instead of a column definition.
+const TABLE_CONSTRAINTS: &[&str] = &[
+    "PRIMARY",
+    "CONSTRAINT",
+    "UNIQUE",
+    "FOREIGN",
+    "CHECK",
+    "KEY",
+    "INDEX",
+    "EXCLUDE",
+    "FULLTEXT",
+    "SPATIAL",
+];
+
+/// Reads all tables created in the dump.
+pub fn read_tables(sql: &str) -> Result<Vec<Table>> {
+    let sql = strip_comments(sql);
+    let upper = sql.to_uppercase();
+
+    let mut tables = vec![];
+    let mut rest = 0;
+    while let Some(start) = upper[rest..].find("CREATE TABLE") {
+        let start = rest + start + "CREATE TABLE".len();
+        let open = match sql[start..].find('(') {
+            Some(open) => start + open,
+            None => bail!("expected '(' after CREATE TABLE"),
+        };
+        let close = match closing_paren(&sql[open..]) {
+            Some(close) => open + close,
+            None => bail!("unclosed '(' in CREATE TABLE"),
+        };
+        rest = close;
+
+        let mut name = sql[start..open].trim();
+        if name.to_uppercase().starts_with("IF NOT EXISTS") {
+            name = name["IF NOT EXISTS".len()..].trim();
+        }
+        tables.push(read_table(name, &sql[open + 1..close]));
+    }
+    Ok(tables)
+}
+
+fn read_table(name: &str, body: &str) -> Table {
+    let mut columns = vec![];
+    let mut primary_key = None;
+    let mut unique = vec![];
+
+    for definition in split_top_level(body) {
+        let upper = definition.to_uppercase();
+        let first = upper.split_whitespace().next().unwrap_or_default();
+        if TABLE_CONSTRAINTS.contains(&first) {
+            // constraints on a single column, like `PRIMARY KEY (id)`
+            let (columns, is_primary) = match constraint_columns(definition) {
+                Some(columns) => (columns, upper.contains("PRIMARY KEY")),
+                None => continue,
+            };
+            if let [column] = &*columns {
+                if is_primary {
+                    primary_key = Some(column.clone());
+                } else if upper.contains("UNIQUE") {
+                    unique.push(column.clone());
+                }
+            }
+            continue;
+        }
+
+        let (column, rest) = split_ident(definition);
+        let is_primary = upper.contains("PRIMARY KEY");
+        if is_primary {
+            primary_key = Some(column.clone());
+        } else if upper.contains("UNIQUE") {
+            unique.push(column.clone());
+        }
+        let sql_type = strip_constraints(rest).to_owned();
+        columns.push(Column {
+            name: column,
+            ty: RustType::Unknown(sql_type.clone()),
+            sql_type,
+            nullable: !upper.contains("NOT NULL") && !is_primary,
+            has_default: upper.contains("DEFAULT")
+                || upper.contains("AUTO_INCREMENT")
+                || upper.contains("SERIAL")
+                || upper.contains("GENERATED"),
+            definition: Some(rest.trim().to_owned()),
+        });
+    }
+
+    // columns of the primary key are implicitly NOT NULL
+    if let Some(pk) = &primary_key {
+        for column in columns.iter_mut().filter(|column| &column.name == pk) {
+            column.nullable = false;
+        }
+    }
+
+    Table {
+        name: name.to_owned(),
+        columns,
+        primary_key,
+        unique,
+    }
+}
+
+/// Removes `-- line` and `/* block */` comments.
+fn strip_comments(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut rest = sql;
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("--") {
+            rest = comment.find('\n').map_or("", |end| &comment[end..]);
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            rest = comment.find("*/").map_or("", |end| &comment[end + 2..]);
+        } else {
+            let c = rest.chars().next().unwrap();
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    out
+}
+
+/// Returns the index of the parenthesis closing the one at the start of `sql`.
+fn closing_paren(sql: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    for (idx, c) in sql.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '\'') | (None, '"') | (None, '`') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(idx);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Splits the body of a `CREATE TABLE` statement at commas which are not nested in parentheses.
+fn split_top_level(body: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (idx, c) in body.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '\'') | (None, '"') | (None, '`') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(body[start..idx].trim());
+                start = idx + 1;
+            }
+            _ => (),
+        }
+    }
+    parts.push(body[start..].trim());
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+/// Splits the leading, possibly quoted, identifier from a column definition.
+fn split_ident(definition: &str) -> (String, &str) {
+    let definition = definition.trim();
+    for quote in ['"', '`'] {
+        if let Some(quoted) = definition.strip_prefix(quote) {
+            if let Some(end) = quoted.find(quote) {
+                return (quoted[..end].to_owned(), &quoted[end + 1..]);
+            }
+        }
+    }
+    match definition.find(char::is_whitespace) {
+        Some(end) => (definition[..end].to_owned(), &definition[end..]),
+        None => (definition.to_owned(), ""),
+    }
+}
+
+/// Returns the columns of a table constraint like `PRIMARY KEY (a, b)`.
+fn constraint_columns(definition: &str) -> Option<Vec<String>> {
+    let open = definition.find('(')?;
+    let close = open + closing_paren(&definition[open..])?;
+    let columns = split_top_level(&definition[open + 1..close]);
+    Some(columns.into_iter().map(|c| split_ident(c).0).collect())
+}
@@ -0,0 +1,113 @@
+use anyhow::{anyhow, bail, Result};
+use clap::ValueEnum;
+
+pub mod dump;
+mod mysql;
+mod postgres;
+mod sqlite;
+
+/// A table read from the database.
+#[derive(Debug)]
+pub struct Table {
+    /// Name of the table, as it has to be written in a query.
+    pub name: String,
+    pub columns: Vec<Column>,
+    /// The primary key, if it consists of a single column.
+    pub primary_key: Option<String>,
+    /// Columns which are unique on their own.
+    pub unique: Vec<String>,
+}
+
+/// A column read from the database.
+#[derive(Debug)]
+pub struct Column {
+    pub name: String,
+    /// The SQL type of this column, e.g. `character varying(128)`.
+    pub sql_type: String,
+    /// The rust type of this column.
+    pub ty: RustType,
+    pub nullable: bool,
+    /// Whether the column has a default value or is generated by the database.
+    pub has_default: bool,
+    /// The definition of the column following its name, e.g. `varchar(128) NOT NULL DEFAULT ''`.
+    /// This is only read for mysql, where `MODIFY COLUMN` has to repeat it.
+    pub definition: Option<String>,
+}
+
+/// The rust type a column is decoded into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RustType {
+    /// A type supported by sqlx out of the box, e.g. `i32` or `chrono::NaiveDateTime`.
+    Builtin(String),
+    /// A user-defined type, like an enum, which has to implement `sqlx::Type`.
+    Custom(String),
+    /// A type without a known mapping, given as the SQL type.
+    Unknown(String),
+}
+
+/// The SQL dialect of a database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Dialect {
+    Postgres,
+    Mysql,
+    Sqlite,
+}
+
+impl Dialect {
+    /// Determines the dialect from the scheme of a database url.
+    pub fn from_url(url: &str) -> Result<Self> {
+        let scheme = url.split(':').next().unwrap_or_default();
+        match scheme {
+            "postgres" | "postgresql" => Ok(Dialect::Postgres),
+            "mysql" | "mariadb" => Ok(Dialect::Mysql),
+            "sqlite" => Ok(Dialect::Sqlite),
+            _ => bail!("unsupported database url \"{}\"", url),
+        }
+    }
+
+    /// Quotes an identifier if it would otherwise be changed or rejected by the database.
+    pub fn quote(self, ident: &str) -> String {
+        let plain = ident
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        match (self, plain) {
+            (_, true) => ident.to_owned(),
+            (Dialect::Mysql, false) => format!("`{}`", ident),
+            (_, false) => format!("\"{}\"", ident),
+        }
+    }
+}
+
+/// Reads the given table from the database at `url`.
+pub async fn read_table(url: &str, name: &str) -> Result<Table> {
+    find_table(url, name)
+        .await?
+        .ok_or_else(|| anyhow!("table \"{}\" does not exist", name))
+}
+
+/// Reads the given table from the database at `url`, returning `None` if it does not exist.
+pub async fn find_table(url: &str, name: &str) -> Result<Option<Table>> {
+    let table = match Dialect::from_url(url)? {
+        Dialect::Postgres => postgres::read_table(url, name).await?,
+        Dialect::Mysql => mysql::read_table(url, name).await?,
+        Dialect::Sqlite => sqlite::read_table(url, name).await?,
+    };
+    Ok(Some(table).filter(|table| !table.columns.is_empty()))
+}
+
+/// Splits `schema.table` into its parts, removing identifier quotes.
+pub fn split_name(name: &str) -> (Option<&str>, &str) {
+    fn unquote(ident: &str) -> &str {
+        ident.trim_matches(|c| c == '"' || c == '`')
+    }
+    match name.rsplit_once('.') {
+        Some((schema, table)) => (Some(unquote(schema)), unquote(table)),
+        None => (None, unquote(name)),
+    }
+}
+
+impl RustType {
+    fn builtin(ty: &str) -> Self {
+        RustType::Builtin(ty.to_owned())
+    }
+}
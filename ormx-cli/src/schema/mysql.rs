@@ -0,0 +1,115 @@
+use anyhow::Result;
+use sqlx::{Connection, MySqlConnection};
+
+use super::{dump, split_name, Column, RustType, Table};
+
+pub async fn read_table(url: &str, table: &str) -> Result<Table> {
+    let mut conn = MySqlConnection::connect(url).await?;
+    let (schema, name) = split_name(table);
+
+    let rows: Vec<(String, String, String, String, Option<String>, String)> = sqlx::query_as(
+        "SELECT CAST(COLUMN_NAME AS CHAR), CAST(DATA_TYPE AS CHAR), CAST(COLUMN_TYPE AS CHAR), \
+         CAST(IS_NULLABLE AS CHAR), CAST(COLUMN_DEFAULT AS CHAR), CAST(EXTRA AS CHAR) \
+         FROM information_schema.COLUMNS \
+         WHERE TABLE_SCHEMA = COALESCE(?, DATABASE()) AND TABLE_NAME = ? \
+         ORDER BY ORDINAL_POSITION",
+    )
+    .bind(schema)
+    .bind(name)
+    .fetch_all(&mut conn)
+    .await?;
+
+    let mut columns = rows
+        .into_iter()
+        .map(|(name, data_type, column_type, nullable, default, extra)| {
+            let extra = extra.to_lowercase();
+            Column {
+                name,
+                ty: rust_type(&data_type, &column_type),
+                sql_type: column_type,
+                nullable: nullable == "YES",
+                has_default: default.is_some()
+                    || extra.contains("auto_increment")
+                    || extra.contains("generated"),
+                definition: None,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // the definitions including the default value, comment, charset and collation of each column
+    let quoted = match schema {
+        Some(schema) => format!("`{}`.`{}`", schema, name),
+        None => format!("`{}`", name),
+    };
+    let (_, create_table): (String, String) =
+        sqlx::query_as(&format!("SHOW CREATE TABLE {}", quoted))
+            .fetch_one(&mut conn)
+            .await?;
+    let definitions = dump::read_tables(&create_table)?
+        .into_iter()
+        .flat_map(|table| table.columns);
+    for definition in definitions {
+        if let Some(column) = columns.iter_mut().find(|c| c.name == definition.name) {
+            column.definition = definition.definition;
+        }
+    }
+
+    let indices: Vec<(String, String)> = sqlx::query_as(
+        "SELECT CAST(MIN(COLUMN_NAME) AS CHAR), CAST(INDEX_NAME AS CHAR) \
+         FROM information_schema.STATISTICS \
+         WHERE TABLE_SCHEMA = COALESCE(?, DATABASE()) AND TABLE_NAME = ? AND NON_UNIQUE = 0 \
+         GROUP BY INDEX_NAME HAVING COUNT(*) = 1",
+    )
+    .bind(schema)
+    .bind(name)
+    .fetch_all(&mut conn)
+    .await?;
+
+    let mut primary_key = None;
+    let mut unique = vec![];
+    for (column, index) in indices {
+        if index == "PRIMARY" {
+            primary_key = Some(column);
+        } else {
+            unique.push(column);
+        }
+    }
+
+    Ok(Table {
+        name: table.to_owned(),
+        columns,
+        primary_key,
+        unique,
+    })
+}
+
+fn rust_type(data_type: &str, column_type: &str) -> RustType {
+    let column_type = column_type.to_lowercase();
+    let unsigned = column_type.contains("unsigned");
+    let ty = match data_type.to_lowercase().as_str() {
+        "tinyint" if column_type.starts_with("tinyint(1)") => "bool",
+        "bool" | "boolean" => "bool",
+        "tinyint" if unsigned => "u8",
+        "tinyint" => "i8",
+        "smallint" if unsigned => "u16",
+        "smallint" => "i16",
+        "int" | "integer" | "mediumint" if unsigned => "u32",
+        "int" | "integer" | "mediumint" => "i32",
+        "bigint" if unsigned => "u64",
+        "bigint" => "i64",
+        "float" => "f32",
+        "double" | "real" => "f64",
+        "char" | "varchar" | "tinytext" | "text" | "mediumtext" | "longtext" | "enum" | "set" => {
+            "String"
+        }
+        "binary" | "varbinary" | "tinyblob" | "blob" | "mediumblob" | "longblob" => "Vec<u8>",
+        "datetime" => "chrono::NaiveDateTime",
+        "timestamp" => "chrono::DateTime<chrono::Utc>",
+        "date" => "chrono::NaiveDate",
+        "time" => "chrono::NaiveTime",
+        "json" => "serde_json::Value",
+        "decimal" | "numeric" => "rust_decimal::Decimal",
+        _ => return RustType::Unknown(column_type),
+    };
+    RustType::builtin(ty)
+}
@@ -0,0 +1,118 @@
+use anyhow::Result;
+use heck::ToUpperCamelCase;
+use sqlx::{Connection, PgConnection};
+
+use super::{split_name, Column, Dialect, RustType, Table};
+
+type ColumnRow = (
+    String,
+    String,
+    String,
+    String,
+    String,
+    Option<String>,
+    String,
+);
+
+pub async fn read_table(url: &str, table: &str) -> Result<Table> {
+    let mut conn = PgConnection::connect(url).await?;
+    let (schema, name) = split_name(table);
+
+    let rows: Vec<ColumnRow> = sqlx::query_as(
+        "SELECT c.column_name::text, c.data_type::text, c.udt_name::text, \
+         format_type(a.atttypid, a.atttypmod), c.is_nullable::text, c.column_default::text, \
+         c.is_identity::text \
+         FROM information_schema.columns c \
+         JOIN pg_attribute a ON a.attname = c.column_name \
+         AND a.attrelid = format('%I.%I', c.table_schema, c.table_name)::regclass \
+         WHERE c.table_schema = COALESCE($1, current_schema()) AND c.table_name = $2 \
+         ORDER BY c.ordinal_position",
+    )
+    .bind(schema)
+    .bind(name)
+    .fetch_all(&mut conn)
+    .await?;
+
+    let columns = rows
+        .into_iter()
+        .map(
+            |(name, data_type, udt_name, sql_type, nullable, default, identity)| Column {
+                name,
+                sql_type,
+                ty: rust_type(&data_type, &udt_name),
+                nullable: nullable == "YES",
+                has_default: default.is_some() || identity == "YES",
+                definition: None,
+            },
+        )
+        .collect::<Vec<_>>();
+
+    let mut primary_key = None;
+    let mut unique = vec![];
+    if !columns.is_empty() {
+        let indices: Vec<(String, bool)> = sqlx::query_as(
+            "SELECT a.attname::text, i.indisprimary \
+             FROM pg_index i \
+             JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = i.indkey[0] \
+             WHERE i.indrelid = format('%I.%I', COALESCE($1, current_schema()), $2)::regclass \
+             AND i.indisunique AND i.indnkeyatts = 1 AND i.indpred IS NULL",
+        )
+        .bind(schema)
+        .bind(name)
+        .fetch_all(&mut conn)
+        .await?;
+
+        for (column, is_primary) in indices {
+            if is_primary {
+                primary_key = Some(column);
+            } else {
+                unique.push(column);
+            }
+        }
+    }
+
+    let quote = |ident| Dialect::Postgres.quote(ident);
+    let table = match schema {
+        Some(schema) => format!("{}.{}", quote(schema), quote(name)),
+        None => quote(name),
+    };
+    Ok(Table {
+        name: table,
+        columns,
+        primary_key,
+        unique,
+    })
+}
+
+fn rust_type(data_type: &str, udt_name: &str) -> RustType {
+    match data_type {
+        "USER-DEFINED" => RustType::Custom(udt_name.to_upper_camel_case()),
+        "ARRAY" => match rust_type("", udt_name.trim_start_matches('_')) {
+            RustType::Builtin(ty) => RustType::Builtin(format!("Vec<{}>", ty)),
+            _ => RustType::Unknown(format!("{}[]", udt_name.trim_start_matches('_'))),
+        },
+        _ => match udt_name {
+            "bool" => RustType::builtin("bool"),
+            "char" => RustType::builtin("i8"),
+            "int2" => RustType::builtin("i16"),
+            "int4" => RustType::builtin("i32"),
+            "int8" => RustType::builtin("i64"),
+            "float4" => RustType::builtin("f32"),
+            "float8" => RustType::builtin("f64"),
+            "oid" => RustType::builtin("sqlx::postgres::types::Oid"),
+            "text" | "varchar" | "bpchar" | "name" | "citext" => RustType::builtin("String"),
+            "bytea" => RustType::builtin("Vec<u8>"),
+            "timestamp" => RustType::builtin("chrono::NaiveDateTime"),
+            "timestamptz" => RustType::builtin("chrono::DateTime<chrono::Utc>"),
+            "date" => RustType::builtin("chrono::NaiveDate"),
+            "time" => RustType::builtin("chrono::NaiveTime"),
+            "interval" => RustType::builtin("sqlx::postgres::types::PgInterval"),
+            "uuid" => RustType::builtin("uuid::Uuid"),
+            "json" | "jsonb" => RustType::builtin("serde_json::Value"),
+            "numeric" => RustType::builtin("rust_decimal::Decimal"),
+            "inet" | "cidr" => RustType::builtin("ipnetwork::IpNetwork"),
+            "macaddr" => RustType::builtin("mac_address::MacAddress"),
+            other => RustType::Unknown(other.to_owned()),
+        },
+    }
+}
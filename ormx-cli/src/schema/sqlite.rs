@@ -0,0 +1,81 @@
+use anyhow::Result;
+use sqlx::{Connection, SqliteConnection};
+
+use super::{Column, RustType, Table};
+
+pub async fn read_table(url: &str, table: &str) -> Result<Table> {
+    let mut conn = SqliteConnection::connect(url).await?;
+
+    let rows: Vec<(String, String, bool, Option<String>, i64)> = sqlx::query_as(
+        "SELECT name, type, \"notnull\", dflt_value, pk FROM pragma_table_info(?) ORDER BY cid",
+    )
+    .bind(table)
+    .fetch_all(&mut conn)
+    .await?;
+
+    let pk = rows.iter().filter(|(.., pk)| *pk > 0).collect::<Vec<_>>();
+    let primary_key = match &*pk {
+        [(name, ..)] => Some(name.clone()),
+        _ => None,
+    };
+
+    let columns = rows
+        .iter()
+        .map(|(name, ty, not_null, default, pk)| {
+            // an `INTEGER PRIMARY KEY` is an alias for the rowid, which is generated on insert
+            let is_rowid = primary_key.as_ref() == Some(name) && ty.eq_ignore_ascii_case("integer");
+            Column {
+                name: name.clone(),
+                sql_type: ty.clone(),
+                ty: rust_type(ty),
+                nullable: !*not_null && *pk == 0,
+                has_default: default.is_some() || is_rowid,
+                definition: None,
+            }
+        })
+        .collect();
+
+    let indices: Vec<(String,)> = sqlx::query_as(
+        "SELECT name FROM pragma_index_list(?) WHERE \"unique\" AND origin <> 'pk' AND NOT partial",
+    )
+    .bind(table)
+    .fetch_all(&mut conn)
+    .await?;
+
+    let mut unique = vec![];
+    for (index,) in indices {
+        let columns: Vec<(Option<String>,)> =
+            sqlx::query_as("SELECT name FROM pragma_index_info(?)")
+                .bind(&index)
+                .fetch_all(&mut conn)
+                .await?;
+        if let [(Some(column),)] = &*columns {
+            unique.push(column.clone());
+        }
+    }
+
+    Ok(Table {
+        name: table.to_owned(),
+        columns,
+        primary_key,
+        unique,
+    })
+}
+
+/// Maps a declared type to a rust type, following the rules sqlite uses to determine the type
+/// affinity of a column.
+fn rust_type(ty: &str) -> RustType {
+    let upper = ty.to_uppercase();
+    let ty = match upper.as_str() {
+        "BOOLEAN" | "BOOL" => "bool",
+        "DATETIME" | "TIMESTAMP" => "chrono::NaiveDateTime",
+        "DATE" => "chrono::NaiveDate",
+        "TIME" => "chrono::NaiveTime",
+        _ if upper.contains("INT") => "i64",
+        _ if upper.contains("CHAR") || upper.contains("CLOB") || upper.contains("TEXT") => "String",
+        _ if upper.is_empty() || upper.contains("BLOB") => "Vec<u8>",
+        _ if upper.contains("REAL") || upper.contains("FLOA") || upper.contains("DOUB") => "f64",
+        _ => return RustType::Unknown(ty.to_owned()),
+    };
+    RustType::builtin(ty)
+}
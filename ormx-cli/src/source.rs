@@ -0,0 +1,440 @@
+//! Reads the tables defined using `#[derive(ormx::Table)]` from the source code of a crate.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use proc_macro2::{TokenStream, TokenTree};
+use syn::{Attribute, Fields, GenericArgument, Item, ItemStruct, LitStr, PathArguments, Type};
+
+use crate::{schema::Dialect, sql};
+
+/// A table defined by a struct deriving `ormx::Table`.
+#[derive(Debug)]
+pub struct Model {
+    /// Name of the table, as given by `#[ormx(table = "..")]`.
+    pub table: String,
+    pub columns: Vec<ModelColumn>,
+}
+
+/// A column of a [Model].
+#[derive(Debug)]
+pub struct ModelColumn {
+    pub name: String,
+    /// The SQL type, either given by `#[ormx(sql_type = "..")]` or inferred from the rust type.
+    /// If there is no known mapping, this is the name of the rust type.
+    pub sql_type: String,
+    /// Whether `sql_type` is known, or just the name of the rust type.
+    pub known_type: bool,
+    pub nullable: bool,
+    pub id: bool,
+    /// Whether this is an ID generated by the database, using a serial type.
+    pub generated: bool,
+    pub unique: bool,
+}
+
+/// A struct of the crate, and the path of the module it is defined in.
+struct SourceStruct {
+    module: Vec<String>,
+    item: ItemStruct,
+}
+
+/// A single argument of an `#[ormx(..)]` attribute, like `table = "users"` or `default`.
+struct Arg {
+    key: String,
+    value: TokenStream,
+}
+
+/// Reads all tables defined in the `.rs` files in `dir`.
+pub fn read_models(dir: &Path, dialect: Dialect) -> Result<Vec<Model>> {
+    let mut files = vec![];
+    collect_files(dir, &mut files)?;
+
+    let mut structs = vec![];
+    for file in files {
+        let content = fs::read_to_string(&file)?;
+        let parsed = syn::parse_file(&content)
+            .with_context(|| format!("failed to parse {}", file.display()))?;
+        let module = module_path(file.strip_prefix(dir).unwrap_or(&file));
+        collect_structs(parsed.items, module, &mut structs);
+    }
+    read_structs(&structs, dialect)
+}
+
+fn read_structs(structs: &[SourceStruct], dialect: Dialect) -> Result<Vec<Model>> {
+    let tables = structs.iter().filter(|s| derives(&s.item, "Table"));
+    tables
+        .map(|table| read_model(table, structs, dialect))
+        .filter_map(Result::transpose)
+        .collect()
+}
+
+/// Returns the path of the module defined by a file, given relative to the source directory,
+/// e.g. `["geo", "point"]` for `geo/point.rs`, and `[]` for `lib.rs` or `main.rs`.
+fn module_path(file: &Path) -> Vec<String> {
+    let mut module = file
+        .with_extension("")
+        .iter()
+        .map(|component| component.to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    let root = module.len() == 1 && matches!(&*module[0], "lib" | "main");
+    if root || module.last().is_some_and(|last| last == "mod") {
+        module.pop();
+    }
+    module
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|name| name.to_str());
+        if path.is_dir() && !matches!(name, Some("target") | Some(".git")) {
+            collect_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "rs") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn collect_structs(items: Vec<Item>, module: Vec<String>, structs: &mut Vec<SourceStruct>) {
+    for item in items {
+        match item {
+            Item::Struct(item) => structs.push(SourceStruct {
+                module: module.clone(),
+                item,
+            }),
+            Item::Mod(item) => {
+                if let Some((_, items)) = item.content {
+                    let mut module = module.clone();
+                    module.push(item.ident.to_string());
+                    collect_structs(items, module, structs);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Reads a struct deriving `Table`. Returns `None` if it doesn't have an `#[ormx(table = "..")]`
+/// attribute, in which case it's not a table of ormx.
+fn read_model(
+    source: &SourceStruct,
+    structs: &[SourceStruct],
+    dialect: Dialect,
+) -> Result<Option<Model>> {
+    let item = &source.item;
+    let args = ormx_args(&item.attrs);
+    let table = match find_arg(&args, "table") {
+        Some(table) => string_value(table)?,
+        None => return Ok(None),
+    };
+    let id = match find_arg(&args, "id") {
+        Some(id) => id.value.to_string(),
+        None => bail!("{} is missing #[ormx(id = ..)]", item.ident),
+    };
+
+    let mut columns = vec![];
+    read_columns(source, "", Some(&id), structs, dialect, &mut columns)?;
+
+    Ok(Some(Model { table, columns }))
+}
+
+/// Reads the columns of a struct, prefixing their names with `prefix`. Columns of embedded
+/// structs are read recursively.
+fn read_columns(
+    source: &SourceStruct,
+    prefix: &str,
+    id: Option<&str>,
+    structs: &[SourceStruct],
+    dialect: Dialect,
+    columns: &mut Vec<ModelColumn>,
+) -> Result<()> {
+    let item = &source.item;
+    let fields = match &item.fields {
+        Fields::Named(fields) => &fields.named,
+        _ => bail!("{} must have named fields", item.ident),
+    };
+
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap().to_string();
+        let (name, arg) = type_name(&field.ty).unwrap_or_default();
+        if name == "PhantomData" {
+            continue;
+        }
+        let args = ormx_args(&field.attrs);
+
+        if let Some(flatten) = find_arg(&args, "flatten") {
+            let prefix = match flatten.value.is_empty() {
+                true => format!("{}{}_", prefix, ident),
+                false => format!("{}{}", prefix, string_value(flatten)?),
+            };
+            let embed = find_embed(source, &field.ty, structs)?;
+            read_columns(embed, &prefix, None, structs, dialect, columns)?;
+            continue;
+        }
+
+        let column = match find_arg(&args, "column") {
+            Some(column) => string_value(column)?,
+            None => ident.clone(),
+        };
+        let (nullable, (name, arg)) = match (&*name, arg) {
+            ("Option", Some(inner)) => (true, type_name(inner).unwrap_or_default()),
+            _ => (false, (name, arg)),
+        };
+        let arg = arg.and_then(type_name).map(|(arg, _)| arg);
+
+        let is_id = id == Some(&*ident);
+        let default = find_arg(&args, "default").is_some();
+        let (sql_type, inferred) = if let Some(sql_type) = find_arg(&args, "sql_type") {
+            (Some(string_value(sql_type)?), false)
+        } else if find_arg(&args, "json").is_some() {
+            (Some(sql::json_type(dialect).to_owned()), true)
+        } else {
+            (sql::sql_type(dialect, &name, arg.as_deref()), true)
+        };
+
+        columns.push(ModelColumn {
+            name: format!("{}{}", prefix, column),
+            known_type: sql_type.is_some(),
+            generated: is_id && default && inferred && sql_type.is_some(),
+            sql_type: sql_type.unwrap_or(name),
+            nullable,
+            id: is_id,
+            unique: find_arg(&args, "get_one").is_some()
+                || find_arg(&args, "get_optional").is_some(),
+        });
+    }
+    Ok(())
+}
+
+/// Finds the struct deriving `Columns` embedded by a field of type `ty` of `source`.
+/// Imports aren't resolved, so if there are multiple structs of that name, the one is chosen
+/// whose module ends with the path of the type, or, for a bare name, the one defined next to
+/// `source`. Otherwise, the embedded struct is ambiguous.
+fn find_embed<'a>(
+    source: &SourceStruct,
+    ty: &Type,
+    structs: &'a [SourceStruct],
+) -> Result<&'a SourceStruct> {
+    let path = match ty {
+        Type::Path(ty) if ty.qself.is_none() => &ty.path,
+        _ => bail!(
+            "the type of an embedded field of {} must be a path",
+            source.item.ident
+        ),
+    };
+    let mut segments = path
+        .segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect::<Vec<_>>();
+    let name = segments.pop().unwrap_or_default();
+    segments.retain(|segment| !matches!(&**segment, "crate" | "self" | "super"));
+
+    let candidates = structs
+        .iter()
+        .filter(|s| s.item.ident == name && derives(&s.item, "Columns"))
+        .collect::<Vec<_>>();
+    let matching = match segments.is_empty() {
+        true => candidates
+            .iter()
+            .filter(|s| s.module == source.module)
+            .copied()
+            .collect::<Vec<_>>(),
+        false => candidates
+            .iter()
+            .filter(|s| s.module.ends_with(&segments))
+            .copied()
+            .collect::<Vec<_>>(),
+    };
+    match (&*candidates, &*matching) {
+        ([], _) => bail!(
+            "embedded struct {} of {} not found",
+            name,
+            source.item.ident
+        ),
+        ([embed], _) | (_, [embed]) => Ok(embed),
+        _ => {
+            let modules = candidates
+                .iter()
+                .map(|s| match s.module.is_empty() {
+                    true => "crate".to_owned(),
+                    false => format!("crate::{}", s.module.join("::")),
+                })
+                .collect::<Vec<_>>();
+            bail!(
+                "embedded struct {} of {} is ambiguous, it is defined in {}; use its full path",
+                name,
+                source.item.ident,
+                modules.join(", ")
+            )
+        }
+    }
+}
+
+/// Whether the struct has a derive ending in `name`, e.g. `#[derive(ormx::Table)]`.
+fn derives(item: &ItemStruct, name: &str) -> bool {
+    item.attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("derive"))
+        .flat_map(|attr| group_stream(&attr.tokens))
+        .any(|token| matches!(token, TokenTree::Ident(ident) if ident == name))
+}
+
+/// Splits the arguments of all `#[ormx(..)]` attributes.
+fn ormx_args(attrs: &[Attribute]) -> Vec<Arg> {
+    let mut args = vec![];
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("ormx")) {
+        let mut tokens = group_stream(&attr.tokens).into_iter();
+        while let Some(key) = tokens.next() {
+            let mut value = TokenStream::new();
+            for token in tokens.by_ref() {
+                match token {
+                    TokenTree::Punct(p) if p.as_char() == ',' => break,
+                    TokenTree::Punct(p) if p.as_char() == '=' && value.is_empty() => (),
+                    other => value.extend(Some(other)),
+                }
+            }
+            args.push(Arg {
+                key: key.to_string(),
+                value,
+            });
+        }
+    }
+    args
+}
+
+fn find_arg<'a>(args: &'a [Arg], key: &str) -> Option<&'a Arg> {
+    args.iter().find(|arg| arg.key == key)
+}
+
+fn string_value(arg: &Arg) -> Result<String> {
+    let lit: LitStr = syn::parse2(arg.value.clone())
+        .with_context(|| format!("expected a string literal for #[ormx({} = ..)]", arg.key))?;
+    Ok(lit.value())
+}
+
+/// Returns the content of the outer group of an attribute, e.g. `a, b` for `(a, b)`.
+fn group_stream(tokens: &TokenStream) -> TokenStream {
+    match tokens.clone().into_iter().next() {
+        Some(TokenTree::Group(group)) => group.stream(),
+        _ => TokenStream::new(),
+    }
+}
+
+/// Returns the name of the last segment of a path type and its generic argument, if there is
+/// exactly one.
+fn type_name(ty: &Type) -> Option<(String, Option<&Type>)> {
+    let path = match ty {
+        Type::Path(ty) if ty.qself.is_none() => &ty.path,
+        _ => return None,
+    };
+    let last = path.segments.last()?;
+    let arg = match &last.arguments {
+        PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    };
+    Some((last.ident.to_string(), arg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(module: &[&str], code: &str, structs: &mut Vec<SourceStruct>) {
+        let module = module.iter().map(|&segment| segment.to_owned()).collect();
+        collect_structs(syn::parse_file(code).unwrap().items, module, structs);
+    }
+
+    /// the structs of a crate with an `Address` in `geo` and one in `shop::billing`
+    fn crate_with(table: &str) -> Vec<SourceStruct> {
+        let mut structs = vec![];
+        parse(
+            &["geo"],
+            "#[derive(ormx::Columns)] struct Address { city: String }",
+            &mut structs,
+        );
+        parse(
+            &["shop"],
+            &format!(
+                "mod billing {{ #[derive(ormx::Columns)] struct Address {{ iban: String }} }} {}",
+                table
+            ),
+            &mut structs,
+        );
+        structs
+    }
+
+    fn column_names(structs: &[SourceStruct]) -> Result<Vec<String>> {
+        let models = read_structs(structs, Dialect::Postgres)?;
+        Ok(models[0].columns.iter().map(|c| c.name.clone()).collect())
+    }
+
+    const TABLE: &str = r#"
+        #[derive(ormx::Table)]
+        #[ormx(table = "users", id = id)]
+        struct User { id: i32, #[ormx(flatten)] address: ADDRESS }
+    "#;
+
+    #[test]
+    fn module_paths_of_files() {
+        assert!(module_path(Path::new("lib.rs")).is_empty());
+        assert!(module_path(Path::new("main.rs")).is_empty());
+        assert_eq!(module_path(Path::new("geo.rs")), ["geo"]);
+        assert_eq!(module_path(Path::new("geo/mod.rs")), ["geo"]);
+        assert_eq!(module_path(Path::new("geo/main.rs")), ["geo", "main"]);
+        assert_eq!(module_path(Path::new("geo/point.rs")), ["geo", "point"]);
+    }
+
+    #[test]
+    fn embeds_are_resolved_by_path() {
+        let structs = crate_with(&TABLE.replace("ADDRESS", "crate::geo::Address"));
+        assert_eq!(column_names(&structs).unwrap(), ["id", "address_city"]);
+        let structs = crate_with(&TABLE.replace("ADDRESS", "billing::Address"));
+        assert_eq!(column_names(&structs).unwrap(), ["id", "address_iban"]);
+    }
+
+    #[test]
+    fn bare_embeds_are_resolved_in_the_module_of_the_table() {
+        let table = format!("mod billing {{ {} }}", TABLE.replace("ADDRESS", "Address"));
+        let mut structs = crate_with("");
+        parse(&["shop"], &table, &mut structs);
+        assert_eq!(column_names(&structs).unwrap(), ["id", "address_iban"]);
+    }
+
+    #[test]
+    fn ambiguous_embeds_are_rejected() {
+        let structs = crate_with(&TABLE.replace("ADDRESS", "Address"));
+        let error = column_names(&structs).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "embedded struct Address of User is ambiguous, it is defined in crate::geo, \
+             crate::shop::billing; use its full path"
+        );
+    }
+
+    #[test]
+    fn unique_embeds_are_resolved_by_name() {
+        let mut structs = vec![];
+        parse(
+            &["geo"],
+            "#[derive(ormx::Columns)] struct Point { x: f64, y: f64 }",
+            &mut structs,
+        );
+        parse(
+            &[],
+            &TABLE.replace("address: ADDRESS", "location: Point"),
+            &mut structs,
+        );
+        assert_eq!(
+            column_names(&structs).unwrap(),
+            ["id", "location_x", "location_y"]
+        );
+    }
+}
@@ -0,0 +1,32 @@
+//! Mapping between rust and SQL types, shared with `ormx` and `ormx-macros` through `ormx-types`.
+
+pub use ormx_types::{strip_constraints, type_family};
+
+use crate::schema::Dialect;
+
+/// Returns the SQL type for a rust type, given by the name of its last path segment and its
+/// generic argument, e.g. `("Vec", Some("u8"))`.
+pub fn sql_type(dialect: Dialect, name: &str, arg: Option<&str>) -> Option<String> {
+    match dialect {
+        Dialect::Postgres => ormx_types::postgres::sql_type(name, arg),
+        Dialect::Mysql => ormx_types::mysql::sql_type(name, arg),
+        Dialect::Sqlite => None,
+    }
+}
+
+/// The type of columns annotated with `#[ormx(json)]`.
+pub fn json_type(dialect: Dialect) -> &'static str {
+    match dialect {
+        Dialect::Postgres => ormx_types::postgres::JSON_TYPE,
+        _ => ormx_types::mysql::JSON_TYPE,
+    }
+}
+
+/// The type of an ID generated by the database.
+pub fn serial_type(dialect: Dialect, sql_type: &str) -> String {
+    match dialect {
+        Dialect::Postgres => ormx_types::postgres::serial_type(sql_type),
+        Dialect::Mysql => ormx_types::mysql::serial_type(sql_type),
+        Dialect::Sqlite => sql_type.to_owned(),
+    }
+}
@@ -1,9 +1,11 @@
 use syn::{
     parse::{Parse, ParseStream},
     punctuated::Punctuated,
-    Attribute, Ident, Path, Result, Token, Type,
+    Attribute, Expr, Ident, Path, Result, Token, Type,
 };
 
+use crate::utils::{set_once, unknown_attr};
+
 pub enum TableAttr {
     // table = <string>
     Table(String),
@@ -12,7 +14,25 @@ pub enum TableAttr {
     // insertable [= [<attribute>]* <ident>]?
     Insertable(Option<Insertable>),
     // deletable
-    Deletable(())
+    Deletable(()),
+    // unchecked
+    Unchecked(()),
+    // hooks
+    Hooks(()),
+    // validate
+    Validate(()),
+    // audit = <string>
+    Audit(String),
+    // tracked
+    Tracked(()),
+    // cache(ttl = <string>, [capacity = <int>]?, [with = <path>]?)
+    Cache(CacheOptions),
+    // factory
+    Factory(()),
+    // tag [= <string>]?
+    Tag(Option<String>),
+    // memory
+    Memory(()),
 }
 
 pub struct Insertable {
@@ -25,6 +45,8 @@ pub enum TableFieldAttr {
     Column(String),
     // custom_type
     CustomType(()),
+    // json
+    Json(()),
     // default
     Default(()),
     // get_one [= <ident>]? [(<type>)]?
@@ -33,12 +55,26 @@ pub enum TableFieldAttr {
     GetOptional(Getter),
     // get_many [= <ident>]? [(<type>)]?
     GetMany(Getter),
+    // get_many_contains [= <ident>]? [(<type>)]?
+    GetManyContains(Getter),
     // set [= <ident>]?
     Set(Option<Ident>),
     // by_ref
     ByRef(()),
     // insert_attribute = <attribute>
-    InsertAttr(AnyAttribute)
+    InsertAttr(AnyAttribute),
+    // flatten [= <string>]?
+    Flatten(Option<String>),
+    // sql_type = <string>
+    SqlType(String),
+    // length([min = <expr>]?, [max = <expr>]?)
+    Length(Bounds),
+    // range([min = <expr>]?, [max = <expr>]?)
+    Range(Bounds),
+    // regex = <expr>
+    Regex(Expr),
+    // factory = <expr> | factory(parent = <path>)
+    Factory(FactoryValue),
 }
 
 #[derive(Clone)]
@@ -47,18 +83,71 @@ pub struct Getter {
     pub arg_ty: Option<Type>,
 }
 
+/// The bounds given to `length(..)` or `range(..)`, of which at least one is present.
+#[derive(Clone)]
+pub struct Bounds {
+    pub min: Option<Expr>,
+    pub max: Option<Expr>,
+}
+
+/// The options given to `cache(..)`.
+#[derive(Clone)]
+pub struct CacheOptions {
+    /// how long rows are cached, in milliseconds
+    pub ttl: u64,
+    /// maximum number of rows in each cache
+    pub capacity: Option<usize>,
+    /// implementation of `ormx::cache::Cache` to use instead of `ormx::cache::LruCache`
+    pub with: Option<Path>,
+}
+
+/// The value of a field generated by a factory.
+#[derive(Clone)]
+pub enum FactoryValue {
+    /// `factory = <expr>`, which may use the sequence number `n`
+    Expr(Expr),
+    /// `factory(parent = <path>)`, the ID of a row of the given table created by its factory
+    Parent(Path),
+}
+
 pub enum PatchAttr {
     // table = <string>
     TableName(String),
     Table(Path),
     Id(String),
+    // unchecked
+    Unchecked(()),
+    // validate
+    Validate(()),
+    // audit = <string>
+    Audit(String),
+    // tag [= <string>]?
+    Tag(Option<String>),
+    // memory
+    Memory(()),
 }
 
 pub enum PatchFieldAttr {
     // column = <string>
     Column(String),
     CustomType(()),
+    Json(()),
     ByRef(()),
+    Length(Bounds),
+    Range(Bounds),
+    Regex(Expr),
+}
+
+pub enum FilterAttr {
+    // table = <path>
+    Table(Path),
+}
+
+pub enum FilterFieldAttr {
+    // field = <ident>
+    Field(Ident),
+    // op = <string>
+    Op(String),
 }
 
 impl Parse for Getter {
@@ -80,6 +169,105 @@ impl Parse for Getter {
     }
 }
 
+impl Parse for Bounds {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let content;
+        let parens = syn::parenthesized!(content in input);
+        let mut bounds = Bounds {
+            min: None,
+            max: None,
+        };
+        while !content.is_empty() {
+            let name = content.parse::<Ident>()?;
+            let bound = match &*name.to_string() {
+                "min" => &mut bounds.min,
+                "max" => &mut bounds.max,
+                _ => return Err(unknown_attr(&name, &["min", "max"])),
+            };
+            content.parse::<Token![=]>()?;
+            set_once(bound, content.parse()?, &name)?;
+            if !content.is_empty() {
+                content.parse::<Token![,]>()?;
+            }
+        }
+        if bounds.min.is_none() && bounds.max.is_none() {
+            return Err(syn::Error::new(parens.span, "expected `min` and/or `max`"));
+        }
+        Ok(bounds)
+    }
+}
+
+impl Parse for CacheOptions {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let content;
+        let parens = syn::parenthesized!(content in input);
+        let (mut ttl, mut capacity, mut with) = (None, None, None);
+        while !content.is_empty() {
+            let name = content.parse::<Ident>()?;
+            content.parse::<Token![=]>()?;
+            match &*name.to_string() {
+                "ttl" => set_once(&mut ttl, parse_duration(&content.parse()?)?, &name)?,
+                "capacity" => {
+                    let lit = content.parse::<syn::LitInt>()?;
+                    set_once(&mut capacity, lit.base10_parse()?, &name)?
+                }
+                "with" => set_once(&mut with, content.parse()?, &name)?,
+                _ => return Err(unknown_attr(&name, &["ttl", "capacity", "with"])),
+            }
+            if !content.is_empty() {
+                content.parse::<Token![,]>()?;
+            }
+        }
+        Ok(CacheOptions {
+            ttl: ttl.ok_or_else(|| syn::Error::new(parens.span, "expected `ttl`"))?,
+            capacity,
+            with,
+        })
+    }
+}
+
+impl Parse for FactoryValue {
+    fn parse(input: ParseStream) -> Result<Self> {
+        if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            return input.parse().map(FactoryValue::Expr);
+        }
+        let content;
+        syn::parenthesized!(content in input);
+        let name = content.parse::<Ident>()?;
+        if name != "parent" {
+            return Err(unknown_attr(&name, &["parent"]));
+        }
+        content.parse::<Token![=]>()?;
+        content.parse().map(FactoryValue::Parent)
+    }
+}
+
+/// parses a duration like `"500ms"`, `"60s"`, `"5m"`, `"1h"` or `"1d"` into milliseconds
+fn parse_duration(lit: &syn::LitStr) -> Result<u64> {
+    let value = lit.value();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let factor = match unit {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ => {
+            let msg = "expected a unit of `ms`, `s`, `m`, `h` or `d`";
+            return Err(syn::Error::new(lit.span(), msg));
+        }
+    };
+    let ms = match amount.parse::<u64>() {
+        Ok(amount) => amount.checked_mul(factor),
+        Err(_) => None,
+    };
+    ms.ok_or_else(|| syn::Error::new(lit.span(), "expected a duration like \"60s\""))
+}
+
 impl Parse for Insertable {
     fn parse(input: ParseStream) -> Result<Self> {
         Ok(Self {
@@ -89,14 +277,26 @@ impl Parse for Insertable {
     }
 }
 
-pub fn parse_attrs<A: Parse>(attrs: &[Attribute]) -> Result<Vec<A>> {
+/// An attribute together with its name, which is used for reporting errors.
+struct Named<A>(Ident, A);
+
+impl<A: Parse> Parse for Named<A> {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let name = input.fork().parse::<Ident>()?;
+        Ok(Named(name, input.parse()?))
+    }
+}
+
+/// Parses all `#[ormx(..)]` attributes, returning them together with their name.
+pub fn parse_attrs<A: Parse>(attrs: &[Attribute]) -> Result<Vec<(Ident, A)>> {
     let attrs = attrs
         .iter()
         .filter(|a| a.path.is_ident("ormx"))
-        .map(|a| a.parse_args_with(Punctuated::<A, Token![,]>::parse_terminated))
+        .map(|a| a.parse_args_with(Punctuated::<Named<A>, Token![,]>::parse_terminated))
         .collect::<Result<Vec<_>>>()?
         .into_iter()
         .flatten()
+        .map(|Named(name, attr)| (name, attr))
         .collect();
     Ok(attrs)
 }
@@ -113,7 +313,7 @@ macro_rules! impl_parse {
                 let ident = input.parse::<syn::Ident>()?;
                 match &*ident.to_string() {
                     $( $s => (impl_parse!($($t)*))(input).map(Self::$v), )*
-                    _ => Err(input.error("unknown attribute"))
+                    _ => Err(crate::utils::unknown_attr(&ident, &[$($s),*]))
                 }
             }
         }
@@ -144,7 +344,16 @@ impl_parse!(TableAttr {
     "table" => Table(= String),
     "id" => Id(= Ident),
     "insertable" => Insertable((= Insertable)?),
-    "deletable" => Deletable()
+    "deletable" => Deletable(),
+    "unchecked" => Unchecked(),
+    "hooks" => Hooks(),
+    "validate" => Validate(),
+    "audit" => Audit(= String),
+    "tracked" => Tracked(),
+    "cache" => Cache(CacheOptions),
+    "factory" => Factory(),
+    "tag" => Tag((= String)?),
+    "memory" => Memory()
 });
 
 impl_parse!(TableFieldAttr {
@@ -152,23 +361,49 @@ impl_parse!(TableFieldAttr {
     "get_one" => GetOne(Getter),
     "get_optional" => GetOptional(Getter),
     "get_many" => GetMany(Getter),
+    "get_many_contains" => GetManyContains(Getter),
     "set" => Set((= Ident)?),
     "custom_type" => CustomType(),
+    "json" => Json(),
     "default" => Default(),
     "by_ref" => ByRef(),
-    "insert_attribute" => InsertAttr(= AnyAttribute)
+    "insert_attribute" => InsertAttr(= AnyAttribute),
+    "flatten" => Flatten((= String)?),
+    "sql_type" => SqlType(= String),
+    "length" => Length(Bounds),
+    "range" => Range(Bounds),
+    "regex" => Regex(= Expr),
+    "factory" => Factory(FactoryValue)
 });
 
 impl_parse!(PatchAttr {
     "table" => Table(= Path),
     "table_name" => TableName(= String),
-    "id" => Id(= String)
+    "id" => Id(= String),
+    "unchecked" => Unchecked(),
+    "validate" => Validate(),
+    "audit" => Audit(= String),
+    "tag" => Tag((= String)?),
+    "memory" => Memory()
 });
 
 impl_parse!(PatchFieldAttr {
     "column" => Column(= String),
     "custom_type" => CustomType(),
-    "by_ref" => ByRef()
+    "json" => Json(),
+    "by_ref" => ByRef(),
+    "length" => Length(Bounds),
+    "range" => Range(Bounds),
+    "regex" => Regex(= Expr)
+});
+
+impl_parse!(FilterAttr {
+    "table" => Table(= Path)
+});
+
+impl_parse!(FilterFieldAttr {
+    "field" => Field(= Ident),
+    "op" => Op(= String)
 });
 
 pub struct AnyAttribute(pub Vec<Attribute>);
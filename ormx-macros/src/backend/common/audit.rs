@@ -0,0 +1,201 @@
+//! Statements which additionally write a row into an audit table (`#[ormx(audit = "..")]`).
+//! The row is written by the same statement using data-modifying `WITH` queries, so it's part of
+//! the same transaction without requiring a connection.
+
+use itertools::Itertools;
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+
+/// A column changed by an audited statement.
+pub struct AuditColumn {
+    /// name of the column, used as key in the JSON objects
+    pub name: String,
+    /// the column as it has to be written in a query, i.e. quoted if necessary
+    pub column: String,
+}
+
+/// The row modified by an audited statement.
+pub struct Audit<'a> {
+    /// name of the audit table
+    pub audit_table: &'a str,
+    pub table: &'a str,
+    pub id: AuditColumn,
+    /// binding of the ID of the row, e.g. `$3`
+    pub id_binding: String,
+    /// binding of the actor, e.g. `$4`
+    pub actor_binding: String,
+}
+
+impl Audit<'_> {
+    /// `UPDATE {table} SET {assignments} WHERE {id} = {id_binding}`, logging the old and new
+    /// values of the changed columns. Nothing is logged if no column has changed.
+    /// The old row is locked, so that it's the row the update overwrites even if it's updated
+    /// concurrently. The update joins the old row, which makes postgres lock it first; otherwise,
+    /// the lock would skip the row if it's updated before. Since the affected rows of the
+    /// statement are those written into the audit table, it selects the number of updated rows as
+    /// `modified`, see [audited_update].
+    pub fn fmt_update(&self, assignments: &str, columns: &[AuditColumn]) -> String {
+        let columns_selected = std::iter::once(&self.id.column)
+            .chain(columns.iter().map(|c| &c.column))
+            .unique();
+        let selected = columns_selected.clone().join(", ");
+        let returned = columns_selected
+            .map(|column| format!("{}.{}", self.table, column))
+            .join(", ");
+        format!(
+            "WITH old_row AS (SELECT {selected} FROM {table} WHERE {id} = {id_binding} \
+             FOR UPDATE), \
+             new_row AS (UPDATE {table} SET {assignments} FROM old_row \
+             WHERE {table}.{id} = old_row.{id} RETURNING {returned}), \
+             audit AS (INSERT INTO {audit_table} {audit_columns} \
+             SELECT {table_name}, CAST(new_row.{id} AS TEXT), \
+             changes.columns, changes.old_values, changes.new_values, {actor}, now() \
+             FROM old_row, new_row, LATERAL (\
+             SELECT array_agg(o.key), jsonb_object_agg(o.key, o.value), \
+             jsonb_object_agg(o.key, n.value) \
+             FROM jsonb_each({old}) o JOIN jsonb_each({new}) n ON o.key = n.key \
+             WHERE o.value IS DISTINCT FROM n.value\
+             ) changes(columns, old_values, new_values) \
+             WHERE changes.columns IS NOT NULL) \
+             SELECT count(*) AS \"modified!\" FROM new_row",
+            selected = selected,
+            returned = returned,
+            table = self.table,
+            id = self.id.column,
+            id_binding = &self.id_binding,
+            assignments = assignments,
+            audit_table = self.audit_table,
+            audit_columns = AUDIT_COLUMNS,
+            table_name = string_literal(self.table),
+            actor = &self.actor_binding,
+            old = json_object("old_row", columns),
+            new = json_object("new_row", columns),
+        )
+    }
+
+    /// `DELETE FROM {table} WHERE {id} = {id_binding}`, logging the values of all columns.
+    pub fn fmt_delete(&self, columns: &[AuditColumn]) -> String {
+        let selected = columns.iter().map(|c| &c.column).join(", ");
+        let names = columns.iter().map(|c| string_literal(&c.name)).join(", ");
+        format!(
+            "WITH old_row AS (DELETE FROM {table} WHERE {id} = {id_binding} \
+             RETURNING {selected}) \
+             INSERT INTO {audit_table} {audit_columns} \
+             SELECT {table_name}, CAST(old_row.{id} AS TEXT), ARRAY[{names}]::TEXT[], {old}, \
+             NULL, {actor}, now() \
+             FROM old_row",
+            table = self.table,
+            id = self.id.column,
+            id_binding = &self.id_binding,
+            selected = selected,
+            audit_table = self.audit_table,
+            audit_columns = AUDIT_COLUMNS,
+            table_name = string_literal(self.table),
+            names = names,
+            old = json_object("old_row", columns),
+            actor = &self.actor_binding,
+        )
+    }
+}
+
+/// Runs the statement built by [Audit::fmt_update] on `db`, evaluating to the number of rows it
+/// updated.
+pub fn audited_update(
+    unchecked: bool,
+    sql: impl ToTokens,
+    args: impl IntoIterator<Item = TokenStream>,
+) -> TokenStream {
+    let args = args.into_iter().collect::<Vec<_>>();
+    let query = match unchecked {
+        true => quote!(sqlx::query_scalar::<_, i64>(#sql) #( .bind(#args) )*),
+        false => quote!(sqlx::query_scalar!(#sql, #( #args, )*)),
+    };
+    let query = crate::utils::with_arguments(&args, query);
+    quote!(#query.fetch_one(db).await? as u64)
+}
+
+const AUDIT_COLUMNS: &str =
+    "(table_name, row_id, columns, old_values, new_values, actor, changed_at)";
+
+/// `jsonb_build_object('a', row.a, ..)`
+fn json_object(row: &str, columns: &[AuditColumn]) -> String {
+    let pairs = columns
+        .iter()
+        .map(|c| format!("{}, {}.{}", string_literal(&c.name), row, c.column))
+        .join(", ");
+    format!("jsonb_build_object({})", pairs)
+}
+
+fn string_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str, column: &str) -> AuditColumn {
+        AuditColumn {
+            name: name.to_owned(),
+            column: column.to_owned(),
+        }
+    }
+
+    fn audit() -> Audit<'static> {
+        Audit {
+            audit_table: "audit_log",
+            table: "users",
+            id: column("id", "id"),
+            id_binding: "$3".to_owned(),
+            actor_binding: "$4".to_owned(),
+        }
+    }
+
+    #[test]
+    fn update() {
+        let columns = [column("id", "id"), column("email", "\"email\"")];
+        assert_eq!(
+            audit().fmt_update("\"email\" = $1, name = $2", &columns),
+            "WITH old_row AS (SELECT id, \"email\" FROM users WHERE id = $3 FOR UPDATE), \
+             new_row AS (UPDATE users SET \"email\" = $1, name = $2 FROM old_row \
+             WHERE users.id = old_row.id RETURNING users.id, users.\"email\"), \
+             audit AS (INSERT INTO audit_log \
+             (table_name, row_id, columns, old_values, new_values, actor, changed_at) \
+             SELECT 'users', CAST(new_row.id AS TEXT), \
+             changes.columns, changes.old_values, changes.new_values, $4, now() \
+             FROM old_row, new_row, LATERAL (\
+             SELECT array_agg(o.key), jsonb_object_agg(o.key, o.value), \
+             jsonb_object_agg(o.key, n.value) \
+             FROM jsonb_each(jsonb_build_object('id', old_row.id, 'email', old_row.\"email\")) o \
+             JOIN jsonb_each(jsonb_build_object('id', new_row.id, 'email', new_row.\"email\")) n \
+             ON o.key = n.key \
+             WHERE o.value IS DISTINCT FROM n.value\
+             ) changes(columns, old_values, new_values) \
+             WHERE changes.columns IS NOT NULL) \
+             SELECT count(*) AS \"modified!\" FROM new_row"
+        );
+    }
+
+    #[test]
+    fn update_selects_the_id() {
+        let sql = audit().fmt_update("name = $1", &[column("name", "name")]);
+        assert!(sql
+            .starts_with("WITH old_row AS (SELECT id, name FROM users WHERE id = $3 FOR UPDATE)"));
+        assert!(sql.contains("RETURNING users.id, users.name)"));
+    }
+
+    #[test]
+    fn delete() {
+        let columns = [column("id", "id"), column("it's", "\"it's\"")];
+        assert_eq!(
+            audit().fmt_delete(&columns),
+            "WITH old_row AS (DELETE FROM users WHERE id = $3 RETURNING id, \"it's\") \
+             INSERT INTO audit_log \
+             (table_name, row_id, columns, old_values, new_values, actor, changed_at) \
+             SELECT 'users', CAST(old_row.id AS TEXT), ARRAY['id', 'it''s']::TEXT[], \
+             jsonb_build_object('id', old_row.id, 'it''s', old_row.\"it's\"), \
+             NULL, $4, now() \
+             FROM old_row"
+        );
+    }
+}
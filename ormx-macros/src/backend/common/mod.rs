@@ -1,96 +1,321 @@
 //! Common functionality used for all database backends
 
-use proc_macro2::TokenStream;
+pub use audit::{audited_update, Audit, AuditColumn};
+use itertools::Itertools;
+use proc_macro2::{Span, TokenStream};
 use quote::quote;
-use syn::{Ident, Type, Visibility};
+use syn::{Generics, Ident, Lifetime, Type};
 pub use table::*;
 
 use crate::{
     attrs::Insertable,
-    backend::Backend,
+    backend::{memory, Backend},
     patch::{Patch, PatchField},
-    table::Table,
+    table::{Table, TableField},
+    utils::{
+        cow_argument, json_argument, observe_modifying, observe_read, option_inner, query,
+        query_as, tag_sql, type_name, with_lifetime,
+    },
+    validate,
 };
 
+mod audit;
 mod table;
 
 pub(crate) fn getters<B: Backend>(table: &Table<B>) -> TokenStream {
     let column_list = table.select_column_list();
-    let vis = &table.vis;
+    let row = table.row_ident();
+    let row_ty = row_ty(row.as_ref());
     let mut getters = TokenStream::new();
+    let mut caches = cached_getters(table).into_iter().map(|(cache, _)| cache);
+
+    // converts the queried rows if the table contains embedded structs
+    let (convert_one, convert_optional, convert_many) = match row {
+        Some(_) => (
+            quote!(.map(Self::from)),
+            quote!(.map(|row| row.map(Self::from))),
+            quote!(.map(|rows| rows.into_iter().map(Self::from).collect())),
+        ),
+        None => (quote!(), quote!(), quote!()),
+    };
 
     for field in table.fields.iter() {
+        let binding = B::Bindings::default().next().unwrap();
         let sql = format!(
             "SELECT {} FROM {} WHERE {} = {}",
             column_list,
             table.table,
             field.column(),
-            B::Bindings::default().next().unwrap()
+            binding
         );
+        // the statement of the getter `func`, taking an argument of type `arg`
+        let query = |func: &Ident, arg: &Type, sql: &str| {
+            let by = match (field.json, field.cow_inner()) {
+                (true, _) => json_argument(&field.ty, quote!(by), table.unchecked),
+                (false, Some(_)) if is_option_ref(arg) => quote!(by.as_deref()),
+                (false, _) => quote!(by),
+            };
+            let sql = table.tag_sql(&func.to_string(), sql.into());
+            query_as(table.unchecked, &row_ty, &sql, Some(by))
+        };
 
         if let Some(getter) = &field.get_one {
             let (func, arg) = getter.or_fallback(field);
-            getters.extend(get_one(vis, &func, &arg, &sql));
+            let fetch = match table.memory {
+                true => {
+                    let rows = memory::find(field, &arg);
+                    quote!(#rows.into_iter().next().ok_or(sqlx::Error::RowNotFound))
+                }
+                false => {
+                    let query = query(&func, &arg, &sql);
+                    quote!(#query.fetch_one(db).await #convert_one)
+                }
+            };
+            getters.extend(get_one(table, &func, &arg, fetch, caches.next()));
         }
 
         if let Some(getter) = &field.get_optional {
             let (func, arg) = getter.or_fallback(field);
-            getters.extend(get_optional(vis, &func, &arg, &sql));
+            let fetch = match table.memory {
+                true => {
+                    let rows = memory::find(field, &arg);
+                    quote!(sqlx::Result::Ok(#rows.into_iter().next()))
+                }
+                false => {
+                    let query = query(&func, &arg, &sql);
+                    quote!(#query.fetch_optional(db).await #convert_optional)
+                }
+            };
+            getters.extend(get_optional(table, &func, &arg, fetch, caches.next()));
         }
 
         if let Some(getter) = &field.get_many {
             let (func, arg) = getter.or_fallback(field);
-            getters.extend(get_many(vis, &func, &arg, &sql));
+            let fetch = match table.memory {
+                true => {
+                    let rows = memory::find(field, &arg);
+                    quote!(sqlx::Result::Ok(#rows))
+                }
+                false => {
+                    let query = query(&func, &arg, &sql);
+                    quote!(#query.fetch_all(db).await #convert_many)
+                }
+            };
+            getters.extend(get_many(table, &func, &arg, fetch));
+        }
+
+        if let Some(getter) = &field.get_many_contains {
+            let (func, arg) =
+                getter.or_fallback_named(field, &format!("by_{}_contains", field.field));
+            let sql = format!(
+                "SELECT {} FROM {} WHERE {}",
+                column_list,
+                table.table,
+                B::fmt_json_contains(&field.column(), &binding)
+            );
+            let fetch = match table.memory {
+                true => memory::find_contains(field),
+                false => {
+                    let query = query(&func, &arg, &sql);
+                    quote!(#query.fetch_all(db).await #convert_many)
+                }
+            };
+            getters.extend(get_many(table, &func, &arg, fetch));
         }
     }
 
     let table_ident = &table.ident;
+    let (impl_generics, ty_generics, where_clause) = table.generics.split_for_impl();
     quote! {
-        impl #table_ident {
+        impl #impl_generics #table_ident #ty_generics #where_clause {
             #getters
         }
     }
 }
 
-pub fn get_one(vis: &Visibility, ident: &Ident, by_ty: &Type, sql: &str) -> TokenStream {
-    quote! {
-        #vis async fn #ident(
-            db: impl sqlx::Executor<'_, Database = ormx::Db>,
-            by: #by_ty,
-        ) -> sqlx::Result<Self> {
-            sqlx::query_as!(Self, #sql, by)
-                .fetch_one(db)
-                .await
+/// Formats a `CREATE TABLE` statement for the table.
+/// Types without known mapping use the name of the rust type, which is the default of
+/// `#[derive(sqlx::Type)]`.
+pub(crate) fn create_table<B: Backend>(table: &Table<B>) -> String {
+    let columns = table
+        .columns()
+        .map(|field| {
+            let ty = option_inner(&field.ty).unwrap_or(&field.ty);
+            let mut sql_type = sql_type(field).unwrap_or_else(|| match type_name(ty) {
+                Some((ident, _)) => ident.to_string(),
+                None => quote!(#ty).to_string(),
+            });
+
+            let is_id = field.field == table.id.field && field.path.is_empty();
+            if is_id && field.default && field.sql_type.is_none() {
+                sql_type = B::serial_type(&sql_type);
+            }
+
+            let mut column = format!("    {} {}", field.column(), sql_type);
+            if !field.nullable() {
+                column.push_str(" NOT NULL");
+            }
+            if is_id {
+                column.push_str(" PRIMARY KEY");
+            } else if field.get_one.is_some() || field.get_optional.is_some() {
+                column.push_str(" UNIQUE");
+            }
+            column
+        })
+        .join(",\n");
+
+    format!("CREATE TABLE {} (\n{}\n)", table.table, columns)
+}
+
+/// The SQL type of a column, either given by `#[ormx(sql_type = "..")]` or inferred from the type
+/// of the field. Returns `None` if there is no known mapping for the type.
+pub(crate) fn sql_type<B: Backend>(field: &TableField<B>) -> Option<String> {
+    if let Some(sql_type) = &field.sql_type {
+        return Some(sql_type.clone());
+    }
+    if field.json {
+        return Some(B::JSON_TYPE.to_owned());
+    }
+
+    let ty = option_inner(&field.ty).unwrap_or(&field.ty);
+    // `Cow<'a, str>` is stored like `String`, and `Cow<'a, [u8]>` like `Vec<u8>`
+    let ty = match field.cow_inner() {
+        Some(Type::Path(ty)) if ty.path.is_ident("str") => return B::sql_type("String", None),
+        Some(Type::Slice(slice)) => {
+            let arg = type_name(&slice.elem).map(|(arg, _)| arg.to_string());
+            return B::sql_type("Vec", arg.as_deref());
         }
+        _ => ty,
+    };
+    let (ident, arg) = type_name(ty)?;
+    let arg = arg.and_then(type_name).map(|(arg, _)| arg.to_string());
+    B::sql_type(&ident.to_string(), arg.as_deref())
+}
+
+/// Type into which rows are queried - either `Self` or, for tables with embedded structs, the
+/// generated row struct (see [Table::row_ident]).
+pub(crate) fn row_ty(row: Option<&Ident>) -> TokenStream {
+    match row {
+        Some(row) => quote!(#row),
+        None => quote!(Self),
     }
 }
 
-pub fn get_optional(vis: &Visibility, ident: &Ident, by_ty: &Type, sql: &str) -> TokenStream {
+/// a `get_one` getter, where `fetch` evaluates to the queried row
+pub fn get_one<B: Backend>(
+    table: &Table<B>,
+    ident: &Ident,
+    by_ty: &Type,
+    fetch: TokenStream,
+    cache: Option<Ident>,
+) -> TokenStream {
+    let fetch = match cache {
+        Some(cache) => {
+            let lookup = cache_lookup(&cache, by_ty, quote!(row));
+            quote! {
+                #lookup
+                let row = #fetch?;
+                ormx::cache::Cache::insert(Self::#cache(), key, Clone::clone(&row));
+                Ok(row)
+            }
+        }
+        None => fetch,
+    };
+    getter(table, ident, by_ty, quote!(Self), fetch)
+}
+
+/// a `get_optional` getter, where `fetch` evaluates to the queried row, if any
+pub fn get_optional<B: Backend>(
+    table: &Table<B>,
+    ident: &Ident,
+    by_ty: &Type,
+    fetch: TokenStream,
+    cache: Option<Ident>,
+) -> TokenStream {
+    let fetch = match cache {
+        Some(cache) => {
+            let lookup = cache_lookup(&cache, by_ty, quote!(Some(row)));
+            quote! {
+                #lookup
+                let row = #fetch?;
+                if let Some(row) = &row {
+                    ormx::cache::Cache::insert(Self::#cache(), key, Clone::clone(row));
+                }
+                Ok(row)
+            }
+        }
+        None => fetch,
+    };
+    getter(table, ident, by_ty, quote!(Option<Self>), fetch)
+}
+
+/// a getter returning `sqlx::Result<#output>`, where `fetch` evaluates to it. The getter isn't an
+/// `async fn`, so that it can be `#[track_caller]`, see `ormx::n_plus_one`.
+fn getter<B: Backend>(
+    table: &Table<B>,
+    ident: &Ident,
+    by_ty: &Type,
+    output: TokenStream,
+    fetch: TokenStream,
+) -> TokenStream {
+    let vis = &table.vis;
+    let box_future = crate::utils::box_future();
+    let lifetime = Lifetime::new("'__a", Span::call_site());
+    let by_ty = with_lifetime(by_ty, &lifetime);
+    let future = observe_read(
+        &table.table,
+        &ident.to_string(),
+        None,
+        quote!(by),
+        quote!(async move { #fetch }),
+    );
     quote! {
-        #vis async fn #ident(
-            db: impl sqlx::Executor<'_, Database = ormx::Db>,
+        #[track_caller]
+        #vis fn #ident<'__a, '__c: '__a>(
+            db: impl sqlx::Executor<'__c, Database = ormx::Db> + '__a,
             by: #by_ty,
-        ) -> sqlx::Result<Option<Self>> {
-            sqlx::query_as!(Self, #sql, by)
-                .fetch_optional(db)
-                .await
+        ) -> #box_future<'__a, sqlx::Result<#output>>
+        where
+            Self: '__a,
+            #by_ty: '__a,
+        {
+            Box::pin(#future)
         }
     }
 }
 
-pub fn get_many(vis: &Visibility, ident: &Ident, by_ty: &Type, sql: &str) -> TokenStream {
+/// whether `ty` is a reference to an `Option`, e.g. `&Option<String>`
+fn is_option_ref(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(reference) => option_inner(&reference.elem).is_some(),
+        _ => false,
+    }
+}
+
+/// returns early with the row cached for the argument of a getter, see [cached_getters]
+fn cache_lookup(cache: &Ident, by_ty: &Type, found: TokenStream) -> TokenStream {
+    let by = match by_ty {
+        Type::Reference(_) => quote!(by),
+        _ => quote!(&by),
+    };
     quote! {
-        #vis async fn #ident(
-            db: impl sqlx::Executor<'_, Database = ormx::Db>,
-            by: #by_ty,
-        ) -> sqlx::Result<Vec<Self>> {
-            sqlx::query_as!(Self, #sql, by)
-                .fetch_all(db)
-                .await
+        let key = ToOwned::to_owned(#by);
+        if let Some(row) = ormx::cache::Cache::get(Self::#cache(), &key) {
+            return Ok(#found);
         }
     }
 }
 
+/// a `get_many` or `get_many_contains` getter, where `fetch` evaluates to the queried rows
+pub fn get_many<B: Backend>(
+    table: &Table<B>,
+    ident: &Ident,
+    by_ty: &Type,
+    fetch: TokenStream,
+) -> TokenStream {
+    getter(table, ident, by_ty, quote!(Vec<Self>), fetch)
+}
+
 pub fn setters<B: Backend>(table: &Table<B>) -> TokenStream {
     let vis = &table.vis;
     let mut setters = TokenStream::new();
@@ -101,41 +326,87 @@ pub fn setters<B: Backend>(table: &Table<B>) -> TokenStream {
 
         if let Some(fn_name) = &field.set {
             let mut bindings = B::Bindings::default();
-            let sql = format!(
-                "UPDATE {} SET {} = {} WHERE {} = {}",
-                table.table,
-                field.column(),
-                bindings.next().unwrap(),
-                table.id.column(),
-                bindings.next().unwrap(),
-            );
+            let assignment = format!("{} = {}", field.column(), bindings.next().unwrap());
+            let id_binding = bindings.next().unwrap();
+            let sql = match &table.audit {
+                Some(audit_table) => Audit {
+                    audit_table,
+                    table: &table.table,
+                    id: table.id.audit_column(),
+                    id_binding: id_binding.into(),
+                    actor_binding: bindings.next().unwrap().into(),
+                }
+                .fmt_update(&assignment, &[field.audit_column()]),
+                None => format!(
+                    "UPDATE {} SET {} WHERE {} = {}",
+                    table.table,
+                    assignment,
+                    table.id.column(),
+                    id_binding,
+                ),
+            };
 
             let mut value = quote!(value);
-            if field.custom_type {
+            if field.json {
+                value = json_argument(field_ty, quote!(&#value), table.unchecked);
+            } else if field.cow_inner().is_some() {
+                value = cow_argument(field_ty, value);
+            } else if table.unchecked {
+                value = quote!(&#value);
+            }
+            if field.custom_type && !table.unchecked {
                 value = quote!(#value as #field_ty)
             }
-            if field.by_ref {
+            if field.by_ref && !table.unchecked {
                 value = quote!(&(#value));
             }
+            let id = quote!(<Self as ormx::Table>::id(self));
+            let invalidate = invalidate_cache(table, id.clone());
+            let query = match table.memory {
+                true => memory::set(field_ident, &id),
+                false => {
+                    let args = vec![value, id.clone()]
+                        .into_iter()
+                        .chain(audit_actor(table));
+                    let sql = table.tag_sql(&fn_name.to_string(), sql);
+                    match table.audit {
+                        Some(_) => audited_update(table.unchecked, &sql, args),
+                        None => {
+                            let query = query(table.unchecked, &sql, args);
+                            quote!(#query.execute(db).await?.rows_affected())
+                        }
+                    }
+                }
+            };
+            let future = observe_modifying(
+                &table.table,
+                &fn_name.to_string(),
+                Some(id),
+                quote! {
+                    async move {
+                        let modified = #query;
+                        #invalidate
+                        self.#field_ident = value;
+                        Ok(ormx::observe::Modified(modified))
+                    }
+                },
+            );
             setters.extend(quote! {
                 #vis async fn #fn_name(
                     &mut self,
                     db: impl sqlx::Executor<'_, Database = ormx::Db>,
                     value: #field_ty
                 ) -> sqlx::Result<()> {
-                    sqlx::query!(#sql, #value, <Self as ormx::Table>::id(self))
-                        .execute(db)
-                        .await?;
-                    self.#field_ident = value;
-                    Ok(())
+                    #future.await
                 }
             })
         }
     }
 
     let table_ident = &table.ident;
+    let (impl_generics, ty_generics, where_clause) = table.generics.split_for_impl();
     quote! {
-        impl #table_ident {
+        impl #impl_generics #table_ident #ty_generics #where_clause {
             #setters
         }
     }
@@ -145,57 +416,211 @@ pub(crate) fn impl_patch<B: Backend>(patch: &Patch) -> TokenStream {
     let patch_ident = &patch.ident;
     let table_path = &patch.table;
     let field_idents = &patch
-        .fields
-        .iter()
+        .columns()
         .map(|field| &field.ident)
         .collect::<Vec<&Ident>>();
-    let query_args = &patch
-        .fields
-        .iter()
-        .map(PatchField::fmt_as_argument)
-        .collect::<Vec<TokenStream>>();
+    let query_args = |unchecked: bool| {
+        patch
+            .columns()
+            .map(move |field| field.fmt_as_argument(unchecked))
+            .chain(Some(quote!(id)))
+            .chain(patch.audit.as_ref().map(|_| quote!(ormx::audit::actor())))
+    };
 
     let mut bindings = B::Bindings::default();
     let mut assignments = Vec::with_capacity(patch.fields.len());
-    for field in &patch.fields {
+    for field in patch.columns() {
         let fragment = format!("{} = {}", field.column, bindings.next().unwrap());
         assignments.push(fragment);
     }
     let assignments = assignments.join(", ");
 
-    let sql = format!(
-        "UPDATE {} SET {} WHERE {} = {}",
-        &patch.table_name,
-        assignments,
-        patch.id,
-        bindings.next().unwrap()
-    );
+    let id_binding = bindings.next().unwrap();
+    let sql = match &patch.audit {
+        Some(audit_table) => Audit {
+            audit_table,
+            table: &patch.table_name,
+            id: AuditColumn {
+                name: patch.id.clone(),
+                column: patch.id.clone(),
+            },
+            id_binding: id_binding.into(),
+            actor_binding: bindings.next().unwrap().into(),
+        }
+        .fmt_update(
+            &assignments,
+            &patch
+                .columns()
+                .map(|field| AuditColumn {
+                    name: field.column.clone(),
+                    column: field.column.clone(),
+                })
+                .collect::<Vec<_>>(),
+        ),
+        None => format!(
+            "UPDATE {} SET {} WHERE {} = {}",
+            &patch.table_name, assignments, patch.id, id_binding
+        ),
+    };
+
+    let query = match patch.memory {
+        true => memory::patch(&quote!(#table_path), field_idents),
+        false => {
+            let update = |unchecked: bool, sql: TokenStream| match patch.audit {
+                Some(_) => audited_update(unchecked, sql, query_args(unchecked)),
+                None => {
+                    let query = query(unchecked, sql, query_args(unchecked));
+                    quote!(#query.execute(db).await?.rows_affected())
+                }
+            };
+            match &patch.tag {
+                Some(tag) => {
+                    let sql = tag_sql(Some(tag), &patch.table_name, "patch_row", sql);
+                    update(patch.unchecked, quote!(#sql))
+                }
+                // inherits the tag of the table, which is only known at runtime. The checked
+                // statement is used if the table isn't tagged, so the SQL is still checked.
+                None => {
+                    let tagged = update(true, quote!(&sql));
+                    let tagged = quote! {{
+                        let sql = ormx::tag_sql::<#table_path>("patch_row", #sql);
+                        #tagged
+                    }};
+                    match patch.unchecked {
+                        true => tagged,
+                        false => {
+                            let untagged = update(false, quote!(#sql));
+                            quote! {
+                                match <#table_path as ormx::TableMeta>::TAG {
+                                    Some(_) => #tagged,
+                                    None => #untagged,
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    let mut assertions = vec![];
+    if cfg!(feature = "memory") {
+        let memory = patch.memory;
+        let message = match memory {
+            true => format!(
+                "`{}` has #[ormx(memory)], but its table doesn't",
+                patch_ident
+            ),
+            false => format!(
+                "the table of `{}` has #[ormx(memory)], so the patch needs it as well",
+                patch_ident
+            ),
+        };
+        assertions.push((
+            "MEMORY",
+            quote!(<#table_path as ormx::TableMeta>::MEMORY == #memory),
+            message,
+        ));
+    }
+    if cfg!(feature = "postgres") && patch.audit.is_none() {
+        assertions.push((
+            "AUDIT",
+            quote!(<#table_path as ormx::TableMeta>::AUDIT.is_none()),
+            format!(
+                "the table of `{}` has #[ormx(audit)], so the patch needs #[ormx(audit = \"..\")] \
+                 as well",
+                patch_ident
+            ),
+        ));
+    }
+    let (assertions, check_assertions) = patch_assertions(patch, &assertions);
 
     let box_future = crate::utils::box_future();
+    let (impl_generics, ty_generics, where_clause) = patch.generics.split_for_impl();
+    let (validate, impl_validate) = match patch.validate {
+        true => (
+            quote!(ormx::Validate::validate(self)?;),
+            validate::impl_validate(
+                patch_ident,
+                &patch.generics,
+                patch.columns().map(PatchField::fmt_validations),
+            ),
+        ),
+        false => (quote!(), quote!()),
+    };
+    let future = observe_modifying(
+        &patch.table_name,
+        "patch_row",
+        Some(quote!(id)),
+        quote! {
+            async move {
+                #validate
+                let modified = #query;
+                <#table_path as ormx::Table>::__ormx_invalidate(id);
+                Ok(ormx::observe::Modified(modified))
+            }
+        },
+    );
     quote! {
-        impl ormx::Patch for #patch_ident {
+        #impl_validate
+        #assertions
+
+        impl #impl_generics ormx::Patch for #patch_ident #ty_generics #where_clause {
             type Table = #table_path;
 
             fn apply_to(self, entity: &mut Self::Table) {
                 #( entity.#field_idents = self.#field_idents; )*
             }
 
-            fn patch_row<'a, 'c: 'a>(
-                &'a self,
-                db: impl sqlx::Executor<'c, Database = ormx::Db> + 'a,
+            fn patch_row<'__a, '__c: '__a>(
+                &'__a self,
+                db: impl sqlx::Executor<'__c, Database = ormx::Db> + '__a,
                 id: <Self::Table as ormx::Table>::Id,
-            ) -> #box_future<'a, sqlx::Result<()>> {
-                Box::pin(async move {
-                    sqlx::query!(#sql, #( self.#query_args, )* id)
-                        .execute(db)
-                        .await?;
-                    Ok(())
-                })
+            ) -> #box_future<'__a, sqlx::Result<()>> {
+                #check_assertions
+                Box::pin(#future)
             }
         }
     }
 }
 
+/// Compile-time assertions that a patch matches the attributes of its table, each given by a
+/// name, a `bool` expression and the message to fail with. Returns the items declaring them and
+/// the statements evaluating them in `patch_row`.
+/// Free constants can't use the generics of a generic patch, so its assertions are associated
+/// constants instead, which only fail once `patch_row` is instantiated.
+fn patch_assertions(
+    patch: &Patch,
+    assertions: &[(&str, TokenStream, String)],
+) -> (TokenStream, TokenStream) {
+    if patch.generics.params.is_empty() {
+        let items = assertions.iter().map(|(_, check, message)| {
+            quote!(
+                const _: () = assert!(#check, #message);
+            )
+        });
+        return (quote!(#(#items)*), quote!());
+    }
+
+    let patch_ident = &patch.ident;
+    let (impl_generics, ty_generics, where_clause) = patch.generics.split_for_impl();
+    let names = assertions
+        .iter()
+        .map(|(name, ..)| Ident::new(&format!("__ORMX_ASSERT_{}", name), Span::call_site()))
+        .collect::<Vec<_>>();
+    let checks = assertions.iter().map(|(_, check, _)| check);
+    let messages = assertions.iter().map(|(.., message)| message);
+    let items = quote! {
+        impl #impl_generics #patch_ident #ty_generics #where_clause {
+            #(
+                #[doc(hidden)]
+                const #names: () = assert!(#checks, #messages);
+            )*
+        }
+    };
+    (items, quote!(#( let () = Self::#names; )*))
+}
+
 pub(crate) fn insert_struct<B: Backend>(table: &Table<B>) -> TokenStream {
     let Insertable { ident, attrs } = match &table.insertable {
         Some(i) => i,
@@ -210,9 +635,11 @@ pub(crate) fn insert_struct<B: Backend>(table: &Table<B>) -> TokenStream {
     });
 
     let from_impl = impl_from_for_insert_struct(table, ident);
+    let generics = &table.generics;
+    let where_clause = &generics.where_clause;
     quote! {
         #(#attrs)*
-        #vis struct #ident {
+        #vis struct #ident #generics #where_clause {
             #( #insert_fields, )*
         }
 
@@ -220,8 +647,107 @@ pub(crate) fn insert_struct<B: Backend>(table: &Table<B>) -> TokenStream {
     }
 }
 
+pub(crate) fn row_struct<B: Backend>(table: &Table<B>) -> TokenStream {
+    let row_ident = match table.row_ident() {
+        Some(row) => row,
+        None => return quote!(),
+    };
+    let table_ident = &table.ident;
+    let (impl_generics, ty_generics, where_clause) = table.generics.split_for_impl();
+    let row_generics = table.row_generics();
+    let (_, row_ty_generics, _) = row_generics.split_for_impl();
+    let row_fields = table.columns().map(|field| {
+        let alias = field.alias();
+        let ty = field.row_ty();
+        quote!(#alias: #ty)
+    });
+    let row = Ident::new("row", Span::call_site());
+    let construct_row = construct_from_row(&table.fields, &mut |field| field.fmt_from_row(&row));
+    let from_row = table
+        .unchecked
+        .then(|| impl_from_row(table, &row_ident, &row_generics));
+
+    quote! {
+        #[doc(hidden)]
+        #[allow(non_camel_case_types, non_snake_case)]
+        struct #row_ident #row_generics {
+            #( #row_fields, )*
+        }
+
+        impl #impl_generics From<#row_ident #row_ty_generics> for #table_ident #ty_generics
+        #where_clause
+        {
+            fn from(row: #row_ident #row_ty_generics) -> Self {
+                Self {
+                    #construct_row
+                }
+            }
+        }
+
+        #from_row
+    }
+}
+
+/// Implements `sqlx::FromRow` for the row struct of an unchecked table.
+/// The columns are read by their index, since the names of custom types and JSON columns contain
+/// the type overrides of `sqlx::query!`.
+fn impl_from_row<B: Backend>(
+    table: &Table<B>,
+    row_ident: &Ident,
+    generics: &Generics,
+) -> TokenStream {
+    let (_, ty_generics, _) = generics.split_for_impl();
+    let params = &generics.params;
+    let aliases = table.columns().map(TableField::alias);
+    let tys = table.columns().map(TableField::row_ty).collect::<Vec<_>>();
+    let indices = 0..tys.len();
+
+    quote! {
+        impl<'__r, #params> sqlx::FromRow<'__r, <ormx::Db as sqlx::Database>::Row>
+            for #row_ident #ty_generics
+        where
+            #( #tys: sqlx::Decode<'__r, ormx::Db> + sqlx::Type<ormx::Db>, )*
+        {
+            fn from_row(row: &'__r <ormx::Db as sqlx::Database>::Row) -> sqlx::Result<Self> {
+                Ok(Self {
+                    #( #aliases: sqlx::Row::try_get(row, #indices)?, )*
+                })
+            }
+        }
+    }
+}
+
+/// assigns the fields of a struct from the flat row struct, constructing embedded structs
+/// `read` returns the value of a column.
+fn construct_from_row<B: Backend>(
+    fields: &[TableField<B>],
+    read: &mut dyn FnMut(&TableField<B>) -> TokenStream,
+) -> TokenStream {
+    fields
+        .iter()
+        .map(|field| {
+            let ident = &field.field;
+            if field.marker {
+                return quote!(#ident: std::marker::PhantomData,);
+            }
+            let flatten = field.flatten.as_ref();
+            match flatten.and_then(|flatten| flatten.fields.as_ref().map(|f| (&flatten.ty, f))) {
+                Some((ty, fields)) => {
+                    let fields = construct_from_row(fields, read);
+                    quote!(#ident: #ty { #fields },)
+                }
+                None => {
+                    let value = read(field);
+                    quote!(#ident: #value,)
+                }
+            }
+        })
+        .collect()
+}
+
 fn impl_from_for_insert_struct<B: Backend>(table: &Table<B>, insert_struct: &Ident) -> TokenStream {
     let table_ident = &table.ident;
+    let (impl_generics, ty_generics, where_clause) = table.generics.split_for_impl();
 
     let fields = table
         .insertable_fields()
@@ -232,8 +758,10 @@ fn impl_from_for_insert_struct<B: Backend>(table: &Table<B>, insert_struct: &Ide
         .collect::<TokenStream>();
 
     quote! {
-        impl From<#table_ident> for #insert_struct {
-            fn from(v: #table_ident) -> Self {
+        impl #impl_generics From<#table_ident #ty_generics> for #insert_struct #ty_generics
+        #where_clause
+        {
+            fn from(v: #table_ident #ty_generics) -> Self {
                 Self {
                     #fields
                 }
@@ -241,3 +769,108 @@ fn impl_from_for_insert_struct<B: Backend>(table: &Table<B>, insert_struct: &Ide
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use syn::{parse_quote, DeriveInput};
+
+    use super::*;
+    use crate::backend::Implementation;
+
+    fn create(input: DeriveInput) -> String {
+        let table = Table::<Implementation>::try_from(&input).unwrap();
+        Implementation::fmt_create_table(&table)
+    }
+
+    // the SQL types of a generated `i32` ID, `String`, `Vec<u8>`, JSON, `i64` and `i32`
+    #[cfg(feature = "postgres")]
+    const TYPES: [&str; 6] = ["SERIAL", "TEXT", "BYTEA", "JSONB", "BIGINT", "INTEGER"];
+    #[cfg(feature = "mysql")]
+    const TYPES: [&str; 6] = [
+        "INT AUTO_INCREMENT",
+        "VARCHAR(255)",
+        "BLOB",
+        "JSON",
+        "BIGINT",
+        "INT",
+    ];
+
+    #[test]
+    fn create_table() {
+        let [serial, text, bytes, json, ..] = TYPES;
+        let sql = create(parse_quote! {
+            #[ormx(table = "users", id = id, insertable)]
+            struct User {
+                #[ormx(default)]
+                id: i32,
+                #[ormx(get_one(&str))]
+                email: String,
+                #[ormx(get_optional)]
+                nickname: Option<String>,
+                bio: Option<String>,
+                avatar: Vec<u8>,
+                #[ormx(json)]
+                settings: Settings,
+                role: Role,
+                #[ormx(default, sql_type = "TIMESTAMP DEFAULT now()")]
+                created_at: NaiveDateTime,
+            }
+        });
+        assert_eq!(
+            sql,
+            format!(
+                "CREATE TABLE users (\n    \
+                 id {serial} NOT NULL PRIMARY KEY,\n    \
+                 email {text} NOT NULL UNIQUE,\n    \
+                 nickname {text} UNIQUE,\n    \
+                 bio {text},\n    \
+                 avatar {bytes} NOT NULL,\n    \
+                 settings {json} NOT NULL,\n    \
+                 role Role NOT NULL,\n    \
+                 created_at TIMESTAMP DEFAULT now() NOT NULL\n\
+                 )"
+            )
+        );
+    }
+
+    #[test]
+    fn ids_without_default_are_not_generated() {
+        let [_, text, _, _, bigint, int] = TYPES;
+        let sql = create(parse_quote! {
+            #[ormx(table = "tokens", id = token)]
+            struct Token {
+                token: String,
+                #[ormx(get_many)]
+                user_id: i64,
+                uses: i32,
+            }
+        });
+        assert_eq!(
+            sql,
+            format!(
+                "CREATE TABLE tokens (\n    \
+                 token {text} NOT NULL PRIMARY KEY,\n    \
+                 user_id {bigint} NOT NULL,\n    \
+                 uses {int} NOT NULL\n\
+                 )"
+            )
+        );
+    }
+
+    #[test]
+    fn sql_type_overrides_the_generated_id() {
+        let sql = create(parse_quote! {
+            #[ormx(table = "orders", id = id, insertable)]
+            struct Order {
+                #[ormx(default, sql_type = "UUID DEFAULT gen_random_uuid()")]
+                id: Uuid,
+            }
+        });
+        assert_eq!(
+            sql,
+            "CREATE TABLE orders (\n    id UUID DEFAULT gen_random_uuid() NOT NULL PRIMARY KEY\n)"
+        );
+    }
+}
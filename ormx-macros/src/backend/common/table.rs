@@ -1,24 +1,45 @@
-use proc_macro2::TokenStream;
-use quote::quote;
+use itertools::Itertools;
+use proc_macro2::{Span, TokenStream};
+use quote::{quote, quote_spanned};
+use syn::{spanned::Spanned, Ident, Type};
 
 use crate::{
-    backend::Backend,
+    attrs::FactoryValue,
+    backend::{common::audited_update, memory, Audit, Backend},
     table::{Table, TableField},
+    utils::{observe, observe_modifying, observe_read, observe_stream, query, query_as},
+    validate,
 };
 
 pub fn impl_table<B: Backend>(table: &Table<B>) -> TokenStream {
     let table_ident = &table.ident;
+    let (impl_generics, ty_generics, where_clause) = table.generics.split_for_impl();
     let id_ident = &table.id.field;
     let id_ty = &table.id.ty;
     let column_list = table.select_column_list();
 
     let get = get::<B>(table, &column_list);
+    let get_by_ids = match table.memory {
+        true => memory::get_by_ids(&table.table),
+        false => B::get_by_ids(table),
+    };
     let stream_all = stream_all(table, &column_list);
     let stream_all_paginated = stream_all_paginated::<B>(table, &column_list);
     let update = update::<B>(table);
+    let patch = patch(table);
+    let reload = reload(table);
+    let invalidate = invalidate_cache(table, quote!(id)).map(|invalidate| {
+        quote! {
+            fn __ormx_invalidate(id: Self::Id) {
+                #invalidate
+            }
+        }
+    });
 
+    // The lifetimes of the generated methods are named `'__a` and `'__c`, so that they don't
+    // shadow the lifetime parameters of the table.
     quote! {
-        impl ormx::Table for #table_ident {
+        impl #impl_generics ormx::Table for #table_ident #ty_generics #where_clause {
             type Id = #id_ty;
 
             fn id(&self) -> Self::Id { self.#id_ident }
@@ -27,25 +48,715 @@ pub fn impl_table<B: Backend>(table: &Table<B>) -> TokenStream {
             #stream_all
             #stream_all_paginated
             #update
+            #patch
+            #reload
+            #invalidate
+        }
+
+        impl #impl_generics ormx::GetByIds for #table_ident #ty_generics #where_clause {
+            #get_by_ids
+        }
+    }
+}
+
+pub fn impl_validate<B: Backend>(table: &Table<B>) -> TokenStream {
+    if !table.validate {
+        return quote!();
+    }
+
+    let columns = table.columns();
+    let mut impls = validate::impl_validate(
+        &table.ident,
+        &table.generics,
+        columns.map(TableField::fmt_validations),
+    );
+    if let Some(insertable) = &table.insertable {
+        let columns = table.insertable_fields().flat_map(TableField::columns);
+        impls.extend(validate::impl_validate(
+            &insertable.ident,
+            &table.generics,
+            columns.map(TableField::fmt_validations),
+        ));
+    }
+    impls
+}
+
+pub fn impl_track<B: Backend>(table: &Table<B>) -> TokenStream {
+    if !table.tracked {
+        return quote!();
+    }
+
+    let table_ident = &table.ident;
+    let (impl_generics, ty_generics, where_clause) = table.generics.split_for_impl();
+    let box_future = crate::utils::box_future();
+
+    let mut comparisons = vec![];
+    let mut assignments = vec![];
+    for (index, column) in table.columns().enumerate() {
+        if column.path.is_empty() && column.field == table.id.field {
+            continue;
+        }
+        let access = column.fmt_access();
+        let (path, field) = (&column.path, &column.field);
+        comparisons.push(quote! {
+            if #access != original #(.#path)* .#field {
+                changed.push(#index);
+            }
+        });
+        let sql = format!("{} = ", column.column());
+        let value = column.fmt_as_argument(true);
+        assignments.push(quote!(#index => query.push(#sql).push_bind(#value)));
+    }
+    let id_sql = format!("{} = ", table.id.column());
+    let id_value = table.id.fmt_as_argument(true);
+
+    let before_save = table.hooks.then(|| {
+        quote! {
+            fn before_save<'__a>(
+                &'__a mut self,
+                db: impl sqlx::Executor<'__a, Database = ormx::Db> + '__a,
+            ) -> #box_future<'__a, sqlx::Result<()>> {
+                ormx::Hooks::before_update(self, db)
+            }
+        }
+    });
+    let validate = table.validate.then(|| {
+        quote! {
+            fn validate_changes(&self) -> sqlx::Result<()> {
+                <Self as ormx::Validate>::validate(self)?;
+                Ok(())
+            }
+        }
+    });
+    let invalidate = invalidate_cache(table, quote!(ormx::Table::id(self)));
+    let after_save = (table.hooks || invalidate.is_some()).then(|| {
+        let after_hook = match table.hooks {
+            true => quote!(ormx::Hooks::after_update(self, db)),
+            false => quote!(Box::pin(async { Ok(()) })),
+        };
+        quote! {
+            fn after_save<'__a>(
+                &'__a self,
+                db: impl sqlx::Executor<'__a, Database = ormx::Db> + '__a,
+            ) -> #box_future<'__a, sqlx::Result<()>> {
+                #invalidate
+                #after_hook
+            }
         }
+    });
+
+    quote! {
+        impl #impl_generics ormx::Track for #table_ident #ty_generics #where_clause {
+            fn changed_columns(&self, original: &Self) -> Vec<usize> {
+                let mut changed = vec![];
+                #( #comparisons )*
+                changed
+            }
+
+            fn push_assignment<'__a>(
+                &'__a self,
+                index: usize,
+                query: &mut sqlx::QueryBuilder<'__a, ormx::Db>,
+            ) {
+                match index {
+                    #( #assignments, )*
+                    _ => panic!("{} is not the index of a column", index),
+                };
+            }
+
+            fn push_id<'__a>(&'__a self, query: &mut sqlx::QueryBuilder<'__a, ormx::Db>) {
+                query.push(#id_sql).push_bind(#id_value);
+            }
+
+            #before_save
+            #validate
+            #after_save
+        }
+    }
+}
+
+pub fn impl_cached<B: Backend>(table: &Table<B>) -> TokenStream {
+    let options = match &table.cache {
+        Some(options) => options,
+        None => return quote!(),
+    };
+
+    let table_ident = &table.ident;
+    let id_ty = &table.id.ty;
+    let cache_ty = match &options.with {
+        Some(with) => quote!(#with),
+        None => quote!(ormx::cache::LruCache),
+    };
+    let ttl = options.ttl;
+    let capacity = match options.capacity {
+        Some(capacity) => quote!(#capacity),
+        None => quote!(ormx::cache::DEFAULT_CAPACITY),
+    };
+    let static_cache = |key: &TokenStream| {
+        quote! {
+            static CACHE: std::sync::OnceLock<#cache_ty<#key, #table_ident>> =
+                std::sync::OnceLock::new();
+            CACHE.get_or_init(|| {
+                let ttl = std::time::Duration::from_millis(#ttl);
+                ormx::cache::Cache::new(ttl, #capacity)
+            })
+        }
+    };
+
+    let getters = cached_getters(table);
+    let getter_caches = getters.iter().map(|(cache, key)| {
+        let init = static_cache(key);
+        quote! {
+            #[doc(hidden)]
+            fn #cache() -> &'static #cache_ty<#key, #table_ident> {
+                #init
+            }
+        }
+    });
+    let clear_getters = getters
+        .iter()
+        .map(|(cache, _)| quote!(ormx::cache::Cache::clear(Self::#cache());))
+        .collect::<Vec<_>>();
+    let init = static_cache(&quote!(#id_ty));
+
+    quote! {
+        impl #table_ident {
+            #( #getter_caches )*
+        }
+
+        impl ormx::cache::Cached for #table_ident {
+            fn cache() -> &'static dyn ormx::cache::Cache<#id_ty, Self> {
+                #init
+            }
+
+            fn invalidate(id: #id_ty) {
+                ormx::cache::Cache::remove(Self::cache(), &id);
+                #( #clear_getters )*
+            }
+
+            fn clear() {
+                ormx::cache::Cache::clear(Self::cache());
+                #( #clear_getters )*
+            }
+        }
+    }
+}
+
+/// the caches of the `get_one` and `get_optional` getters of a cached table, as the name of the
+/// function returning the cache and the type of its key
+pub fn cached_getters<B: Backend>(table: &Table<B>) -> Vec<(Ident, TokenStream)> {
+    if table.cache.is_none() {
+        return vec![];
     }
+    let getters = table.fields.iter().flat_map(|field| {
+        let getters = field.get_one.iter().chain(&field.get_optional);
+        getters.map(move |getter| (getter, field))
+    });
+    getters
+        .map(|(getter, field)| {
+            let (func, by_ty) = getter.or_fallback(field);
+            let cache = Ident::new(&format!("__ormx_cache_{}", func), Span::call_site());
+            let owned = match &by_ty {
+                Type::Reference(reference) => &*reference.elem,
+                by_ty => by_ty,
+            };
+            (cache, quote!(<#owned as ToOwned>::Owned))
+        })
+        .collect()
+}
+
+/// removes the row with the given id from the cache, if the table is cached
+pub fn invalidate_cache<B: Backend>(table: &Table<B>, id: TokenStream) -> Option<TokenStream> {
+    table
+        .cache
+        .as_ref()
+        .map(|_| quote!(<Self as ormx::cache::Cached>::invalidate(#id);))
+}
+
+pub fn impl_factory<B: Backend>(table: &Table<B>) -> TokenStream {
+    let insert_ident = match (&table.insertable, table.factory) {
+        (Some(insertable), true) => &insertable.ident,
+        _ => return quote!(),
+    };
+
+    let vis = &table.vis;
+    let table_ident = &table.ident;
+    let factory_ident = Ident::new(&format!("{}Factory", insert_ident), Span::call_site());
+    let fields = table.insertable_fields().collect::<Vec<_>>();
+    let field_idents = fields.iter().map(|field| &field.field).collect::<Vec<_>>();
+    let field_tys = fields.iter().map(|field| &field.ty).collect::<Vec<_>>();
+
+    // rows are inserted using the executor `db`, which is a connection acquired from the argument
+    // of `create`, or the pool which is ignored by the in-memory store
+    let (acquire, db) = match table.memory {
+        true => (
+            quote!(let _ = db; let db = ormx::memory::pool();),
+            quote!(&db),
+        ),
+        false => (quote!(let mut db = db.acquire().await?;), quote!(&mut *db)),
+    };
+    let values = fields.iter().map(|field| match &field.factory {
+        Some(FactoryValue::Expr(expr)) => quote!(#expr),
+        Some(FactoryValue::Parent(parent)) => quote! {{
+            let builder = <#parent as ormx::factory::Factory>::factory();
+            let parent = ormx::factory::Create::create(builder, #db).await?;
+            Into::into(ormx::Table::id(&parent))
+        }},
+        None => {
+            // spanned, so that a type not implementing `Generate` is reported at the field
+            let (name, ty) = (field.field.to_string(), &field.ty);
+            quote_spanned!(ty.span()=> <#ty as ormx::factory::Generate>::generate(#name, n))
+        }
+    });
+    let box_future = crate::utils::box_future();
+    let insert = match table.hooks {
+        true => quote!(insert.insert(#db)),
+        false => quote!(ormx::Insert::insert(insert, #db)),
+    };
+
+    quote! {
+        #vis struct #factory_ident {
+            #( #field_idents: Option<#field_tys>, )*
+            __sequence: u64,
+        }
+
+        impl #factory_ident {
+            #(
+                #vis fn #field_idents(mut self, value: #field_tys) -> Self {
+                    self.#field_idents = Some(value);
+                    self
+                }
+            )*
+        }
+
+        impl #insert_ident {
+            #vis fn factory() -> #factory_ident {
+                <#table_ident as ormx::factory::Factory>::factory()
+            }
+        }
+
+        impl ormx::factory::Factory for #table_ident {
+            type Builder = #factory_ident;
+
+            fn factory() -> #factory_ident {
+                static SEQUENCE: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+                #factory_ident {
+                    #( #field_idents: None, )*
+                    __sequence: SEQUENCE.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1,
+                }
+            }
+        }
+
+        impl ormx::factory::Create for #factory_ident {
+            type Table = #table_ident;
+
+            fn create<'__c, A>(
+                self,
+                db: A,
+            ) -> #box_future<'__c, sqlx::Result<#table_ident>>
+            where
+                A: sqlx::Acquire<'__c, Database = ormx::Db> + Send + '__c,
+            {
+                Box::pin(async move {
+                    #[allow(unused_variables)]
+                    let n = self.__sequence;
+                    #acquire
+                    let insert = #insert_ident {
+                        #(
+                            #field_idents: match self.#field_idents {
+                                Some(value) => value,
+                                None => #values,
+                            },
+                        )*
+                    };
+                    #insert.await
+                })
+            }
+        }
+    }
+}
+
+/// the actor logged into the audit table, if the table has one
+pub fn audit_actor<B: Backend>(table: &Table<B>) -> Option<TokenStream> {
+    table.audit.as_ref().map(|_| quote!(ormx::audit::actor()))
+}
+
+/// the path of the table struct, which can be used in expressions, e.g. `Item::<T>`
+pub fn table_path<B: Backend>(table: &Table<B>) -> TokenStream {
+    let table_ident = &table.ident;
+    let (_, ty_generics, _) = table.generics.split_for_impl();
+    let turbofish = ty_generics.as_turbofish();
+    quote!(#table_ident #turbofish)
+}
+
+/// Implements `ormx::Insert` for the insert struct, where `future` inserts `self` using `db`.
+/// If the table has hooks, the insert struct gets an inherent `insert` instead, which calls the
+/// hooks around `future`, see `ormx::Hooks`.
+pub fn impl_insert_method<B: Backend>(table: &Table<B>, future: TokenStream) -> TokenStream {
+    let insert_ident = match &table.insertable {
+        Some(insertable) => &insertable.ident,
+        None => return quote!(),
+    };
+    let table_ident = &table.ident;
+    let (impl_generics, ty_generics, where_clause) = table.generics.split_for_impl();
+    let box_future = crate::utils::box_future();
+
+    if !table.hooks {
+        return quote! {
+            impl #impl_generics ormx::Insert for #insert_ident #ty_generics #where_clause {
+                type Table = #table_ident #ty_generics;
+
+                fn insert<'__a, '__c: '__a>(
+                    self,
+                    db: impl sqlx::Executor<'__c, Database = ormx::Db> + '__a,
+                ) -> #box_future<'__a, sqlx::Result<Self::Table>>
+                where
+                    Self: '__a,
+                    Self::Table: '__a,
+                {
+                    Box::pin(#future)
+                }
+            }
+        };
+    }
+
+    let vis = &table.vis;
+    let db = quote!(ormx::Reborrow::reborrow(&mut db));
+    quote! {
+        impl #impl_generics #insert_ident #ty_generics #where_clause {
+            #[doc(hidden)]
+            fn __ormx_insert<'__a, '__c: '__a>(
+                self,
+                db: impl sqlx::Executor<'__c, Database = ormx::Db> + '__a,
+            ) -> #box_future<'__a, sqlx::Result<#table_ident #ty_generics>>
+            where
+                Self: '__a,
+                #table_ident #ty_generics: '__a,
+            {
+                Box::pin(#future)
+            }
+
+            #vis fn insert<'__a, '__c: '__a>(
+                mut self,
+                db: impl ormx::Reborrow<'__c> + '__a,
+            ) -> #box_future<'__a, sqlx::Result<#table_ident #ty_generics>>
+            where
+                Self: '__a,
+                #table_ident #ty_generics: '__a,
+            {
+                Box::pin(async move {
+                    let mut db = db;
+                    <#table_ident #ty_generics as ormx::Hooks>::before_insert(&mut self, #db).await?;
+                    let row = self.__ormx_insert(#db).await?;
+                    ormx::Hooks::after_insert(&row, #db).await?;
+                    Ok(row)
+                })
+            }
+        }
+    }
+}
+
+/// Generates the inherent methods of a table with hooks, which call the hooks around the queries
+/// of `update`, `patch` and, if the table is deletable, `delete_row`, see `ormx::Hooks`.
+pub fn impl_hooks<B: Backend>(table: &Table<B>) -> TokenStream {
+    if !table.hooks {
+        return quote!();
+    }
+
+    let vis = &table.vis;
+    let table_ident = &table.ident;
+    let (impl_generics, ty_generics, where_clause) = table.generics.split_for_impl();
+    let box_future = crate::utils::box_future();
+    let db = quote!(ormx::Reborrow::reborrow(&mut db));
+    let update = update_future::<B>(table);
+
+    let delete = table.deletable.then(|| {
+        let id_ty = &table.id.ty;
+        let delete = delete_future::<B>(table);
+        // the hooks receive the row as it is in the database, not the cached one
+        let uncache = table.cache.as_ref().map(
+            |_| quote!(ormx::cache::Cache::remove(<Self as ormx::cache::Cached>::cache(), &id);),
+        );
+        quote! {
+            #[doc(hidden)]
+            fn __ormx_delete_row<'__a, '__c: '__a>(
+                db: impl sqlx::Executor<'__c, Database = ormx::Db> + '__a,
+                id: #id_ty,
+            ) -> #box_future<'__a, sqlx::Result<()>>
+            where
+                Self: '__a,
+            {
+                Box::pin(#delete)
+            }
+
+            #vis fn delete_row<'__a, '__c: '__a>(
+                db: impl ormx::Reborrow<'__c> + '__a,
+                id: #id_ty,
+            ) -> #box_future<'__a, sqlx::Result<()>>
+            where
+                Self: '__a,
+            {
+                Box::pin(async move {
+                    let mut db = db;
+                    #uncache
+                    let row = <Self as ormx::Table>::get(#db, id).await?;
+                    ormx::Hooks::before_delete(&row, #db).await?;
+                    Self::__ormx_delete_row(#db, id).await?;
+                    ormx::Hooks::after_delete(&row, #db).await?;
+                    Ok(())
+                })
+            }
+
+            #vis fn delete<'__a, '__c: '__a>(
+                self,
+                db: impl ormx::Reborrow<'__c> + '__a,
+            ) -> #box_future<'__a, sqlx::Result<()>>
+            where
+                Self: '__a,
+            {
+                Self::delete_row(db, ormx::Table::id(&self))
+            }
+
+            #vis fn delete_ref<'__a, '__c: '__a>(
+                &self,
+                db: impl ormx::Reborrow<'__c> + '__a,
+            ) -> #box_future<'__a, sqlx::Result<()>>
+            where
+                Self: '__a,
+            {
+                Self::delete_row(db, ormx::Table::id(self))
+            }
+        }
+    });
+
+    quote! {
+        impl #impl_generics #table_ident #ty_generics #where_clause {
+            #[doc(hidden)]
+            fn __ormx_update<'__a, '__c: '__a>(
+                &'__a self,
+                db: impl sqlx::Executor<'__c, Database = ormx::Db> + '__a,
+            ) -> #box_future<'__a, sqlx::Result<()>> {
+                Box::pin(#update)
+            }
+
+            #vis fn update<'__a, '__c: '__a>(
+                mut self,
+                db: impl ormx::Reborrow<'__c> + '__a,
+            ) -> #box_future<'__a, sqlx::Result<Self>>
+            where
+                Self: '__a,
+            {
+                Box::pin(async move {
+                    let mut db = db;
+                    ormx::Hooks::before_update(&mut self, #db).await?;
+                    self.__ormx_update(#db).await?;
+                    ormx::Hooks::after_update(&self, #db).await?;
+                    Ok(self)
+                })
+            }
+
+            #vis fn patch<'__a, '__c: '__a, __P>(
+                &'__a mut self,
+                db: impl ormx::Reborrow<'__c> + '__a,
+                patch: __P,
+            ) -> #box_future<'__a, sqlx::Result<()>>
+            where
+                __P: ormx::PatchHooks<Table = Self> + '__a,
+            {
+                Box::pin(async move {
+                    let mut db = db;
+                    let mut patch: __P = patch;
+                    ormx::PatchHooks::before_patch(&mut patch, &*self, #db).await?;
+                    ormx::Patch::patch_row(&patch, #db, ormx::Table::id(self)).await?;
+                    ormx::Patch::apply_to(patch, self);
+                    ormx::Hooks::after_update(&*self, #db).await?;
+                    Ok(())
+                })
+            }
+
+            #delete
+        }
+    }
+}
+
+/// the body of a method of `ormx::Table` which can't be used on a table with hooks, since it can't
+/// pass the executor on to them
+fn unhooked<B: Backend>(table: &Table<B>, method: &str) -> TokenStream {
+    let message = format!(
+        "{} has #[ormx(hooks)], so the inherent method `{}` has to be used, which calls the hooks",
+        table.ident, method
+    );
+    quote!(Box::pin(async { Err(sqlx::Error::Configuration(#message.into())) }))
 }
 
 pub fn impl_delete<B: Backend>(table: &Table<B>) -> TokenStream {
-    if !table.deletable {
+    // tables with hooks get inherent methods instead, see `impl_hooks`
+    if !table.deletable || table.hooks {
         return quote!();
     }
 
     let table_ident = &table.ident;
+    let (impl_generics, ty_generics, where_clause) = table.generics.split_for_impl();
     let delete = delete::<B>(table);
 
     quote! {
-        impl ormx::Delete for #table_ident {
+        impl #impl_generics ormx::Delete for #table_ident #ty_generics #where_clause {
             #delete
         }
     }
 }
 
+pub fn impl_table_meta<B: Backend>(table: &Table<B>) -> TokenStream {
+    let table_ident = &table.ident;
+    let (impl_generics, ty_generics, where_clause) = table.generics.split_for_impl();
+    let table_name = &table.table;
+    let id_column = &table.id.column_name;
+    let select_columns = table.select_column_list();
+    let create_table = B::fmt_create_table(table);
+    let memory = match table.memory {
+        true => quote!(
+            const MEMORY: bool = true;
+        ),
+        false => quote!(),
+    };
+    let audit = table
+        .audit
+        .as_ref()
+        .map(|audit| quote!(const AUDIT: Option<&'static str> = Some(#audit);));
+    let tag = table
+        .tag
+        .as_ref()
+        .map(|tag| quote!(const TAG: Option<&'static str> = Some(#tag);));
+    let columns = table.columns().map(|column| {
+        let name = &column.column_name;
+        let field = column
+            .path
+            .iter()
+            .chain(std::iter::once(&column.field))
+            .join(".");
+        let alias = column.alias().to_string();
+        let default = column.default;
+        let custom_type = column.custom_type;
+        let json = column.json;
+        let nullable = column.nullable();
+        let sql_type = match super::sql_type(column) {
+            Some(sql_type) => quote!(Some(#sql_type)),
+            None => quote!(None),
+        };
+        quote! {
+            ormx::ColumnMeta {
+                name: #name,
+                field: #field,
+                alias: #alias,
+                sql_type: #sql_type,
+                nullable: #nullable,
+                default: #default,
+                custom_type: #custom_type,
+                json: #json,
+            }
+        }
+    });
+
+    let columns_ident = Ident::new(&format!("{}Columns", table_ident), Span::call_site());
+    let mut columns_structs = TokenStream::new();
+    let construct_columns = columns_struct(
+        table,
+        &columns_ident,
+        &table.fields,
+        false,
+        &mut columns_structs,
+    );
+
+    let mut index = 0;
+    let construct_row = super::construct_from_row(&table.fields, &mut |field| {
+        index += 1;
+        field.fmt_from_row_index(&quote!(row), index - 1)
+    });
+
+    quote! {
+        #columns_structs
+
+        impl #impl_generics ormx::TableMeta for #table_ident #ty_generics #where_clause {
+            const TABLE: &'static str = #table_name;
+            const ID_COLUMN: &'static str = #id_column;
+            const COLUMNS: &'static [ormx::ColumnMeta] = &[ #( #columns, )* ];
+            const SELECT_COLUMNS: &'static str = #select_columns;
+            #memory
+            #audit
+            #tag
+
+            type Columns = #columns_ident #ty_generics;
+
+            fn columns() -> Self::Columns {
+                #construct_columns
+            }
+
+            fn from_row(row: &<ormx::Db as sqlx::Database>::Row) -> sqlx::Result<Self> {
+                Ok(Self {
+                    #construct_row
+                })
+            }
+
+            fn create_table_sql() -> &'static str {
+                #create_table
+            }
+        }
+    }
+}
+
+/// Generates a struct named `ident` containing an `ormx::Column` for each of the `fields`, and
+/// nested structs for embedded structs. Returns an expression constructing the struct.
+fn columns_struct<B: Backend>(
+    table: &Table<B>,
+    ident: &Ident,
+    fields: &[TableField<B>],
+    nested: bool,
+    structs: &mut TokenStream,
+) -> TokenStream {
+    let vis = &table.vis;
+    let table_ident = &table.ident;
+    let generics = &table.generics;
+    let (_, ty_generics, where_clause) = generics.split_for_impl();
+
+    let mut definitions = vec![];
+    let mut values = vec![];
+    for field in fields.iter().filter(|field| !field.marker) {
+        let field_ident = &field.field;
+        let embedded = field.flatten.as_ref().and_then(|f| f.fields.as_ref());
+        match embedded {
+            Some(embedded) => {
+                let alias = field.alias();
+                let nested = Ident::new(
+                    &format!("__Ormx{}Columns__{}", table_ident, alias),
+                    Span::call_site(),
+                );
+                let value = columns_struct(table, &nested, embedded, true, structs);
+                definitions.push(quote!(#vis #field_ident: #nested #ty_generics));
+                values.push(quote!(#field_ident: #value));
+            }
+            None => {
+                let ty = field.row_ty();
+                let column = field.column();
+                definitions.push(quote! {
+                    #vis #field_ident: ormx::Column<#table_ident #ty_generics, #ty>
+                });
+                values.push(quote!(#field_ident: ormx::Column::new(#column)));
+            }
+        }
+    }
+
+    let hidden = nested.then(|| quote!(#[doc(hidden)] #[allow(non_camel_case_types)]));
+    structs.extend(quote! {
+        #hidden
+        #[allow(dead_code)]
+        #vis struct #ident #generics #where_clause {
+            #( #definitions, )*
+        }
+    });
+
+    quote!(#ident { #( #values, )* })
+}
+
 fn get<B: Backend>(table: &Table<B>, column_list: &str) -> TokenStream {
     let box_future = crate::utils::box_future();
     let get_sql = format!(
@@ -55,52 +766,236 @@ fn get<B: Backend>(table: &Table<B>, column_list: &str) -> TokenStream {
         table.id.column(),
         B::Bindings::default().next().unwrap()
     );
+    let get_sql = table.tag_sql("get", get_sql);
+
+    let row = table.row_ident();
+    let row_ty = super::row_ty(row.as_ref());
+    let convert = row.map(|_| quote!(.map(Self::from)));
+    let query = query_as(table.unchecked, &row_ty, &get_sql, Some(quote!(id)));
+    let query = match table.memory {
+        true => memory::get(),
+        false => quote! {
+            #query
+                .fetch_one(db)
+                .await
+                #convert
+        },
+    };
+
+    let fetch = match table.cache {
+        Some(_) => quote! {
+            let cache = <Self as ormx::cache::Cached>::cache();
+            if let Some(row) = ormx::cache::Cache::get(cache, &id) {
+                return Ok(row);
+            }
+            let row = #query?;
+            ormx::cache::Cache::insert(cache, id, Clone::clone(&row));
+            Ok(row)
+        },
+        None => query,
+    };
+    let future = observe_read(
+        &table.table,
+        "get",
+        Some(quote!(id)),
+        quote!(id),
+        quote!(async move { #fetch }),
+    );
 
     quote! {
-        fn get<'a, 'c: 'a>(
-            db: impl sqlx::Executor<'c, Database = ormx::Db> + 'a,
+        #[track_caller]
+        fn get<'__a, '__c: '__a>(
+            db: impl sqlx::Executor<'__c, Database = ormx::Db> + '__a,
             id: Self::Id,
-        ) -> #box_future<'a, sqlx::Result<Self>> {
+        ) -> #box_future<'__a, sqlx::Result<Self>>
+        where
+            Self: '__a,
+        {
+            Box::pin(#future)
+        }
+    }
+}
+
+/// `GetByIds::get_by_ids`, using `IN (..)` with a binding for each ID
+pub fn get_by_ids<B: Backend>(table: &Table<B>) -> TokenStream {
+    let box_future = crate::utils::box_future();
+    let sql = format!(
+        "SELECT {} FROM {} WHERE {} IN (",
+        table.select_column_list(),
+        table.table,
+        table.id.column()
+    );
+    let sql = table.tag_sql("get_by_ids", sql);
+    let future = observe(
+        &table.table,
+        "get_by_ids",
+        None,
+        quote! {
+            async move {
+                if ids.is_empty() {
+                    return Ok(vec![]);
+                }
+                let mut query = sqlx::QueryBuilder::new(#sql);
+                let mut bindings = query.separated(", ");
+                for id in ids {
+                    bindings.push_bind(*id);
+                }
+                query.push(")");
+                let arguments = ormx::testing::arguments(|| {
+                    #[allow(unused_imports)]
+                    use ormx::testing::{DebugArgument as _, NoDebug as _};
+                    ids.iter().map(|id| (&ormx::testing::Argument(id)).debug()).collect()
+                });
+                let query = ormx::testing::with_arguments(arguments, query.build());
+                let rows = query.fetch_all(db).await?;
+                rows.iter().map(<Self as ormx::TableMeta>::from_row).collect()
+            }
+        },
+    );
+
+    quote! {
+        fn get_by_ids<'__a, '__c: '__a>(
+            db: impl sqlx::Executor<'__c, Database = ormx::Db> + '__a,
+            ids: &'__a [Self::Id],
+        ) -> #box_future<'__a, sqlx::Result<Vec<Self>>>
+        where
+            Self: '__a,
+        {
+            Box::pin(#future)
+        }
+    }
+}
+
+/// overrides `Table::reload` if the table is cached, so that the row is queried from the database
+fn reload<B: Backend>(table: &Table<B>) -> TokenStream {
+    if table.cache.is_none() {
+        return quote!();
+    }
+
+    let box_future = crate::utils::box_future();
+    quote! {
+        fn reload<'__a, '__c: '__a>(
+            &'__a mut self,
+            db: impl sqlx::Executor<'__c, Database = ormx::Db> + '__a,
+        ) -> #box_future<'__a, sqlx::Result<()>> {
             Box::pin(async move {
-                sqlx::query_as!(Self, #get_sql, id)
-                    .fetch_one(db)
-                    .await
+                let id = ormx::Table::id(self);
+                ormx::cache::Cache::remove(<Self as ormx::cache::Cached>::cache(), &id);
+                *self = <Self as ormx::Table>::get(db, id).await?;
+                Ok(())
             })
         }
     }
 }
 
+/// `Table::update`, or, if the table has hooks, a method returning an error
 fn update<B: Backend>(table: &Table<B>) -> TokenStream {
     let box_future = crate::utils::box_future();
+    let body = match table.hooks {
+        true => unhooked(table, "update"),
+        false => {
+            let future = update_future(table);
+            quote!(Box::pin(#future))
+        }
+    };
+    quote! {
+        fn update<'__a, '__c: '__a>(
+            &'__a self,
+            db: impl sqlx::Executor<'__c, Database = ormx::Db> + '__a,
+        ) -> #box_future<'__a, sqlx::Result<()>> {
+            #body
+        }
+    }
+}
+
+/// the future updating all columns of `self`
+fn update_future<B: Backend>(table: &Table<B>) -> TokenStream {
     let mut bindings = B::Bindings::default();
     let mut assignments = vec![];
-    for field in table.fields_except_id() {
+    for field in table.fields_except_id().flat_map(TableField::columns) {
         let fragment = format!("{} = {}", field.column(), bindings.next().unwrap());
         assignments.push(fragment);
     }
     let assignments = assignments.join(", ");
 
-    let update_sql = format!(
-        "UPDATE {} SET {} WHERE {} = {}",
-        table.table,
-        assignments,
-        table.id.column(),
-        bindings.next().unwrap()
-    );
+    let id_binding = bindings.next().unwrap();
+    let update_sql = match &table.audit {
+        Some(audit_table) => Audit {
+            audit_table,
+            table: &table.table,
+            id: table.id.audit_column(),
+            id_binding: id_binding.into(),
+            actor_binding: bindings.next().unwrap().into(),
+        }
+        .fmt_update(
+            &assignments,
+            &table
+                .fields_except_id()
+                .flat_map(TableField::columns)
+                .map(TableField::audit_column)
+                .collect::<Vec<_>>(),
+        ),
+        None => format!(
+            "UPDATE {} SET {} WHERE {} = {}",
+            table.table,
+            assignments,
+            table.id.column(),
+            id_binding
+        ),
+    };
+    let update_sql = table.tag_sql("update", update_sql);
     let id_argument = &table.id.field;
-    let other_arguments = table.fields_except_id().map(TableField::fmt_as_argument);
+    let arguments = table
+        .fields_except_id()
+        .flat_map(TableField::columns)
+        .map(|field| field.fmt_as_argument(table.unchecked))
+        .chain(Some(quote!(self.#id_argument)))
+        .chain(audit_actor(table));
+    let query = match table.memory {
+        true => memory::update(&quote!(self.#id_argument)),
+        false if table.audit.is_some() => audited_update(table.unchecked, &update_sql, arguments),
+        false => {
+            let query = query(table.unchecked, &update_sql, arguments);
+            quote!(#query.execute(db).await?.rows_affected())
+        }
+    };
+    let validate = table
+        .validate
+        .then(|| quote!(<Self as ormx::Validate>::validate(self)?;));
+    let invalidate = invalidate_cache(table, quote!(self.#id_argument));
+    observe_modifying(
+        &table.table,
+        "update",
+        Some(quote!(self.#id_argument)),
+        quote! {
+            async move {
+                #validate
+                let modified = #query;
+                #invalidate
+                Ok(ormx::observe::Modified(modified))
+            }
+        },
+    )
+}
+
+/// overrides `Table::patch` with a method returning an error if the table has hooks
+fn patch<B: Backend>(table: &Table<B>) -> TokenStream {
+    if !table.hooks {
+        return quote!();
+    }
+    let body = unhooked(table, "patch");
 
+    let box_future = crate::utils::box_future();
     quote! {
-        fn update<'a, 'c: 'a>(
-            &'a self,
-            db: impl sqlx::Executor<'c, Database = ormx::Db> + 'a,
-        ) -> #box_future<'a, sqlx::Result<()>> {
-            Box::pin(async move {
-                sqlx::query!(#update_sql, #( #other_arguments, )* self.#id_argument)
-                    .execute(db)
-                    .await?;
-                Ok(())
-            })
+        fn patch<'__a, '__c: '__a, __P>(
+            &'__a mut self,
+            db: impl sqlx::Executor<'__c, Database = ormx::Db> + '__a,
+            patch: __P,
+        ) -> #box_future<'__a, sqlx::Result<()>>
+        where
+            __P: ormx::Patch<Table = Self> + '__a,
+        {
+            #body
         }
     }
 }
@@ -108,13 +1003,24 @@ fn update<B: Backend>(table: &Table<B>) -> TokenStream {
 fn stream_all<B: Backend>(table: &Table<B>, column_list: &str) -> TokenStream {
     let box_stream = crate::utils::box_stream();
     let all_sql = format!("SELECT {} FROM {}", column_list, table.table);
+    let all_sql = table.tag_sql("stream_all", all_sql);
+    let row_ty = super::row_ty(table.row_ident().as_ref());
+
+    let query = query_as(table.unchecked, &row_ty, &all_sql, None);
+    let stream = match table.memory {
+        true => memory::stream_all(false),
+        false => convert_stream(table, quote!(#query.fetch(db))),
+    };
+    let stream = observe_stream(&table.table, "stream_all", stream);
 
     quote! {
-        fn stream_all<'a, 'c: 'a>(
-            db: impl sqlx::Executor<'c, Database = ormx::Db> + 'a,
-        ) -> #box_stream<'a, sqlx::Result<Self>> {
-            sqlx::query_as!(Self, #all_sql)
-                .fetch(db)
+        fn stream_all<'__a, '__c: '__a>(
+            db: impl sqlx::Executor<'__c, Database = ormx::Db> + '__a,
+        ) -> #box_stream<'__a, sqlx::Result<Self>>
+        where
+            Self: '__a,
+        {
+            #stream
         }
     }
 }
@@ -129,28 +1035,45 @@ fn stream_all_paginated<B: Backend>(table: &Table<B>, column_list: &str) -> Toke
         bindings.next().unwrap(),
         bindings.next().unwrap()
     );
+    let all_sql = table.tag_sql("stream_all_paginated", all_sql);
+
+    let row_ty = super::row_ty(table.row_ident().as_ref());
+    let args = vec![quote!(limit), quote!(offset)];
+    let query = query_as(table.unchecked, &row_ty, &all_sql, args);
+    let stream = match table.memory {
+        true => memory::stream_all(true),
+        false => convert_stream(table, quote!(#query.fetch(db))),
+    };
+    let stream = observe_stream(&table.table, "stream_all_paginated", stream);
 
     quote! {
-        fn stream_all_paginated<'a, 'c: 'a>(
-            db: impl sqlx::Executor<'c, Database = ormx::Db> + 'a,
+        fn stream_all_paginated<'__a, '__c: '__a>(
+            db: impl sqlx::Executor<'__c, Database = ormx::Db> + '__a,
             offset: i64,
             limit: i64,
-        ) -> #box_stream<'a, sqlx::Result<Self>> {
-            sqlx::query_as!(Self, #all_sql, limit, offset)
-                .fetch(db)
+        ) -> #box_stream<'__a, sqlx::Result<Self>>
+        where
+            Self: '__a,
+        {
+            #stream
         }
     }
 }
 
+/// converts a stream of rows into a stream of `Self` if the table contains embedded structs
+fn convert_stream<B: Backend>(table: &Table<B>, stream: TokenStream) -> TokenStream {
+    match table.row_ident() {
+        Some(_) => quote! {
+            Box::pin(ormx::exports::futures::TryStreamExt::map_ok(#stream, Self::from))
+        },
+        None => stream,
+    }
+}
+
 fn delete<B: Backend>(table: &Table<B>) -> TokenStream {
     let box_future = crate::utils::box_future();
     let id_ty = &table.id.ty;
-    let delete_sql = format!(
-        "DELETE FROM {} WHERE {} = {}",
-        table.table,
-        table.id.column(),
-        B::Bindings::default().next().unwrap()
-    );
+    let future = delete_future(table);
     #[cfg(feature = "mysql")]
     let result_import = quote!(sqlx::mysql::MySqlQueryResult);
     #[cfg(feature = "postgres")]
@@ -159,22 +1082,66 @@ fn delete<B: Backend>(table: &Table<B>) -> TokenStream {
     let result_import = quote!(sqlx::sqlite::SqliteQueryResult);
 
     quote! {
-        fn delete_row<'a, 'c: 'a>(
-            db: impl sqlx::Executor<'c, Database = ormx::Db> + 'a,
+        fn delete_row<'__a, '__c: '__a>(
+            db: impl sqlx::Executor<'__c, Database = ormx::Db> + '__a,
             id: #id_ty
-        ) -> #box_future<'a, sqlx::Result<()>> {
+        ) -> #box_future<'__a, sqlx::Result<()>>
+        where
+            Self: '__a,
+        {
             use #result_import;
 
-            Box::pin(async move {
-                let result = sqlx::query!(#delete_sql, id)
-                    .execute(db)
-                    .await?;
-                if result.rows_affected() == 0 {
-                    Err(sqlx::Error::RowNotFound)
-                } else {
-                    Ok(())
-                }
-            })
+            Box::pin(#future)
         }
     }
 }
+
+/// the future deleting the row of `id`
+fn delete_future<B: Backend>(table: &Table<B>) -> TokenStream {
+    let mut bindings = B::Bindings::default();
+    let id_binding = bindings.next().unwrap();
+    let delete_sql = match &table.audit {
+        Some(audit_table) => Audit {
+            audit_table,
+            table: &table.table,
+            id: table.id.audit_column(),
+            id_binding: id_binding.into(),
+            actor_binding: bindings.next().unwrap().into(),
+        }
+        .fmt_delete(
+            &table
+                .columns()
+                .map(TableField::audit_column)
+                .collect::<Vec<_>>(),
+        ),
+        None => format!(
+            "DELETE FROM {} WHERE {} = {}",
+            table.table,
+            table.id.column(),
+            id_binding
+        ),
+    };
+    let delete_sql = table.tag_sql("delete_row", delete_sql);
+    let arguments = Some(quote!(id)).into_iter().chain(audit_actor(table));
+    let query = query(table.unchecked, &delete_sql, arguments);
+    let deleted = match table.memory {
+        true => memory::delete(),
+        false => quote!(#query.execute(db).await?.rows_affected()),
+    };
+    let invalidate = invalidate_cache(table, quote!(id));
+    observe_modifying(
+        &table.table,
+        "delete_row",
+        Some(quote!(id)),
+        quote! {
+            async move {
+                let deleted = #deleted;
+                #invalidate
+                if deleted == 0 {
+                    return Err(sqlx::Error::RowNotFound);
+                }
+                Ok(ormx::observe::Modified(deleted))
+            }
+        },
+    )
+}
@@ -0,0 +1,161 @@
+//! For tables and patches with `#[ormx(memory)]`, the generated methods read and write rows from
+//! `ormx::memory::Store` instead of querying the database. The functions in this module generate
+//! the code replacing the queries, while hooks, validation and caching are generated as usual.
+
+use proc_macro2::TokenStream;
+use quote::{quote, quote_spanned};
+use syn::{spanned::Spanned, Ident, Type};
+
+use crate::{
+    backend::{
+        common::{impl_insert_method, table_path},
+        Backend,
+    },
+    table::{Table, TableField},
+    utils::observe,
+};
+
+fn store() -> TokenStream {
+    quote!(ormx::memory::Store::current())
+}
+
+/// `sqlx::Result<Self>` of the row with the ID `id`
+pub fn get() -> TokenStream {
+    let store = store();
+    quote!(#store.get::<Self>(id))
+}
+
+/// `GetByIds::get_by_ids`
+pub fn get_by_ids(table: &str) -> TokenStream {
+    let box_future = crate::utils::box_future();
+    let store = store();
+    let future = observe(
+        table,
+        "get_by_ids",
+        None,
+        quote! {
+            async move {
+                Ok(#store.filter::<Self>(|row| ids.contains(&ormx::Table::id(row))))
+            }
+        },
+    );
+    quote! {
+        fn get_by_ids<'__a, '__c: '__a>(
+            db: impl sqlx::Executor<'__c, Database = ormx::Db> + '__a,
+            ids: &'__a [Self::Id],
+        ) -> #box_future<'__a, sqlx::Result<Vec<Self>>>
+        where
+            Self: '__a,
+        {
+            Box::pin(#future)
+        }
+    }
+}
+
+/// the stream returned by `Table::stream_all` and, given `offset` and `limit`,
+/// `Table::stream_all_paginated`
+pub fn stream_all(paginated: bool) -> TokenStream {
+    let store = store();
+    let paginate = paginated.then(|| quote!(.skip(offset as usize).take(limit as usize)));
+    quote! {{
+        let rows = #store.rows::<Self>();
+        Box::pin(ormx::exports::futures::stream::iter(
+            rows.into_iter() #paginate .map(Ok),
+        ))
+    }}
+}
+
+/// `u64` of the rows modified by replacing the row of `id` with `self`
+pub fn update(id: &TokenStream) -> TokenStream {
+    let store = store();
+    quote!(u64::from(#store.update::<Self>(#id, |row| *row = Clone::clone(self))))
+}
+
+/// `u64` of the rows modified by setting the field of the row of `id` to `value`
+pub fn set(field: &Ident, id: &TokenStream) -> TokenStream {
+    let store = store();
+    quote!(u64::from(#store.update::<Self>(#id, |row| row.#field = Clone::clone(&value))))
+}
+
+/// `u64` of the rows modified by applying the fields of the patch `self` to the row of `id`
+pub fn patch(table: &TokenStream, fields: &[&Ident]) -> TokenStream {
+    let store = store();
+    quote! {
+        u64::from(#store.update::<#table>(id, |row| {
+            #( row.#fields = Clone::clone(&self.#fields); )*
+        }))
+    }
+}
+
+/// `u64` of the rows deleted by deleting the row of `id`
+pub fn delete() -> TokenStream {
+    let store = store();
+    quote!(u64::from(#store.delete::<Self>(id)))
+}
+
+/// `Vec<Self>` of the rows whose field equals the argument `by` of a getter
+pub fn find<B: Backend>(field: &TableField<B>, by_ty: &Type) -> TokenStream {
+    let store = store();
+    let ident = &field.field;
+    let filter = match (by_ty, field.cow_inner(), field.nullable()) {
+        (_, Some(_), false) => quote!(by == &*row.#ident),
+        (_, Some(_), true) => quote!(by.as_deref() == row.#ident.as_deref()),
+        (Type::Reference(_), None, _) => quote!(by == &row.#ident),
+        (_, None, _) => quote!(by == row.#ident),
+    };
+    quote!(#store.filter::<Self>(|row| #filter))
+}
+
+/// `sqlx::Result<Vec<Self>>` of the rows whose JSON field contains the argument `by` of a
+/// `get_many_contains` getter
+pub fn find_contains<B: Backend>(field: &TableField<B>) -> TokenStream {
+    let store = store();
+    let ident = &field.field;
+    quote! {
+        sqlx::Result::Ok(#store.filter::<Self>(|row| ormx::memory::json_contains(&row.#ident, &by)))
+    }
+}
+
+pub fn impl_insert<B: Backend>(table: &Table<B>) -> TokenStream {
+    if table.insertable.is_none() {
+        return quote!();
+    }
+
+    let row_path = table_path(table);
+    let insert_fields = table.insertable_fields().map(|field| &field.field);
+    let default_fields = table.default_fields().map(|field| {
+        let ident = &field.field;
+        let ty = &field.ty;
+        match field.field == table.id.field {
+            true => {
+                // reports IDs which aren't integers at the type of the field
+                let id = quote_spanned!(ty.span()=> <#ty as ormx::memory::GeneratedId>);
+                quote!(#ident: #id::from_sequence(store.next_id::<#row_path>()))
+            }
+            false => quote!(#ident: Default::default()),
+        }
+    });
+
+    let validate = table
+        .validate
+        .then(|| quote!(ormx::Validate::validate(&self)?;));
+    let store = store();
+    let future = observe(
+        &table.table,
+        "insert",
+        None,
+        quote! {
+            async move {
+                #validate
+                let store = #store;
+                let row = #row_path {
+                    #( #insert_fields: self.#insert_fields, )*
+                    #( #default_fields, )*
+                };
+                store.insert(Clone::clone(&row))?;
+                Ok(row)
+            }
+        },
+    );
+    impl_insert_method(table, future)
+}
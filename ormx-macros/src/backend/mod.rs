@@ -5,6 +5,9 @@ use proc_macro2::TokenStream;
 use crate::{patch::Patch, table::Table};
 
 mod common;
+mod memory;
+
+pub use common::{Audit, AuditColumn};
 #[cfg(feature = "mysql")]
 mod mysql;
 #[cfg(feature = "postgres")]
@@ -24,6 +27,24 @@ pub trait Backend: Sized + Clone {
 
     type Bindings: Iterator<Item = Cow<'static, str>> + Default;
 
+    /// Format a condition checking if the JSON document in `column` contains `value`
+    fn fmt_json_contains(column: &str, value: &str) -> String;
+
+    /// SQL type of JSON columns
+    const JSON_TYPE: &'static str;
+
+    /// Returns the SQL type corresponding to the rust type with the given name, if it is known.
+    /// `arg` is the generic argument of the type, e.g. `u8` for `Vec<u8>`.
+    fn sql_type(name: &str, arg: Option<&str>) -> Option<String>;
+
+    /// Returns the SQL type of an ID column generated by the database, e.g. `SERIAL`
+    fn serial_type(sql_type: &str) -> String;
+
+    /// Format a `CREATE TABLE` statement for the table
+    fn fmt_create_table(table: &Table<Self>) -> String {
+        common::create_table::<Self>(table)
+    }
+
     /// Generate an `impl <Table>` block, containing getter methods
     fn impl_getters(table: &Table<Self>) -> TokenStream {
         common::getters::<Self>(table)
@@ -39,19 +60,59 @@ pub trait Backend: Sized + Clone {
         common::impl_table::<Self>(table)
     }
 
+    /// Generate `GetByIds::get_by_ids`, querying multiple rows by their ID
+    fn get_by_ids(table: &Table<Self>) -> TokenStream {
+        common::get_by_ids(table)
+    }
+
+    /// Generate an `impl TableMeta for <Table>` block
+    fn impl_table_meta(table: &Table<Self>) -> TokenStream {
+        common::impl_table_meta::<Self>(table)
+    }
+
     /// Generate an `impl Delete for <Table>` block
     fn impl_delete(table: &Table<Self>) -> TokenStream {
         common::impl_delete::<Self>(table)
     }
 
+    /// Generate the inherent methods of a table with hooks, which call `ormx::Hooks`
+    fn impl_hooks(table: &Table<Self>) -> TokenStream {
+        common::impl_hooks(table)
+    }
+
     /// Implement [Insert] for the helper struct for inserting
     fn impl_insert(table: &Table<Self>) -> TokenStream;
 
+    /// Implement `ormx::Validate` for the table and its insert struct
+    fn impl_validate(table: &Table<Self>) -> TokenStream {
+        common::impl_validate(table)
+    }
+
+    /// Implement `ormx::Track` for the table
+    fn impl_track(table: &Table<Self>) -> TokenStream {
+        common::impl_track(table)
+    }
+
+    /// Implement `ormx::cache::Cached` for the table
+    fn impl_cached(table: &Table<Self>) -> TokenStream {
+        common::impl_cached(table)
+    }
+
+    /// Generate the builder of rows used by `ormx::factory::Factory`
+    fn impl_factory(table: &Table<Self>) -> TokenStream {
+        common::impl_factory(table)
+    }
+
     /// Generate a helper struct for inserting
     fn insert_struct(table: &Table<Self>) -> TokenStream {
         common::insert_struct(table)
     }
 
+    /// Generate a flat struct for querying tables with embedded structs
+    fn row_struct(table: &Table<Self>) -> TokenStream {
+        common::row_struct(table)
+    }
+
     /// Implement [Patch]
     fn impl_patch(patch: &Patch) -> TokenStream {
         common::impl_patch::<Self>(patch)
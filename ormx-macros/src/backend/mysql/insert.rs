@@ -1,46 +1,47 @@
 use itertools::Itertools;
-use proc_macro2::TokenStream;
+use proc_macro2::{Span, TokenStream};
 use quote::quote;
+use syn::Ident;
 
 use super::MySqlBackend;
 use crate::{
-    backend::mysql::MySqlBindings,
+    backend::{
+        common::{impl_insert_method, table_path},
+        mysql::MySqlBindings,
+    },
     table::{Table, TableField},
+    utils::{observe, query},
 };
 
 pub fn impl_insert(table: &Table<MySqlBackend>) -> TokenStream {
-    let insert_ident = match &table.insertable {
-        Some(i) => &i.ident,
-        None => return quote!(),
-    };
-
-    let table_ident = &table.ident;
-    let box_future = quote!(ormx::exports::futures::future::BoxFuture);
+    if table.insertable.is_none() {
+        return quote!();
+    }
 
     let insert = insert(&table);
     let query_id = query_id(&table);
     let query_default = query_default(&table);
     let construct_row = construct_row(&table);
-
-    quote! {
-        impl ormx::Insert for #insert_ident {
-            type Table = #table_ident;
-
-            fn insert<'a, 'c: 'a>(
-                self,
-                db: impl sqlx::Executor<'c, Database = ormx::Db> + 'a,
-            ) -> #box_future<'a, sqlx::Result<Self::Table>> {
-                Box::pin(async move {
-                    let mut tx = db.begin().await?;
-                    #insert
-                    #query_id
-                    #query_default
-                    tx.commit().await?;
-                    Ok(#construct_row)
-                })
+    let validate = table
+        .validate
+        .then(|| quote!(ormx::Validate::validate(&self)?;));
+    let future = observe(
+        &table.table,
+        "insert",
+        None,
+        quote! {
+            async move {
+                #validate
+                let mut tx = db.begin().await?;
+                #insert
+                #query_id
+                #query_default
+                tx.commit().await?;
+                Ok(#construct_row)
             }
-        }
-    }
+        },
+    );
+    impl_insert_method(table, future)
 }
 
 /// build an instance of the table struct from
@@ -53,16 +54,24 @@ fn construct_row(table: &Table<MySqlBackend>) -> TokenStream {
         .insertable_fields()
         .map(|f| &f.field)
         .filter(|f| *f != id_ident);
-    let default_field_idents = table
+    let generated = Ident::new("_generated", Span::call_site());
+    let default_fields = table
         .default_fields()
-        .map(|f| &f.field)
-        .filter(|f| *f != id_ident);
-
+        .filter(|f| f.field != *id_ident);
+    let default_field_idents = default_fields.clone().map(|f| &f.field);
+    let default_field_values = default_fields
+        .enumerate()
+        .map(|(index, f)| match table.unchecked {
+            true => f.fmt_from_row_index(&quote!(&_generated), index),
+            false => f.fmt_from_row(&generated),
+        });
+
+    let row_path = table_path(table);
     quote! {
-        Self::Table {
+        #row_path {
             #id_ident: _id as _,
             #( #insert_field_idents: self.#insert_field_idents, )*
-            #( #default_field_idents: _generated.#default_field_idents, )*
+            #( #default_field_idents: #default_field_values, )*
         }
     }
 }
@@ -84,9 +93,11 @@ fn query_default(table: &Table<MySqlBackend>) -> TokenStream {
         table.table,
         table.id.column()
     );
+    let query_default_sql = table.tag_sql("insert", query_default_sql);
+    let query = query(table.unchecked, &query_default_sql, Some(quote!(_id)));
 
     quote! {
-        let _generated = sqlx::query!(#query_default_sql, _id)
+        let _generated = #query
             .fetch_one(&mut tx)
             .await?;
     }
@@ -94,8 +105,13 @@ fn query_default(table: &Table<MySqlBackend>) -> TokenStream {
 
 /// inserts the struct into the database
 fn insert(table: &Table<MySqlBackend>) -> TokenStream {
-    let insert_fields: Vec<_> = table.insertable_fields().collect();
-    let insert_field_idents = insert_fields.iter().map(|field| &field.field);
+    let insert_fields: Vec<_> = table
+        .insertable_fields()
+        .flat_map(TableField::columns)
+        .collect();
+    let insert_field_exprs = insert_fields
+        .iter()
+        .map(|field| field.fmt_as_argument(table.unchecked));
 
     let insert_sql = format!(
         "INSERT INTO {} ({}) VALUES ({})",
@@ -103,9 +119,12 @@ fn insert(table: &Table<MySqlBackend>) -> TokenStream {
         insert_fields.iter().map(|field| field.column()).join(", "),
         MySqlBindings.take(insert_fields.len()).join(", ")
     );
+    let insert_sql = table.tag_sql("insert", insert_sql);
+
+    let query = query(table.unchecked, &insert_sql, insert_field_exprs);
 
     quote! {
-        sqlx::query!(#insert_sql, #( self.#insert_field_idents, )*)
+        #query
             .execute(&mut tx)
             .await?;
     }
@@ -119,6 +138,11 @@ fn insert(table: &Table<MySqlBackend>) -> TokenStream {
 ///     The ID is already known, so we can just use it.
 fn query_id(table: &Table<MySqlBackend>) -> TokenStream {
     match table.id.default {
+        true if table.unchecked => quote! {
+            let _id: u64 = sqlx::query_scalar("SELECT LAST_INSERT_ID() AS id")
+                .fetch_one(&mut tx)
+                .await?;
+        },
         true => quote! {
             let _id = sqlx::query!("SELECT LAST_INSERT_ID() AS id")
                 .fetch_one(&mut tx)
@@ -2,7 +2,10 @@ use std::borrow::Cow;
 
 use proc_macro2::TokenStream;
 
-use crate::{backend::Backend, table::Table};
+use crate::{
+    backend::{memory, Backend},
+    table::Table,
+};
 
 mod insert;
 
@@ -14,8 +17,25 @@ impl Backend for MySqlBackend {
     const RESERVED_IDENTS: &'static [&'static str] = &[];
     type Bindings = MySqlBindings;
 
+    fn fmt_json_contains(column: &str, value: &str) -> String {
+        format!("JSON_CONTAINS({}, {})", column, value)
+    }
+
+    const JSON_TYPE: &'static str = ormx_types::mysql::JSON_TYPE;
+
+    fn sql_type(name: &str, arg: Option<&str>) -> Option<String> {
+        ormx_types::mysql::sql_type(name, arg)
+    }
+
+    fn serial_type(sql_type: &str) -> String {
+        ormx_types::mysql::serial_type(sql_type)
+    }
+
     fn impl_insert(table: &Table<Self>) -> TokenStream {
-        insert::impl_insert(table)
+        match table.memory {
+            true => memory::impl_insert(table),
+            false => insert::impl_insert(table),
+        }
     }
 }
 
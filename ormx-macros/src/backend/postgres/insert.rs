@@ -4,8 +4,12 @@ use quote::quote;
 use syn::Ident;
 
 use crate::{
-    backend::postgres::{PgBackend, PgBindings},
+    backend::{
+        common::{impl_insert_method, table_path},
+        postgres::{PgBackend, PgBindings},
+    },
     table::{Table, TableField},
+    utils::{observe, query},
 };
 
 fn insert_sql(table: &Table<PgBackend>, insert_fields: &[&TableField<PgBackend>]) -> String {
@@ -30,15 +34,17 @@ fn insert_sql(table: &Table<PgBackend>, insert_fields: &[&TableField<PgBackend>]
 }
 
 pub fn impl_insert(table: &Table<PgBackend>) -> TokenStream {
-    let insert_ident = match &table.insertable {
-        Some(i) => &i.ident,
-        None => return quote!(),
-    };
+    if table.insertable.is_none() {
+        return quote!();
+    }
 
     let insert_fields: Vec<&TableField<PgBackend>> = table.insertable_fields().collect();
+    let insert_columns: Vec<&TableField<PgBackend>> = insert_fields
+        .iter()
+        .flat_map(|field| field.columns())
+        .collect();
     let default_fields: Vec<&TableField<PgBackend>> = table.default_fields().collect();
 
-    let table_ident = &table.ident;
     let insert_field_idents = insert_fields
         .iter()
         .map(|field| &field.field)
@@ -47,13 +53,23 @@ pub fn impl_insert(table: &Table<PgBackend>) -> TokenStream {
         .iter()
         .map(|field| &field.field)
         .collect::<Vec<&Ident>>();
+    let generated = Ident::new("_generated", Span::call_site());
+    let default_field_values = default_fields
+        .iter()
+        .enumerate()
+        .map(|(index, field)| match table.unchecked {
+            true => field.fmt_from_row_index(&quote!(&_generated), index),
+            false => field.fmt_from_row(&generated),
+        })
+        .collect::<Vec<TokenStream>>();
 
-    let insert_sql = insert_sql(table, &insert_fields);
+    let insert_sql = insert_sql(table, &insert_columns);
+    let insert_sql = table.tag_sql("insert", insert_sql);
 
-    let insert_field_exprs = insert_fields
+    let insert_field_exprs = insert_columns
         .iter()
-        .map(|f| f.fmt_as_argument())
-        .collect::<Vec<TokenStream>>();
+        .map(|f| f.fmt_as_argument(table.unchecked));
+    let query = query(table.unchecked, &insert_sql, insert_field_exprs);
 
     let fetch_funtion = if default_fields.is_empty() {
         Ident::new("execute", Span::call_site())
@@ -61,26 +77,28 @@ pub fn impl_insert(table: &Table<PgBackend>) -> TokenStream {
         Ident::new("fetch_one", Span::call_site())
     };
 
-    let box_future = crate::utils::box_future();
-    quote! {
-        impl ormx::Insert for #insert_ident {
-            type Table = #table_ident;
+    let row_path = table_path(table);
+    let validate = table
+        .validate
+        .then(|| quote!(ormx::Validate::validate(&self)?;));
+    let future = observe(
+        &table.table,
+        "insert",
+        None,
+        quote! {
+            async move {
+                #validate
+                let _generated = #query
+                    .#fetch_funtion(db)
+                    .await?;
 
-            fn insert<'a, 'c: 'a>(
-                self,
-                db: impl sqlx::Executor<'c, Database = ormx::Db> + 'a,
-            ) -> #box_future<'a, sqlx::Result<Self::Table>> {
-                Box::pin(async move {
-                    let _generated = sqlx::query!(#insert_sql, #( #insert_field_exprs, )*)
-                        .#fetch_funtion(db)
-                        .await?;
-
-                    Ok(Self::Table {
-                        #( #insert_field_idents: self.#insert_field_idents, )*
-                        #( #default_field_idents: _generated.#default_field_idents, )*
-                    })
-                })
+                let row = #row_path {
+                    #( #insert_field_idents: self.#insert_field_idents, )*
+                    #( #default_field_idents: #default_field_values, )*
+                };
+                Ok(row)
             }
-        }
-    }
+        },
+    );
+    impl_insert_method(table, future)
 }
@@ -1,8 +1,13 @@
 use std::borrow::Cow;
 
 use proc_macro2::TokenStream;
+use quote::quote;
 
-use crate::{backend::Backend, table::Table};
+use crate::{
+    backend::{common, memory, Backend},
+    table::Table,
+    utils::{observe, query_as},
+};
 
 mod insert;
 
@@ -28,8 +33,69 @@ impl Backend for PgBackend {
     ];
     type Bindings = PgBindings;
 
+    fn fmt_json_contains(column: &str, value: &str) -> String {
+        format!("{} @> {}", column, value)
+    }
+
+    const JSON_TYPE: &'static str = ormx_types::postgres::JSON_TYPE;
+
+    fn sql_type(name: &str, arg: Option<&str>) -> Option<String> {
+        ormx_types::postgres::sql_type(name, arg)
+    }
+
+    fn serial_type(sql_type: &str) -> String {
+        ormx_types::postgres::serial_type(sql_type)
+    }
+
     fn impl_insert(table: &Table<Self>) -> TokenStream {
-        insert::impl_insert(table)
+        match table.memory {
+            true => memory::impl_insert(table),
+            false => insert::impl_insert(table),
+        }
+    }
+
+    /// Binds all IDs as a single array, which allows the query to be checked at compile time.
+    fn get_by_ids(table: &Table<Self>) -> TokenStream {
+        let box_future = crate::utils::box_future();
+        let sql = format!(
+            "SELECT {} FROM {} WHERE {} = ANY($1)",
+            table.select_column_list(),
+            table.table,
+            table.id.column()
+        );
+        let sql = table.tag_sql("get_by_ids", sql);
+        let row = table.row_ident();
+        let row_ty = common::row_ty(row.as_ref());
+        let convert = row.map(|_| quote!(.map(|rows| rows.into_iter().map(Self::from).collect())));
+        let query = query_as(table.unchecked, &row_ty, &sql, Some(quote!(ids)));
+        let future = observe(
+            &table.table,
+            "get_by_ids",
+            None,
+            quote! {
+                async move {
+                    if ids.is_empty() {
+                        return Ok(vec![]);
+                    }
+                    #query
+                        .fetch_all(db)
+                        .await
+                        #convert
+                }
+            },
+        );
+
+        quote! {
+            fn get_by_ids<'__a, '__c: '__a>(
+                db: impl sqlx::Executor<'__c, Database = ormx::Db> + '__a,
+                ids: &'__a [Self::Id],
+            ) -> #box_future<'__a, sqlx::Result<Vec<Self>>>
+            where
+                Self: '__a,
+            {
+                Box::pin(#future)
+            }
+        }
     }
 }
 
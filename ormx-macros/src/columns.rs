@@ -0,0 +1,32 @@
+use std::convert::TryFrom;
+
+use proc_macro2::{Ident, Span, TokenStream};
+use quote::quote;
+use syn::{DeriveInput, Result};
+
+use crate::{backend::Implementation, table::TableField, utils::named_fields};
+
+/// name of the macro generated by `#[derive(ormx::Columns)]` for the given struct
+pub fn macro_ident(ident: &Ident) -> Ident {
+    Ident::new(&format!("__ormx_columns_{}", ident), Span::call_site())
+}
+
+pub fn derive(input: DeriveInput) -> Result<TokenStream> {
+    for field in named_fields(&input)? {
+        TableField::<Implementation>::try_from(field)?.check_embeddable()?;
+    }
+
+    // Invoked by `#[derive(ormx::Table)]` when it requires the definition of this struct.
+    // The definition is appended to the input of the callback, which is then invoked again.
+    let macro_ident = macro_ident(&input.ident);
+    Ok(quote! {
+        #[doc(hidden)]
+        macro_rules! #macro_ident {
+            ([$($callback:tt)*] $($state:tt)*) => {
+                $($callback)*! { $($state)* { #input } }
+            };
+        }
+        #[doc(hidden)]
+        pub(crate) use #macro_ident;
+    })
+}
@@ -0,0 +1,102 @@
+use std::convert::TryFrom;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{DeriveInput, Generics, Ident, Path, Result};
+
+mod parse;
+
+pub struct Filter {
+    pub ident: Ident,
+    pub generics: Generics,
+    pub table: Path,
+    pub fields: Vec<FilterField>,
+}
+
+pub struct FilterField {
+    pub ident: Ident,
+    /// the field of the table this field is compared to
+    pub column: Ident,
+    pub op: Op,
+    /// `None` fields are ignored
+    pub optional: bool,
+}
+
+#[derive(Clone, Copy)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Like,
+    In,
+}
+
+impl Op {
+    pub const ALL: &'static [(&'static str, Op)] = &[
+        ("eq", Op::Eq),
+        ("ne", Op::Ne),
+        ("lt", Op::Lt),
+        ("le", Op::Le),
+        ("gt", Op::Gt),
+        ("ge", Op::Ge),
+        ("like", Op::Like),
+        ("in", Op::In),
+    ];
+
+    /// the method of `ormx::Column` creating the condition
+    fn method(self) -> Ident {
+        let name = match self {
+            Op::Eq => "eq",
+            Op::Ne => "ne",
+            Op::Lt => "lt",
+            Op::Le => "le",
+            Op::Gt => "gt",
+            Op::Ge => "ge",
+            Op::Like => "like",
+            Op::In => "is_in",
+        };
+        Ident::new(name, proc_macro2::Span::call_site())
+    }
+}
+
+impl FilterField {
+    fn fmt_apply(&self) -> TokenStream {
+        let ident = &self.ident;
+        let column = &self.column;
+        let method = self.op.method();
+        if self.optional {
+            quote! {
+                if let Some(value) = self.#ident {
+                    select = select.filter(columns.#column.#method(value));
+                }
+            }
+        } else {
+            quote!(select = select.filter(columns.#column.#method(self.#ident));)
+        }
+    }
+}
+
+pub fn derive(input: DeriveInput) -> Result<TokenStream> {
+    let parsed = Filter::try_from(&input)?;
+
+    let ident = &parsed.ident;
+    let table = &parsed.table;
+    let (impl_generics, ty_generics, where_clause) = parsed.generics.split_for_impl();
+    let apply = parsed.fields.iter().map(FilterField::fmt_apply);
+
+    Ok(quote! {
+        impl #impl_generics ormx::Filter for #ident #ty_generics #where_clause {
+            type Table = #table;
+
+            fn apply(self, select: ormx::Select<Self::Table>) -> ormx::Select<Self::Table> {
+                let columns = <Self::Table as ormx::TableMeta>::columns();
+                let mut select = select;
+                #( #apply )*
+                select
+            }
+        }
+    })
+}
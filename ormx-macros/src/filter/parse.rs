@@ -0,0 +1,136 @@
+use std::convert::TryFrom;
+
+use syn::{DeriveInput, Error, Field, Result};
+
+use super::{Filter, FilterField, Op};
+use crate::{
+    attrs::{parse_attrs, FilterAttr, FilterFieldAttr},
+    utils::{missing_attr, named_fields, option_inner, set_once},
+};
+
+impl TryFrom<&syn::DeriveInput> for Filter {
+    type Error = Error;
+
+    fn try_from(value: &DeriveInput) -> Result<Self> {
+        let fields = named_fields(value)?
+            .iter()
+            .map(FilterField::try_from)
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut table = None;
+        for (name, attr) in parse_attrs::<FilterAttr>(&value.attrs)? {
+            match attr {
+                FilterAttr::Table(x) => set_once(&mut table, x, &name)?,
+            }
+        }
+
+        Ok(Filter {
+            ident: value.ident.clone(),
+            generics: value.generics.clone(),
+            table: table.ok_or_else(|| missing_attr("table", &value.ident))?,
+            fields,
+        })
+    }
+}
+
+impl TryFrom<&syn::Field> for FilterField {
+    type Error = Error;
+
+    fn try_from(value: &Field) -> Result<Self> {
+        let ident = value.ident.clone().unwrap();
+
+        let mut column = None;
+        let mut op = None;
+        for (name, attr) in parse_attrs::<FilterFieldAttr>(&value.attrs)? {
+            match attr {
+                FilterFieldAttr::Field(x) => set_once(&mut column, x, &name)?,
+                FilterFieldAttr::Op(x) => {
+                    let parsed = Op::ALL
+                        .iter()
+                        .find(|(op, _)| *op == x)
+                        .map(|(_, op)| *op)
+                        .ok_or_else(|| {
+                            let valid = Op::ALL.iter().map(|(op, _)| *op).collect::<Vec<_>>();
+                            Error::new(
+                                name.span(),
+                                format!(
+                                    "unknown operator \"{}\"\nexpected one of: {}",
+                                    x,
+                                    valid.join(", ")
+                                ),
+                            )
+                        })?;
+                    set_once(&mut op, parsed, &name)?
+                }
+            }
+        }
+
+        Ok(FilterField {
+            column: column.unwrap_or_else(|| ident.clone()),
+            ident,
+            op: op.unwrap_or(Op::Eq),
+            optional: option_inner(&value.ty).is_some(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse_quote;
+
+    use super::*;
+
+    fn filter(input: DeriveInput) -> Result<Filter> {
+        Filter::try_from(&input)
+    }
+
+    #[test]
+    fn fields_default_to_equality() {
+        let parsed = filter(parse_quote! {
+            #[ormx(table = User)]
+            struct UserFilter {
+                email: Option<String>,
+                #[ormx(field = last_login, op = "ge")]
+                active_since: NaiveDateTime,
+            }
+        })
+        .unwrap();
+        let email = &parsed.fields[0];
+        assert_eq!(email.column, "email");
+        assert!(matches!(email.op, Op::Eq));
+        assert!(email.optional);
+        let active_since = &parsed.fields[1];
+        assert_eq!(active_since.column, "last_login");
+        assert!(matches!(active_since.op, Op::Ge));
+        assert!(!active_since.optional);
+    }
+
+    #[test]
+    fn unknown_operators_are_rejected() {
+        let error = filter(parse_quote! {
+            #[ormx(table = User)]
+            struct UserFilter {
+                #[ormx(op = "ilike")]
+                name: Option<String>,
+            }
+        })
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "unknown operator \"ilike\"\nexpected one of: eq, ne, lt, le, gt, ge, like, in"
+        );
+    }
+
+    #[test]
+    fn table_is_required() {
+        let error = filter(parse_quote! {
+            struct UserFilter {
+                name: Option<String>,
+            }
+        })
+        .err()
+        .unwrap();
+        assert_eq!(error.to_string(), "missing #[ormx(table)] attribute");
+    }
+}
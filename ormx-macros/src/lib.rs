@@ -2,9 +2,12 @@
 
 mod attrs;
 mod backend;
+mod columns;
+mod filter;
 mod patch;
 mod table;
 mod utils;
+mod validate;
 
 /// Derives [Table](trait.Table.html) and generates a struct for inserting rows and accessors
 /// to certain fields.
@@ -52,6 +55,80 @@ mod utils;
 /// ormx will implement [Delete](trait.Delete.html) for your struct when using
 /// `#[ormx(deletable)].
 ///
+/// # Hooks
+/// With `#[ormx(hooks)]`, the generated `insert`, `update`, `patch` and `delete_row` call the
+/// methods of [ormx::Hooks](trait.Hooks.html) before and after modifying the row, which must then
+/// be implemented for the struct. The hooks receive the executor passed to the generated method,
+/// which therefore has to implement [ormx::Reborrow](trait.Reborrow.html). These methods are
+/// generated as inherent methods instead of implementing `Insert` and `Delete`, and `update`
+/// returns the updated row, see [ormx::Hooks](trait.Hooks.html) for details.
+/// Patches of the table have to implement [ormx::PatchHooks](trait.PatchHooks.html).
+///
+/// ```rust,ignore
+/// impl ormx::Hooks for User {
+///     type Insert = InsertUser;
+///
+///     fn before_insert<'a>(
+///         row: &'a mut InsertUser,
+///         _db: impl sqlx::Executor<'a, Database = ormx::Db> + 'a,
+///     ) -> BoxFuture<'a, sqlx::Result<()>> {
+///         row.email = row.email.to_lowercase();
+///         Box::pin(async { Ok(()) })
+///     }
+/// }
+/// ```
+///
+/// # Validation
+/// With `#[ormx(validate)]`, ormx implements [ormx::Validate](trait.Validate.html) for the table
+/// and its insert struct, which is called by the generated `insert` and `update` before running
+/// any SQL. Constraints are added to fields using these attributes:
+/// - `#[ormx(length(min = 1, max = 255))]` for strings and `Vec`s, counting characters
+/// - `#[ormx(range(min = 0, max = 100))]` for any type implementing `PartialOrd`
+/// - `#[ormx(regex = EMAIL_REGEX)]`, calling `EMAIL_REGEX.is_match(&field)`, e.g. on a
+///   `Lazy<regex::Regex>`
+///
+/// Fields of type `Option<T>` are only checked if they are `Some`. If a constraint is violated,
+/// the generated methods return an error containing the
+/// [ValidationErrors](struct.ValidationErrors.html). Convert it into an
+/// [ormx::WriteError](enum.WriteError.html) to catch them:
+///
+/// ```rust,ignore
+/// match new_user.insert(&db).await.map_err(ormx::WriteError::from) {
+///     Ok(user) => ..,
+///     Err(ormx::WriteError::Invalid(errors)) => ..,
+///     Err(ormx::WriteError::Sqlx(err)) => ..,
+/// }
+/// ```
+///
+/// # Audit log
+/// With `#[ormx(audit = "audit_log")]`, the generated `update`, `delete_row` and setters log each
+/// change into the table `audit_log`, in the same statement which modifies the row. See
+/// [ormx::audit](audit/index.html) for the schema of the audit table and how to set the actor.
+/// Patches are logged if they are annotated with `#[ormx(audit = "..")]` as well.
+/// This is only supported on postgres.
+///
+/// # Tracking changes
+/// With `#[ormx(tracked)]`, rows can be wrapped in [ormx::Tracked](struct.Tracked.html), which
+/// remembers the values a row was loaded with. Its `save` method then only updates the columns
+/// which have changed. This requires the table to implement `Clone` and all fields to implement
+/// `PartialEq`. Hooks and validation run just like for `update`.
+///
+/// # Caching
+/// With `#[ormx(cache(ttl = "60s"))]`, rows queried by `get` and by `get_one` and `get_optional`
+/// getters are cached in-process for the given duration (`ms`, `s`, `m`, `h` or `d`). The caches
+/// are invalidated by the generated `update`, `patch`, `delete_row` and setters, and by the
+/// `patch_row` of patches.
+/// The maximum number of cached rows can be given using `capacity = ..`, and a custom cache using
+/// `with = path::to::MyCache`. See [ormx::cache](cache/index.html) for details.
+/// This requires the table to implement `Clone`, and isn't supported on generic tables.
+///
+/// # Factories
+/// With `#[ormx(factory)]`, which requires `insertable`, `InsertUser::factory()` returns a builder
+/// for tests, which generates the values of all fields unless they are overridden.
+/// The value of a field can be given using `#[ormx(factory = format!("user{}", n))]`, where `n` is
+/// a sequence number, and foreign keys can be filled in by creating a parent row using
+/// `#[ormx(factory(parent = Organization))]`. See [ormx::factory](factory/index.html) for details.
+///
 /// # Accessors: Getters
 /// ormx will generate accessor functions for fields annotated with `#[ormx(get_one)]`,
 /// `#[ormx(get_optional)]` and `#[ormx(get_many)]`.
@@ -88,6 +165,143 @@ mod utils;
 /// `#[ormx(custom_type)]`.
 /// This will use a column type override for querying this field
 /// (see [the sqlx docs on this](https://docs.rs/sqlx/0.4.0-beta.1/sqlx/macro.query_as.html#column-type-override-infer-from-struct-field)).
+///
+/// # JSON
+/// Fields annotated with `#[ormx(json)]` are stored as JSON. Their type has to implement
+/// `Serialize` and `DeserializeOwned`, and is wrapped in `sqlx::types::Json` when querying.
+/// This requires the `json` feature.
+///
+/// Additionally, `#[ormx(get_many_contains)]` generates a getter returning all rows in which the
+/// JSON document of the field contains the given one (`@>` on postgres, `JSON_CONTAINS` on
+/// mysql):
+/// `{pub} async fn by_{field_name}_contains(&{field_type}) -> Result<Vec<Self>>`
+///
+/// # Generics
+/// Tables, their insert structs and patches may have lifetime and type parameters. Fields of type
+/// `PhantomData` don't correspond to a column, which allows using marker types:
+///
+/// ```rust,ignore
+/// #[derive(ormx::Table)]
+/// #[ormx(table = "projects", id = id, insertable)]
+/// struct Project<T: Tenant> {
+///     id: i32,
+///     name: String,
+///     tenant: PhantomData<T>,
+/// }
+/// ```
+///
+/// Since rows are decoded into owned values, columns can't borrow from the database. Columns of
+/// type `Cow<'a, T>`, e.g. `Cow<'a, str>`, are supported though, and decoded into `Cow::Owned`:
+///
+/// ```rust,ignore
+/// #[derive(ormx::Table)]
+/// #[ormx(table = "notes", id = id, insertable)]
+/// struct Note<'a> {
+///     id: i32,
+///     body: Cow<'a, str>,
+/// }
+/// ```
+///
+/// Getters of such columns take `&T`, e.g. `&str`.
+///
+/// # Embedded structs
+/// Fields annotated with `#[ormx(flatten)]` are stored in multiple columns, one for each field of
+/// the embedded struct, which has to derive [Columns](derive.Columns.html).
+/// The columns are prefixed with `{field_name}_`, though this can be changed by supplying a custom
+/// prefix: `#[ormx(flatten = "addr_")]`. Embedded structs can be nested.
+///
+/// ```rust,ignore
+/// #[derive(ormx::Columns)]
+/// struct Address {
+///     street: String,
+///     city: String,
+/// }
+///
+/// #[derive(ormx::Table)]
+/// #[ormx(table = "users", id = id, insertable)]
+/// struct User {
+///     id: i32,
+///     // stored in the columns `address_street` and `address_city`
+///     #[ormx(flatten)]
+///     address: Address,
+/// }
+/// ```
+///
+/// The embedded struct has to be defined in the same crate, and its type has to be written as a
+/// path through which it is visible from the module of the table. This also applies to structs
+/// nested within embedded structs, so structs of the same name in different modules can be told
+/// apart.
+///
+/// # Metadata
+/// ormx implements [TableMeta](trait.TableMeta.html) for every table, providing the name of the
+/// table, its columns and the column list used by the generated queries. This can be used to write
+/// custom queries:
+///
+/// ```rust,ignore
+/// let sql = format!("SELECT {} FROM {} WHERE ...", User::SELECT_COLUMNS, User::TABLE);
+/// ```
+///
+/// # Creating tables
+/// `TableMeta::create_table_sql()` returns a `CREATE TABLE` statement for the table, e.g. for
+/// setting up a database in tests. The SQL types of the columns are inferred from the types of
+/// the fields. Types without a known mapping, like custom types, use the name of the rust type.
+/// The ID is the primary key, which is generated by the database if it is annotated with
+/// `#[ormx(default)]`, and fields with `#[ormx(get_one)]` or `#[ormx(get_optional)]` are unique.
+///
+/// The type of a column can be overridden using `#[ormx(sql_type = "..")]`. Since the statement
+/// doesn't contain default values, this can be used to add them:
+/// `#[ormx(default, sql_type = "TIMESTAMP DEFAULT now()")]`.
+///
+/// # Verifying the schema
+/// `ormx::verify::<Table>(&db)` compares the table in the database with the struct, e.g. at
+/// startup or in tests. It returns an `ormx::SchemaReport`, listing columns which don't exist,
+/// have an incompatible type or nullability, or are left out when inserting but have no default.
+/// Types are only compared if they are known, so they should be given using
+/// `#[ormx(sql_type = "..")]` for custom types.
+///
+/// # Query builder
+/// For each table, ormx generates a struct `{struct_name}Columns`, containing an
+/// [ormx::Column](struct.Column.html) for each column. Columns of embedded structs are nested.
+/// They can be used to build queries which are checked at runtime:
+///
+/// ```rust,ignore
+/// let col = User::columns();
+/// let users = User::select()
+///     .filter(col.email.eq(email))
+///     .order_by(col.last_login.desc())
+///     .limit(10)
+///     .fetch_all(&db)
+///     .await?;
+/// ```
+///
+/// # Unchecked queries
+/// By default, all queries are checked against the database at compile time using
+/// `sqlx::query!`, which requires `DATABASE_URL` or the offline data of sqlx when building.
+/// Tables annotated with `#[ormx(unchecked)]` instead use `sqlx::query_as` with arguments bound at
+/// runtime, generating the same SQL. The `unchecked` feature enables this for all tables and
+/// patches.
+///
+/// Columns are then decoded by their position within the generated column list, so there is no
+/// compile-time check that the types of the fields match the schema.
+///
+/// # SQL comments
+/// With `#[ormx(tag)]`, the statements of the generated methods start with a comment naming the
+/// table and the method, e.g. `/* ormx:table=users,op=get */`, which makes them identifiable in
+/// `pg_stat_statements`, slow query logs or the `performance_schema` of MySQL. The operations are
+/// named like in [ormx::observe](observe/index.html). `#[ormx(tag = "billing")]` replaces the
+/// `ormx` prefix, e.g. to tell services apart; it can't contain `/*` or `*/` or start with `+` or
+/// `!`. Since the comments are part of the static SQL, queries are still checked at compile time.
+///
+/// The queries built using `Table::select()` and the statements of `Tracked::save` are tagged as
+/// well, with the operations `select` and `save`. Patches inherit the tag of their table unless
+/// they have `#[ormx(tag)]` themselves.
+///
+/// # In-memory store
+/// With `#[ormx(memory)]`, which requires the `memory` feature, the generated methods read and
+/// write rows from an in-memory store instead of querying the database. It's meant for unit tests,
+/// e.g. using `#[cfg_attr(test, ormx(memory))]`, and has to be repeated on the patches of the
+/// table. Queries aren't checked against the database then. See
+/// [ormx::memory](memory/index.html) for details.
 #[proc_macro_error::proc_macro_error]
 #[proc_macro_derive(Table, attributes(ormx))]
 pub fn derive_table(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
@@ -99,7 +313,72 @@ pub fn derive_table(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     .into()
 }
 
+/// Derives the columns of a struct which can be embedded into a table using `#[ormx(flatten)]`.
+///
+/// Fields of the struct support `#[ormx(column)]`, `#[ormx(custom_type)]`, `#[ormx(json)]`,
+/// `#[ormx(by_ref)]`, `#[ormx(sql_type)]` and `#[ormx(flatten)]`.
+#[proc_macro_error::proc_macro_error]
+#[proc_macro_derive(Columns, attributes(ormx))]
+pub fn derive_columns(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
+    match columns::derive(input) {
+        Ok(ok) => ok,
+        Err(err) => err.to_compile_error(),
+    }
+    .into()
+}
+
+#[doc(hidden)]
+#[proc_macro_error::proc_macro_error]
+#[proc_macro]
+pub fn __table_callback(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as table::TableCallback);
+    match table::derive_callback(input) {
+        Ok(ok) => ok,
+        Err(err) => err.to_compile_error(),
+    }
+    .into()
+}
+
+/// Derives [Filter](trait.Filter.html) for a struct of search criteria on a table.
+///
+/// Each field is compared to the field of the same name of the table, though this can be changed
+/// using `#[ormx(field = other_field)]`. Fields of type `Option<T>` are ignored if they are `None`.
+/// By default, fields are compared for equality. Other comparisons can be used with
+/// `#[ormx(op = "..")]`, which is one of `eq`, `ne`, `lt`, `le`, `gt`, `ge`, `like` and `in`.
+/// For `in`, the field has to contain a collection of values.
+///
+/// ```rust,ignore
+/// #[derive(ormx::Filter)]
+/// #[ormx(table = User)]
+/// struct UserFilter {
+///     email: Option<String>,
+///     #[ormx(op = "like")]
+///     first_name: Option<String>,
+///     #[ormx(field = last_login, op = "ge")]
+///     active_since: Option<NaiveDateTime>,
+/// }
+///
+/// let users = User::find(&db, UserFilter { .. }).await?;
+/// ```
+#[proc_macro_error::proc_macro_error]
+#[proc_macro_derive(Filter, attributes(ormx))]
+pub fn derive_filter(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
+    match filter::derive(input) {
+        Ok(ok) => ok,
+        Err(err) => err.to_compile_error(),
+    }
+    .into()
+}
+
 /// Derives [Patch](trait.Patch.html).
+///
+/// Like tables, patches support `#[ormx(unchecked)]` for using queries which are not checked at
+/// compile time, `#[ormx(validate)]` for checking their fields before they are applied,
+/// `#[ormx(audit = "..")]` for logging the changes into an audit table, `#[ormx(tag)]` for
+/// prepending a comment to the statement instead of the one of the table, and `#[ormx(memory)]`
+/// for writing to the in-memory store if the table does.
 #[proc_macro_error::proc_macro_error]
 #[proc_macro_derive(Patch, attributes(ormx))]
 pub fn derive_patch(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
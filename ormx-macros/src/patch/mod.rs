@@ -2,18 +2,33 @@ use std::convert::TryFrom;
 
 use proc_macro2::TokenStream;
 use quote::quote;
-use syn::{DeriveInput, Ident, Path, Result, Type};
+use syn::{DeriveInput, Generics, Ident, Path, Result, Type};
 
-use crate::backend::{Backend, Implementation};
+use crate::{
+    backend::{Backend, Implementation},
+    utils::{cow_argument, cow_inner, json_argument, option_inner},
+    validate::Validations,
+};
 
 mod parse;
 
 pub struct Patch {
     pub ident: Ident,
+    pub generics: Generics,
     pub table_name: String,
     pub table: Path,
     pub id: String,
     pub fields: Vec<PatchField>,
+    /// use runtime-checked queries instead of `sqlx::query!`
+    pub unchecked: bool,
+    /// implement `ormx::Validate`, which is called before applying the patch
+    pub validate: bool,
+    /// table into which changes are logged
+    pub audit: Option<String>,
+    /// prefix of the comment prepended to the generated statement
+    pub tag: Option<String>,
+    /// write to `ormx::memory::Store` instead of querying the database, like the table
+    pub memory: bool,
 }
 
 pub struct PatchField {
@@ -21,15 +36,29 @@ pub struct PatchField {
     pub column: String,
     pub ty: Type,
     pub custom_type: bool,
+    pub json: bool,
     pub by_ref: bool,
+    /// `PhantomData` fields don't correspond to a column
+    pub marker: bool,
+    pub validations: Validations,
 }
 
 impl PatchField {
-    pub fn fmt_as_argument(&self) -> TokenStream {
+    pub fn fmt_as_argument(&self, unchecked: bool) -> TokenStream {
         let ident = &self.ident;
         let ty = &self.ty;
 
-        let mut out = quote!(#ident);
+        if self.json {
+            return json_argument(ty, quote!(&self.#ident), unchecked);
+        }
+        if cow_inner(option_inner(ty).unwrap_or(ty)).is_some() {
+            return cow_argument(ty, quote!(self.#ident));
+        }
+        if unchecked {
+            return quote!(&self.#ident);
+        }
+
+        let mut out = quote!(self.#ident);
         if self.custom_type {
             out = quote!(#out as #ty);
         }
@@ -38,6 +67,21 @@ impl PatchField {
         }
         out
     }
+
+    /// checks the constraints of this field, see [Validations::fmt_checks]
+    pub fn fmt_validations(&self) -> TokenStream {
+        let ident = &self.ident;
+        let nullable = option_inner(&self.ty).is_some();
+        self.validations
+            .fmt_checks(&ident.to_string(), quote!(self.#ident), nullable)
+    }
+}
+
+impl Patch {
+    /// the fields of this patch which correspond to a column
+    pub fn columns(&self) -> impl Iterator<Item = &PatchField> {
+        self.fields.iter().filter(|field| !field.marker)
+    }
 }
 
 pub fn derive(input: DeriveInput) -> Result<TokenStream> {
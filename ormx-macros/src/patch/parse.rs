@@ -1,25 +1,21 @@
 use std::convert::TryFrom;
 
-use syn::{Data, DeriveInput, Error, Field, Result};
+use syn::{DeriveInput, Error, Field, Result};
 
 use super::Patch;
 use crate::{
     attrs::{parse_attrs, PatchAttr, PatchFieldAttr},
     patch::PatchField,
-    utils::{missing_attr, set_once},
+    table::{check_audit, check_memory, is_phantom_data, parse_tag},
+    utils::{check_conflict, missing_attr, named_fields, set_once},
+    validate::Validations,
 };
 
 impl TryFrom<&syn::DeriveInput> for Patch {
     type Error = Error;
 
     fn try_from(value: &DeriveInput) -> Result<Self> {
-        let data = match &value.data {
-            Data::Struct(s) => s,
-            _ => panic!("not a struct with named fields"),
-        };
-
-        let fields = data
-            .fields
+        let fields = named_fields(value)?
             .iter()
             .map(PatchField::try_from)
             .collect::<Result<Vec<_>>>()?;
@@ -27,20 +23,51 @@ impl TryFrom<&syn::DeriveInput> for Patch {
         let mut table = None;
         let mut table_name = None;
         let mut id = None;
-        for attr in parse_attrs::<PatchAttr>(&value.attrs)? {
+        let mut unchecked = None;
+        let mut validate = None;
+        let mut audit = None;
+        let mut tag = None;
+        let mut memory = None;
+        for (name, attr) in parse_attrs::<PatchAttr>(&value.attrs)? {
             match attr {
-                PatchAttr::Table(x) => set_once(&mut table, x)?,
-                PatchAttr::TableName(x) => set_once(&mut table_name, x)?,
-                PatchAttr::Id(x) => set_once(&mut id, x)?,
+                PatchAttr::Table(x) => set_once(&mut table, x, &name)?,
+                PatchAttr::TableName(x) => set_once(&mut table_name, x, &name)?,
+                PatchAttr::Id(x) => set_once(&mut id, x, &name)?,
+                PatchAttr::Unchecked(_) => set_once(&mut unchecked, true, &name)?,
+                PatchAttr::Validate(_) => set_once(&mut validate, true, &name)?,
+                PatchAttr::Audit(x) => {
+                    check_audit(&name)?;
+                    set_once(&mut audit, x, &name)?
+                }
+                PatchAttr::Tag(x) => set_once(&mut tag, parse_tag(&name, x)?, &name)?,
+                PatchAttr::Memory(_) => {
+                    check_memory(&name)?;
+                    set_once(&mut memory, true, &name)?
+                }
+            }
+        }
+
+        if validate.is_none() {
+            if let Some(field) = fields.iter().find(|field| !field.validations.is_empty()) {
+                return Err(Error::new(
+                    field.ident.span(),
+                    "validation attributes have no effect without #[ormx(validate)]",
+                ));
             }
         }
 
         Ok(Patch {
             ident: value.ident.clone(),
-            table_name: table_name.ok_or_else(|| missing_attr("table_name"))?,
-            table: table.ok_or_else(|| missing_attr("table"))?,
-            id: id.ok_or_else(|| missing_attr("id"))?,
+            generics: value.generics.clone(),
+            table_name: table_name.ok_or_else(|| missing_attr("table_name", &value.ident))?,
+            table: table.ok_or_else(|| missing_attr("table", &value.ident))?,
+            id: id.ok_or_else(|| missing_attr("id", &value.ident))?,
             fields,
+            unchecked: unchecked.unwrap_or(cfg!(feature = "unchecked")),
+            validate: validate.unwrap_or(false),
+            audit,
+            tag,
+            memory: memory.unwrap_or(false),
         })
     }
 }
@@ -53,21 +80,33 @@ impl TryFrom<&syn::Field> for PatchField {
 
         let mut column = None;
         let mut custom_type = None;
+        let mut json = None;
         let mut by_ref = None;
-        for attr in parse_attrs::<PatchFieldAttr>(&value.attrs)? {
+        let mut validations = Validations::default();
+        let mut names = vec![];
+        for (name, attr) in parse_attrs::<PatchFieldAttr>(&value.attrs)? {
             match attr {
-                PatchFieldAttr::Column(x) => set_once(&mut column, x)?,
-                PatchFieldAttr::CustomType(_) => set_once(&mut custom_type, true)?,
-                PatchFieldAttr::ByRef(_) => set_once(&mut by_ref, true)?,
+                PatchFieldAttr::Column(x) => set_once(&mut column, x, &name)?,
+                PatchFieldAttr::CustomType(_) => set_once(&mut custom_type, true, &name)?,
+                PatchFieldAttr::Json(_) => set_once(&mut json, true, &name)?,
+                PatchFieldAttr::ByRef(_) => set_once(&mut by_ref, true, &name)?,
+                PatchFieldAttr::Length(x) => set_once(&mut validations.length, x, &name)?,
+                PatchFieldAttr::Range(x) => set_once(&mut validations.range, x, &name)?,
+                PatchFieldAttr::Regex(x) => set_once(&mut validations.regex, x, &name)?,
             }
+            names.push(name);
         }
+        check_conflict(&names, "json", "custom_type")?;
 
         Ok(PatchField {
             ident: value.ident.clone().unwrap(),
             column: column.unwrap_or_else(|| ident.to_string()),
             ty: value.ty.clone(),
             custom_type: custom_type.unwrap_or(false),
+            json: json.unwrap_or(false),
             by_ref: by_ref.unwrap_or(false),
+            marker: is_phantom_data(&value.ty),
+            validations,
         })
     }
 }
@@ -1,25 +1,52 @@
 use std::{borrow::Cow, convert::TryFrom, marker::PhantomData};
 
 use itertools::Itertools;
-use proc_macro2::{Ident, Span, TokenStream};
+use proc_macro2::{Ident, Span, TokenStream, TokenTree};
 use quote::quote;
-use syn::{DeriveInput, Result, Type, Visibility, Attribute};
+use syn::{
+    parse::{Parse, ParseStream},
+    DeriveInput, GenericParam, Generics, Path, PathArguments, Result, Type, Visibility,
+    Attribute,
+};
 
 use crate::{
-    attrs::{Getter, Insertable},
-    backend::{Backend, Implementation},
+    attrs::{CacheOptions, FactoryValue, Getter, Insertable},
+    backend::{AuditColumn, Backend, Implementation},
+    utils::{cow_argument, cow_inner, json_argument, option_inner, tag_sql},
+    validate::Validations,
 };
 
 mod parse;
 
+pub(crate) use parse::{check_audit, check_memory, is_phantom_data, parse_tag};
+
 pub struct Table<B: Backend> {
     pub ident: Ident,
     pub vis: Visibility,
+    pub generics: Generics,
     pub table: String,
     pub id: TableField<B>,
     pub fields: Vec<TableField<B>>,
     pub insertable: Option<Insertable>,
-    pub deletable: bool
+    pub deletable: bool,
+    /// use runtime-checked queries instead of `sqlx::query!`
+    pub unchecked: bool,
+    /// call the methods of `ormx::Hooks` before and after modifying a row
+    pub hooks: bool,
+    /// implement `ormx::Validate`, which is called before writing a row
+    pub validate: bool,
+    /// table into which changes are logged
+    pub audit: Option<String>,
+    /// implement `ormx::Track`, for updating only changed columns
+    pub tracked: bool,
+    /// cache rows queried by `get` and by `get_one` and `get_optional` getters
+    pub cache: Option<CacheOptions>,
+    /// implement `ormx::factory::Factory`, building rows for tests
+    pub factory: bool,
+    /// prefix of the comments prepended to the generated statements
+    pub tag: Option<String>,
+    /// read and write rows from `ormx::memory::Store` instead of querying the database
+    pub memory: bool,
 }
 
 #[derive(Clone)]
@@ -28,17 +55,38 @@ pub struct TableField<B: Backend> {
     pub ty: Type,
     pub column_name: String,
     pub custom_type: bool,
+    pub json: bool,
     pub reserved_ident: bool,
     pub default: bool,
     pub get_one: Option<Getter>,
     pub get_optional: Option<Getter>,
     pub get_many: Option<Getter>,
+    pub get_many_contains: Option<Getter>,
     pub set: Option<Ident>,
     pub by_ref: bool,
     pub insert_attrs: Vec<Attribute>,
+    /// `PhantomData` fields don't correspond to a column
+    pub marker: bool,
+    pub flatten: Option<Flatten<B>>,
+    /// SQL type of the column, overriding the one inferred from the type of the field
+    pub sql_type: Option<String>,
+    /// fields of the embedding structs this field is nested in, starting at the table
+    pub path: Vec<Ident>,
+    pub validations: Validations,
+    /// value of the field generated by the factory, instead of `ormx::factory::Generate`
+    pub factory: Option<FactoryValue>,
     pub _phantom: PhantomData<*const B>,
 }
 
+/// A field annotated with `#[ormx(flatten)]`, whose columns are those of an embedded struct.
+#[derive(Clone)]
+pub struct Flatten<B: Backend> {
+    pub ty: Path,
+    pub prefix: String,
+    /// fields of the embedded struct, `None` until its definition has been resolved
+    pub fields: Option<Vec<TableField<B>>>,
+}
+
 impl<B: Backend> Table<B> {
     pub fn fields_except_id(&self) -> impl Iterator<Item = &TableField<B>> + Clone {
         let id = self.id.field.clone();
@@ -53,36 +101,236 @@ impl<B: Backend> Table<B> {
         self.fields.iter().filter(|field| field.default)
     }
 
+    /// all columns of this table, with embedded structs expanded into their fields
+    pub fn columns(&self) -> impl Iterator<Item = &TableField<B>> + Clone {
+        self.fields.iter().flat_map(TableField::columns)
+    }
+
     pub fn select_column_list(&self) -> String {
-        self.fields
-            .iter()
+        self.columns()
             .map(|field| field.fmt_for_select())
             .join(", ")
     }
+
+    /// prepends the comment given using `#[ormx(tag)]` to the statement of the method `operation`
+    pub fn tag_sql(&self, operation: &str, sql: String) -> String {
+        tag_sql(self.tag.as_deref(), &self.table, operation, sql)
+    }
+
+    /// Tables containing embedded structs, JSON or `Cow` columns can't be queried using
+    /// `query_as!(Self, ..)`, since sqlx can only map columns to flat structs of decodable types.
+    /// Instead, rows are queried into a generated struct with this name and then converted.
+    /// Unchecked tables always use this struct, which implements `sqlx::FromRow`.
+    pub fn row_ident(&self) -> Option<Ident> {
+        let flat = self
+            .fields
+            .iter()
+            .all(|field| field.flatten.is_none() && !field.marker);
+        let decodable = self
+            .columns()
+            .all(|field| !field.json && field.cow_inner().is_none());
+        if self.unchecked || !flat || !decodable {
+            Some(Ident::new(&format!("__Ormx{}Row", self.ident), Span::call_site()))
+        } else {
+            None
+        }
+    }
+
+    /// The generic parameters of the table used by the types of its columns, without bounds.
+    /// Unlike the table, the row struct has no `PhantomData` fields using the other parameters.
+    pub fn row_generics(&self) -> Generics {
+        let mut used = vec![];
+        for field in self.columns() {
+            collect_idents(field.row_ty(), &mut used);
+        }
+
+        let mut generics = self.generics.clone();
+        generics.where_clause = None;
+        generics.params = generics
+            .params
+            .into_iter()
+            .filter_map(|mut param| {
+                let ident = match &mut param {
+                    GenericParam::Lifetime(lt) => {
+                        lt.colon_token = None;
+                        lt.bounds.clear();
+                        lt.lifetime.ident.clone()
+                    }
+                    GenericParam::Type(ty) => {
+                        ty.colon_token = None;
+                        ty.bounds.clear();
+                        ty.eq_token = None;
+                        ty.default = None;
+                        ty.ident.clone()
+                    }
+                    GenericParam::Const(c) => {
+                        c.eq_token = None;
+                        c.default = None;
+                        c.ident.clone()
+                    }
+                };
+                used.contains(&ident).then_some(param)
+            })
+            .collect();
+        generics
+    }
+}
+
+fn collect_idents(tokens: TokenStream, idents: &mut Vec<Ident>) {
+    for token in tokens {
+        match token {
+            TokenTree::Ident(ident) => idents.push(ident),
+            TokenTree::Group(group) => collect_idents(group.stream(), idents),
+            _ => (),
+        }
+    }
 }
 
 impl<B: Backend> TableField<B> {
+    /// the columns of this field - either the field itself, or the columns of the embedded struct
+    pub fn columns(&self) -> Vec<&TableField<B>> {
+        if self.marker {
+            return vec![];
+        }
+        match self.flatten.as_ref().and_then(|flatten| flatten.fields.as_ref()) {
+            Some(fields) => fields.iter().flat_map(TableField::columns).collect(),
+            None => vec![self],
+        }
+    }
+
+    /// name under which this column is selected.
+    /// For fields of embedded structs, this is `{path}__{field}`.
+    pub fn alias(&self) -> Ident {
+        if self.path.is_empty() {
+            return self.field.clone();
+        }
+        let alias = self
+            .path
+            .iter()
+            .chain(std::iter::once(&self.field))
+            .join("__");
+        Ident::new(&alias, Span::call_site())
+    }
+
+    /// type of this column when querying it.
+    /// Nullable JSON columns are queried as `Option<Json<T>>`, and `Cow<'a, T>` columns as the
+    /// owned `T::Owned`, since rows can't be borrowed from.
+    pub fn row_ty(&self) -> TokenStream {
+        let ty = &self.ty;
+        match (self.json, option_inner(ty), self.cow_inner()) {
+            (true, Some(inner), _) => quote!(Option<sqlx::types::Json<#inner>>),
+            (true, None, _) => quote!(sqlx::types::Json<#ty>),
+            (false, Some(_), Some(cow)) => quote!(Option<<#cow as ToOwned>::Owned>),
+            (false, None, Some(cow)) => quote!(<#cow as ToOwned>::Owned),
+            (false, _, None) => quote!(#ty),
+        }
+    }
+
+    /// if this is a (nullable) `Cow<'a, T>` column, returns `T`
+    pub fn cow_inner(&self) -> Option<&Type> {
+        cow_inner(option_inner(&self.ty).unwrap_or(&self.ty))
+    }
+
     pub fn fmt_for_select(&self) -> String {
-        if self.custom_type {
+        let alias = self.alias();
+        if self.json {
+            // the type is inferred from the `Json<T>` field of the row struct, since spelling it
+            // out could exceed the maximum length of identifiers
+            let nullable = option_inner(&self.ty).map_or("", |_| "?");
+            format!(
+                "{} AS {}{}{}: _{}",
+                self.column(),
+                B::QUOTE,
+                alias,
+                nullable,
+                B::QUOTE
+            )
+        } else if self.custom_type {
             format!(
                 "{} AS {}{}: _{}",
                 self.column(),
                 B::QUOTE,
-                self.field,
+                alias,
                 B::QUOTE
             )
-        } else if self.field == self.column_name {
+        } else if alias == self.column_name {
             self.column().into()
         } else {
-            format!("{} AS {}", self.column(), self.field)
+            format!("{} AS {}", self.column(), alias)
         }
     }
 
-    pub fn fmt_as_argument(&self) -> TokenStream {
+    /// whether the column may be `NULL`, which is the case for fields of type `Option<T>`
+    pub fn nullable(&self) -> bool {
+        option_inner(&self.ty).is_some()
+    }
+
+    /// accesses this field on `self`, following the path through embedded structs
+    pub fn fmt_access(&self) -> TokenStream {
         let ident = &self.field;
+        let path = &self.path;
+        quote!(self #(.#path)* .#ident)
+    }
+
+    /// this column as it's logged into an audit table
+    pub fn audit_column(&self) -> AuditColumn {
+        AuditColumn {
+            name: self.column_name.clone(),
+            column: self.column().into_owned(),
+        }
+    }
+
+    /// checks the constraints of this column, see [Validations::fmt_checks]
+    pub fn fmt_validations(&self) -> TokenStream {
+        let name = self
+            .path
+            .iter()
+            .chain(std::iter::once(&self.field))
+            .join(".");
+        self.validations
+            .fmt_checks(&name, self.fmt_access(), self.nullable())
+    }
+
+    /// reads this column from a queried row
+    pub fn fmt_from_row(&self, row: &Ident) -> TokenStream {
+        let alias = self.alias();
+        self.fmt_unwrap(quote!(#row.#alias))
+    }
+
+    /// reads this column from the column `index` of an untyped row, returned by an unchecked query.
+    /// `row` is a reference to the row.
+    pub fn fmt_from_row_index(&self, row: &TokenStream, index: usize) -> TokenStream {
+        let ty = self.row_ty();
+        self.fmt_unwrap(quote!(sqlx::Row::try_get::<#ty, _>(#row, #index)?))
+    }
+
+    /// converts a `value` of the type returned by [Self::row_ty] into the type of this field
+    fn fmt_unwrap(&self, value: TokenStream) -> TokenStream {
+        match (self.json, option_inner(&self.ty), self.cow_inner()) {
+            (true, Some(_), _) => quote!(#value.map(|json| json.0)),
+            (true, None, _) => quote!(#value.0),
+            (false, Some(_), Some(_)) => quote!(#value.map(std::borrow::Cow::Owned)),
+            (false, None, Some(_)) => quote!(std::borrow::Cow::Owned(#value)),
+            (false, _, None) => value,
+        }
+    }
+
+    pub fn fmt_as_argument(&self, unchecked: bool) -> TokenStream {
         let ty = &self.ty;
 
-        let mut out = quote!(self.#ident);
+        if self.json {
+            let access = self.fmt_access();
+            return json_argument(ty, quote!(&#access), unchecked);
+        }
+        if self.cow_inner().is_some() {
+            return cow_argument(ty, self.fmt_access());
+        }
+        if unchecked {
+            let access = self.fmt_access();
+            return quote!(&#access);
+        }
+
+        let mut out = self.fmt_access();
         let mut ty = quote!(#ty);
         if self.by_ref {
             out = quote!(&#out);
@@ -106,34 +354,172 @@ impl<B: Backend> TableField<B> {
 
 impl Getter {
     pub fn or_fallback<B: Backend>(&self, field: &TableField<B>) -> (Ident, Type) {
+        self.or_fallback_named(field, &format!("by_{}", field.field))
+    }
+
+    pub fn or_fallback_named<B: Backend>(
+        &self,
+        field: &TableField<B>,
+        name: &str,
+    ) -> (Ident, Type) {
         let ident = self
             .func
             .clone()
-            .unwrap_or_else(|| Ident::new(&format!("by_{}", field.field), Span::call_site()));
+            .unwrap_or_else(|| Ident::new(name, Span::call_site()));
         let arg = self.arg_ty.clone().unwrap_or_else(|| {
             let ty = &field.ty;
-            syn::parse2(quote!(&#ty)).unwrap()
+            let arg = match (field.nullable(), field.cow_inner()) {
+                (false, Some(cow)) => quote!(&#cow),
+                (true, Some(cow)) => quote!(&Option<<#cow as ToOwned>::Owned>),
+                (_, None) => quote!(&#ty),
+            };
+            syn::parse2(arg).unwrap()
         });
         (ident, arg)
     }
 }
 
+/// Input of `ormx::__table_callback!`: The table struct and the definitions of all embedded
+/// structs resolved so far, each following the path it was requested by, e.g.
+/// `{ struct User { .. } } (crate::geo::Point) { struct Point { .. } }`.
+pub struct TableCallback {
+    pub input: DeriveInput,
+    pub embeds: Vec<Embed>,
+}
+
+/// The definition of an embedded struct, and the path it was requested by.
+pub struct Embed {
+    pub path: Path,
+    pub input: DeriveInput,
+}
+
+impl Embed {
+    /// whether this is the definition of the struct at `path`, ignoring generic arguments
+    pub fn is_named_by(&self, path: &Path) -> bool {
+        let idents = |path: &Path| {
+            let segments = path
+                .segments
+                .iter()
+                .map(|segment| segment.ident.to_string());
+            (path.leading_colon.is_some(), segments.collect::<Vec<_>>())
+        };
+        idents(&self.path) == idents(path)
+    }
+}
+
+impl Parse for TableCallback {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let content;
+        syn::braced!(content in input);
+        let table = content.parse()?;
+
+        let mut embeds = vec![];
+        while !input.is_empty() {
+            let (path, content);
+            syn::parenthesized!(path in input);
+            syn::braced!(content in input);
+            embeds.push(Embed {
+                path: path.parse()?,
+                input: content.parse()?,
+            });
+        }
+
+        Ok(TableCallback {
+            input: table,
+            embeds,
+        })
+    }
+}
+
 pub fn derive(input: DeriveInput) -> Result<TokenStream> {
-    let parsed = Table::try_from(&input)?;
+    expand(&input, &[])
+}
+
+pub fn derive_callback(callback: TableCallback) -> Result<TokenStream> {
+    expand(&callback.input, &callback.embeds)
+}
+
+/// The definitions of embedded structs are not visible to this macro. If one is missing, it is
+/// requested from the macro generated by `#[derive(ormx::Columns)]`, which expands to a call to
+/// `ormx::__table_callback!` with the definition appended to the input.
+fn request_embed(missing: &Path, input: &DeriveInput, embeds: &[Embed]) -> TokenStream {
+    let mut columns_macro = missing.clone();
+    let last = columns_macro.segments.last_mut().unwrap();
+    last.ident = crate::columns::macro_ident(&last.ident);
+    last.arguments = PathArguments::None;
+    let paths = embeds.iter().map(|embed| &embed.path);
+    let embeds = embeds.iter().map(|embed| &embed.input);
+
+    quote! {
+        #columns_macro! {
+            [ormx::__table_callback]
+            { #input }
+            #( (#paths) { #embeds } )*
+            (#missing)
+        }
+    }
+}
 
+fn expand(input: &DeriveInput, embeds: &[Embed]) -> Result<TokenStream> {
+    let mut parsed = Table::try_from(input)?;
+    if let Some(missing) = parsed.resolve_embeds(embeds)? {
+        return Ok(request_embed(&missing, input, embeds));
+    }
+
+    let row_struct = Implementation::row_struct(&parsed);
     let impl_table = Implementation::impl_table(&parsed);
+    let table_meta = Implementation::impl_table_meta(&parsed);
     let delete = Implementation::impl_delete(&parsed);
+    let hooks = Implementation::impl_hooks(&parsed);
     let insert_struct = Implementation::insert_struct(&parsed);
     let impl_insert = Implementation::impl_insert(&parsed);
     let getters = Implementation::impl_getters(&parsed);
     let setters = Implementation::impl_setters(&parsed);
+    let validate = Implementation::impl_validate(&parsed);
+    let track = Implementation::impl_track(&parsed);
+    let cached = Implementation::impl_cached(&parsed);
+    let factory = Implementation::impl_factory(&parsed);
 
     Ok(quote! {
+        #row_struct
         #impl_table
+        #table_meta
         #delete
+        #hooks
         #insert_struct
         #impl_insert
         #getters
         #setters
+        #validate
+        #track
+        #cached
+        #factory
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use syn::{parse::Parser, Field};
+
+    use super::*;
+
+    fn field(tokens: TokenStream) -> TableField<Implementation> {
+        let field = Field::parse_named.parse2(tokens).unwrap();
+        TableField::try_from(&field).unwrap()
+    }
+
+    #[test]
+    fn json_columns_are_selected_without_their_type() {
+        let q = Implementation::QUOTE;
+        let prefs = field(quote!(#[ormx(json)] prefs: HashMap<String, Vec<String>>));
+        assert_eq!(
+            prefs.fmt_for_select(),
+            format!("prefs AS {0}prefs: _{0}", q)
+        );
+        let extra = field(quote!(#[ormx(json)] extra: Option<Vec<String>>));
+        assert_eq!(
+            extra.fmt_for_select(),
+            format!("extra AS {0}extra?: _{0}", q)
+        );
+    }
+}
@@ -1,13 +1,14 @@
 use std::{convert::TryFrom, marker::PhantomData};
 
 use proc_macro2::Span;
-use syn::{Data, DeriveInput, Error, Ident, Result};
+use syn::{spanned::Spanned, DeriveInput, Error, Ident, Path, Result, Type};
 
-use super::{Table, TableField};
+use super::{Embed, Flatten, Table, TableField};
 use crate::{
-    attrs::{parse_attrs, Insertable, TableAttr, TableFieldAttr},
+    attrs::{parse_attrs, FactoryValue, Insertable, TableAttr, TableFieldAttr},
     backend::Backend,
-    utils::{missing_attr, set_once},
+    utils::{check_conflict, check_owned, is_primitive, missing_attr, named_fields, set_once},
+    validate::Validations,
 };
 
 macro_rules! none {
@@ -31,107 +32,509 @@ impl<B: Backend> TryFrom<&syn::Field> for TableField<B> {
         none!(
             column,
             custom_type,
+            json,
             get_one,
             get_optional,
             get_many,
+            get_many_contains,
             set,
             default,
-            by_ref
+            by_ref,
+            flatten,
+            sql_type,
+            length,
+            range,
+            regex,
+            factory
         );
         let mut insert_attrs = vec![];
+        let mut names = vec![];
 
-        for attr in parse_attrs::<TableFieldAttr>(&value.attrs)? {
+        for (name, attr) in parse_attrs::<TableFieldAttr>(&value.attrs)? {
             match attr {
-                TableFieldAttr::Column(c) => set_once(&mut column, c)?,
-                TableFieldAttr::CustomType(..) => set_once(&mut custom_type, true)?,
-                TableFieldAttr::GetOne(g) => set_once(&mut get_one, g)?,
-                TableFieldAttr::GetOptional(g) => set_once(&mut get_optional, g)?,
-                TableFieldAttr::GetMany(g) => set_once(&mut get_many, g)?,
+                TableFieldAttr::Column(c) => set_once(&mut column, c, &name)?,
+                TableFieldAttr::CustomType(..) => set_once(&mut custom_type, true, &name)?,
+                TableFieldAttr::Json(..) => set_once(&mut json, true, &name)?,
+                TableFieldAttr::GetOne(g) => set_once(&mut get_one, g, &name)?,
+                TableFieldAttr::GetOptional(g) => set_once(&mut get_optional, g, &name)?,
+                TableFieldAttr::GetMany(g) => set_once(&mut get_many, g, &name)?,
+                TableFieldAttr::GetManyContains(g) => {
+                    set_once(&mut get_many_contains, g, &name)?
+                }
                 TableFieldAttr::Set(s) => {
                     let default = || Ident::new(&format!("set_{}", ident), Span::call_site());
-                    set_once(&mut set, s.unwrap_or_else(default))?
+                    set_once(&mut set, s.unwrap_or_else(default), &name)?
                 }
-                TableFieldAttr::Default(..) => set_once(&mut default, true)?,
-                TableFieldAttr::ByRef(..) => set_once(&mut by_ref, true)?,
+                TableFieldAttr::Default(..) => set_once(&mut default, true, &name)?,
+                TableFieldAttr::ByRef(..) => set_once(&mut by_ref, true, &name)?,
                 TableFieldAttr::InsertAttr(mut attr) => insert_attrs.append(&mut attr.0),
+                TableFieldAttr::Flatten(prefix) => {
+                    let default = || format!("{}_", ident);
+                    set_once(&mut flatten, prefix.unwrap_or_else(default), &name)?
+                }
+                TableFieldAttr::SqlType(x) => set_once(&mut sql_type, x, &name)?,
+                TableFieldAttr::Length(x) => set_once(&mut length, x, &name)?,
+                TableFieldAttr::Range(x) => set_once(&mut range, x, &name)?,
+                TableFieldAttr::Regex(x) => set_once(&mut regex, x, &name)?,
+                TableFieldAttr::Factory(x) => set_once(&mut factory, x, &name)?,
             }
+            names.push(name);
+        }
+
+        check_conflict(&names, "json", "custom_type")?;
+        check_conflict(&names, "default", "insert_attribute")?;
+        check_conflict(&names, "default", "factory")?;
+        for other in &[
+            "column",
+            "custom_type",
+            "json",
+            "default",
+            "by_ref",
+            "get_one",
+            "get_optional",
+            "get_many",
+            "get_many_contains",
+            "set",
+            "sql_type",
+            "length",
+            "range",
+            "regex",
+        ] {
+            check_conflict(&names, "flatten", other)?;
+        }
+        if let Some(attr) = names.iter().find(|name| *name == "get_many_contains") {
+            if json.is_none() {
+                return Err(Error::new(
+                    attr.span(),
+                    "#[ormx(get_many_contains)] requires #[ormx(json)]",
+                ));
+            }
+        }
+        if let Some(attr) = names.iter().find(|name| *name == "by_ref") {
+            if is_primitive(&value.ty) {
+                return Err(Error::new(
+                    attr.span(),
+                    "#[ormx(by_ref)] has no effect on `Copy` types",
+                ));
+            }
+        }
+
+        let flatten = match flatten {
+            None => None,
+            Some(prefix) => {
+                let ty = match &value.ty {
+                    Type::Path(ty) if ty.qself.is_none() => ty.path.clone(),
+                    _ => {
+                        return Err(Error::new_spanned(
+                            &value.ty,
+                            "#[ormx(flatten)] requires the field to be a struct",
+                        ))
+                    }
+                };
+                Some(Flatten {
+                    ty,
+                    prefix,
+                    fields: None,
+                })
+            }
+        };
+
+        if flatten.is_none() && !is_phantom_data(&value.ty) {
+            check_owned(&value.ty)?;
         }
+
+        let column_name = column.unwrap_or_else(|| ident.to_string());
         Ok(TableField {
-            column_name: column.unwrap_or_else(|| ident.to_string()),
+            reserved_ident: reserved_ident || needs_quotes::<B>(&column_name),
+            column_name,
             field: ident,
             ty: value.ty.clone(),
             custom_type: custom_type.unwrap_or(false),
-            reserved_ident,
+            json: json.unwrap_or(false),
             default: default.unwrap_or(false),
             get_one,
             get_optional,
             get_many,
+            get_many_contains,
             set,
             by_ref: by_ref.unwrap_or(false),
             insert_attrs,
+            marker: is_phantom_data(&value.ty),
+            flatten,
+            sql_type,
+            path: vec![],
+            validations: Validations {
+                length,
+                range,
+                regex,
+            },
+            factory,
             _phantom: PhantomData,
         })
     }
 }
 
+pub(crate) fn is_phantom_data(ty: &Type) -> bool {
+    match ty {
+        Type::Path(ty) => ty
+            .path
+            .segments
+            .last()
+            .is_some_and(|last| last.ident == "PhantomData"),
+        _ => false,
+    }
+}
+
+impl<B: Backend> TableField<B> {
+    /// Checks that this field can be part of a struct embedded using `#[ormx(flatten)]`.
+    pub fn check_embeddable(&self) -> Result<()> {
+        let unsupported = self.default
+            || self.get_one.is_some()
+            || self.get_optional.is_some()
+            || self.get_many.is_some()
+            || self.get_many_contains.is_some()
+            || self.set.is_some()
+            || self.factory.is_some()
+            || !self.insert_attrs.is_empty();
+        if unsupported {
+            return Err(Error::new(
+                self.field.span(),
+                "fields of embedded structs only support #[ormx(column)], #[ormx(custom_type)], \
+                 #[ormx(json)], #[ormx(by_ref)], #[ormx(sql_type)] and #[ormx(flatten)]",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Resolves the fields of the embedded struct (if any), returning the path of the first
+    /// embedded struct whose definition is missing from `embeds`.
+    fn resolve_embeds(&mut self, embeds: &[Embed]) -> Result<Option<Path>> {
+        let mut path = self.path.clone();
+        path.push(self.field.clone());
+
+        let flatten = match &mut self.flatten {
+            Some(flatten) => flatten,
+            None => return Ok(None),
+        };
+
+        if flatten.fields.is_none() {
+            let embed = match embeds.iter().find(|embed| embed.is_named_by(&flatten.ty)) {
+                Some(embed) => &embed.input,
+                None => return Ok(Some(flatten.ty.clone())),
+            };
+            let mut fields = vec![];
+            for field in named_fields(embed)? {
+                let mut field = TableField::<B>::try_from(field)?;
+                field.check_embeddable()?;
+                field.column_name = format!("{}{}", flatten.prefix, field.column_name);
+                field.reserved_ident = needs_quotes::<B>(&field.column_name);
+                field.path = path.clone();
+                if let Some(nested) = &mut field.flatten {
+                    nested.prefix = format!("{}{}", flatten.prefix, nested.prefix);
+                }
+                fields.push(field);
+            }
+            flatten.fields = Some(fields);
+        }
+
+        for field in flatten.fields.iter_mut().flatten() {
+            if let Some(missing) = field.resolve_embeds(embeds)? {
+                return Ok(Some(missing));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<B: Backend> Table<B> {
+    /// Resolves the fields of all embedded structs, returning the path of the first embedded
+    /// struct whose definition is missing from `embeds`.
+    pub fn resolve_embeds(&mut self, embeds: &[Embed]) -> Result<Option<Path>> {
+        for field in &mut self.fields {
+            if let Some(missing) = field.resolve_embeds(embeds)? {
+                return Ok(Some(missing));
+            }
+        }
+        Ok(None)
+    }
+}
+
 impl<B: Backend> TryFrom<&syn::DeriveInput> for Table<B> {
     type Error = Error;
 
     fn try_from(value: &DeriveInput) -> Result<Self> {
-        let data = match &value.data {
-            Data::Struct(s) => s,
-            _ => panic!("not a struct with named fields"),
-        };
-
-        let fields = data
-            .fields
+        let fields = named_fields(value)?
             .iter()
             .map(TableField::try_from)
             .collect::<Result<Vec<_>>>()?;
 
-        none!(table, id, insertable, deletable);
-        for attr in parse_attrs::<TableAttr>(&value.attrs)? {
+        none!(
+            table, id, insertable, deletable, unchecked, hooks, validate, audit, tracked, cache,
+            factory, tag, memory
+        );
+        for (name, attr) in parse_attrs::<TableAttr>(&value.attrs)? {
             match attr {
-                TableAttr::Table(x) => set_once(&mut table, x)?,
-                TableAttr::Id(x) => set_once(&mut id, x)?,
+                TableAttr::Table(x) => set_once(&mut table, x, &name)?,
+                TableAttr::Id(x) => set_once(&mut id, x, &name)?,
                 TableAttr::Insertable(x) => {
                     let default = || Insertable {
                         attrs: vec![],
                         ident: Ident::new(&format!("Insert{}", value.ident), Span::call_site()),
                     };
-                    set_once(&mut insertable, x.unwrap_or_else(default))?;
+                    set_once(&mut insertable, x.unwrap_or_else(default), &name)?;
+                }
+                TableAttr::Deletable(_) => set_once(&mut deletable, true, &name)?,
+                TableAttr::Unchecked(_) => set_once(&mut unchecked, true, &name)?,
+                TableAttr::Hooks(_) => set_once(&mut hooks, true, &name)?,
+                TableAttr::Validate(_) => set_once(&mut validate, true, &name)?,
+                TableAttr::Audit(x) => {
+                    check_audit(&name)?;
+                    set_once(&mut audit, x, &name)?
+                }
+                TableAttr::Tracked(_) => set_once(&mut tracked, name.clone(), &name)?,
+                TableAttr::Cache(x) => {
+                    if !value.generics.params.is_empty() {
+                        return Err(Error::new(
+                            name.span(),
+                            "#[ormx(cache)] is not supported on generic tables",
+                        ));
+                    }
+                    set_once(&mut cache, x, &name)?
+                }
+                TableAttr::Factory(_) => {
+                    if !value.generics.params.is_empty() {
+                        return Err(Error::new(
+                            name.span(),
+                            "#[ormx(factory)] is not supported on generic tables",
+                        ));
+                    }
+                    set_once(&mut factory, name.clone(), &name)?
+                }
+                TableAttr::Tag(x) => set_once(&mut tag, parse_tag(&name, x)?, &name)?,
+                TableAttr::Memory(_) => {
+                    check_memory(&name)?;
+                    set_once(&mut memory, true, &name)?
                 }
-                TableAttr::Deletable(_) => set_once(&mut deletable, true)?,
             }
         }
 
-        let id = id.ok_or_else(|| missing_attr("id"))?;
+        let id = id.ok_or_else(|| missing_attr("id", &value.ident))?;
         let id = fields
             .iter()
             .find(|field| field.field == id)
-            .ok_or_else(|| {
-                Error::new(
-                    Span::call_site(),
-                    "id does not refer to a field of the struct",
-                )
-            })?
+            .ok_or_else(|| Error::new(id.span(), "id does not refer to a field of the struct"))?
             .clone();
+        if id.flatten.is_some() {
+            return Err(Error::new(
+                id.field.span(),
+                "the id can't be an embedded struct",
+            ));
+        }
+        if id.set.is_some() {
+            return Err(Error::new(
+                id.field.span(),
+                "#[ormx(set)] can't be used on the id of the table",
+            ));
+        }
 
-        if insertable.is_none() && fields.iter().any(|field| field.default) {
+        if insertable.is_none() {
+            if let Some(field) = fields.iter().find(|field| field.default) {
+                return Err(Error::new(
+                    field.field.span(),
+                    "#[ormx(default)] has no effect without #[ormx(insertable = ..)]",
+                ));
+            }
+        }
+
+        if let (Some(factory), None) = (&factory, &insertable) {
             return Err(Error::new(
-                Span::call_site(),
-                "#[ormx(default)] has no effect without #[ormx(insertable = ..)]",
+                factory.span(),
+                "#[ormx(factory)] requires #[ormx(insertable)]",
+            ));
+        }
+        // creating a row using the factory of its own table would never return
+        let parents = fields.iter().filter_map(|field| match &field.factory {
+            Some(FactoryValue::Parent(parent)) => Some(parent),
+            _ => None,
+        });
+        for parent in parents {
+            if parent.is_ident("Self") || parent.is_ident(&value.ident) {
+                return Err(Error::new(
+                    parent.span(),
+                    "a table can't be its own parent; set the field using \
+                     #[ormx(factory = ..)] instead",
+                ));
+            }
+        }
+        if factory.is_none() {
+            if let Some(field) = fields.iter().find(|field| field.factory.is_some()) {
+                return Err(Error::new(
+                    field.field.span(),
+                    "#[ormx(factory = ..)] has no effect without #[ormx(factory)] on the table",
+                ));
+            }
+        }
+
+        if validate.is_none() {
+            if let Some(field) = fields.iter().find(|field| !field.validations.is_empty()) {
+                return Err(Error::new(
+                    field.field.span(),
+                    "validation attributes have no effect without #[ormx(validate)]",
+                ));
+            }
+        }
+
+        if let (Some(tracked), Some(_)) = (&tracked, &audit) {
+            return Err(Error::new(
+                tracked.span(),
+                "#[ormx(tracked)] can't be used together with #[ormx(audit)], since saving \
+                 tracked changes isn't logged",
             ));
         }
 
         Ok(Table {
             ident: value.ident.clone(),
             vis: value.vis.clone(),
-            table: table.ok_or_else(|| missing_attr("table"))?,
+            generics: value.generics.clone(),
+            table: table.ok_or_else(|| missing_attr("table", &value.ident))?,
             id,
             insertable,
             fields,
-            deletable: deletable.unwrap_or(false)
+            deletable: deletable.unwrap_or(false),
+            unchecked: unchecked.unwrap_or(cfg!(feature = "unchecked")),
+            hooks: hooks.unwrap_or(false),
+            validate: validate.unwrap_or(false),
+            audit,
+            tracked: tracked.is_some(),
+            cache,
+            factory: factory.is_some(),
+            tag,
+            memory: memory.unwrap_or(false),
         })
     }
 }
+
+/// Audit logs are written using data-modifying `WITH` queries, which only postgres supports.
+pub(crate) fn check_audit(attr: &Ident) -> Result<()> {
+    match cfg!(feature = "postgres") {
+        true => Ok(()),
+        false => Err(Error::new(
+            attr.span(),
+            "#[ormx(audit)] is only supported on postgres",
+        )),
+    }
+}
+
+/// The code reading from `ormx::memory::Store` needs the dependencies enabled by the `memory`
+/// feature of ormx.
+pub(crate) fn check_memory(attr: &Ident) -> Result<()> {
+    match cfg!(feature = "memory") {
+        true => Ok(()),
+        false => Err(Error::new(
+            attr.span(),
+            "#[ormx(memory)] requires the `memory` feature of ormx",
+        )),
+    }
+}
+
+/// The prefix of the comments prepended to statements, given using `#[ormx(tag [= ..])]`.
+///
+/// Besides `*/`, which would end the comment early, `/*` is rejected because postgres nests
+/// comments, and so is a leading `+` or `!`, which would turn the comment into an optimizer hint
+/// or a MySQL executable comment.
+pub(crate) fn parse_tag(attr: &Ident, tag: Option<String>) -> Result<String> {
+    let tag = tag.unwrap_or_else(|| "ormx".to_owned());
+    let error = |message| Err(Error::new(attr.span(), message));
+    if tag.contains("*/") || tag.contains("/*") {
+        error("the tag can't contain `/*` or `*/`")
+    } else if tag.starts_with(['+', '!']) {
+        error("the tag can't start with `+` or `!`")
+    } else {
+        Ok(tag)
+    }
+}
+
+/// whether the column has to be quoted, either because it's a reserved keyword or because it
+/// contains characters which would otherwise be changed or rejected, e.g. in `"firstName"`
+fn needs_quotes<B: Backend>(column: &str) -> bool {
+    B::RESERVED_IDENTS.contains(&&*column.to_uppercase())
+        || !column
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse_quote;
+
+    use super::*;
+    use crate::backend::Implementation;
+
+    fn table(input: DeriveInput) -> Result<Table<Implementation>> {
+        Table::try_from(&input)
+    }
+
+    fn tag(tag: Option<&str>) -> Result<String> {
+        let attr = Ident::new("tag", Span::call_site());
+        parse_tag(&attr, tag.map(str::to_owned))
+    }
+
+    #[test]
+    fn parse_tag_defaults_to_ormx() {
+        assert_eq!(tag(None).unwrap(), "ormx");
+        assert_eq!(tag(Some("billing-service")).unwrap(), "billing-service");
+        assert_eq!(tag(Some("a+b!")).unwrap(), "a+b!");
+    }
+
+    #[test]
+    fn parse_tag_rejects_comment_delimiters() {
+        for invalid in ["billing */", "/* billing", "*/", "a/*b"] {
+            let error = tag(Some(invalid)).unwrap_err().to_string();
+            assert_eq!(error, "the tag can't contain `/*` or `*/`");
+        }
+    }
+
+    #[test]
+    fn parse_tag_rejects_hints() {
+        for invalid in ["+ INDEX(users)", "!50001 billing", "+", "!"] {
+            let error = tag(Some(invalid)).unwrap_err().to_string();
+            assert_eq!(error, "the tag can't start with `+` or `!`");
+        }
+    }
+
+    #[test]
+    fn factory_parent_can_be_another_table() {
+        let node = table(parse_quote! {
+            #[ormx(table = "nodes", id = id, insertable, factory)]
+            struct Node {
+                #[ormx(default)]
+                id: i32,
+                #[ormx(factory(parent = Tree))]
+                tree_id: i32,
+            }
+        });
+        assert!(node.is_ok());
+    }
+
+    #[test]
+    fn factory_parent_can_not_be_the_table_itself() {
+        for parent in [quote::quote!(Self), quote::quote!(Node)] {
+            let error = table(parse_quote! {
+                #[ormx(table = "nodes", id = id, insertable, factory)]
+                struct Node {
+                    #[ormx(default)]
+                    id: i32,
+                    #[ormx(factory(parent = #parent))]
+                    parent_id: i32,
+                }
+            })
+            .err()
+            .unwrap();
+            assert_eq!(
+                error.to_string(),
+                "a table can't be its own parent; set the field using #[ormx(factory = ..)] \
+                 instead"
+            );
+        }
+    }
+}
@@ -1,6 +1,9 @@
-use proc_macro2::{Span, TokenStream};
-use quote::quote;
-use syn::{Error, Result};
+use proc_macro2::{Ident, TokenStream, TokenTree};
+use quote::{quote, ToTokens};
+use syn::{
+    punctuated::Punctuated, Data, DataStruct, DeriveInput, Error, Expr, Field, Fields,
+    GenericArgument, Lifetime, PathArguments, Result, Token, Type,
+};
 
 pub fn box_future() -> TokenStream {
     quote!(ormx::exports::futures::future::BoxFuture)
@@ -10,16 +13,472 @@ pub fn box_stream() -> TokenStream {
     quote!(ormx::exports::futures::stream::BoxStream)
 }
 
-pub fn set_once<T>(opt: &mut Option<T>, v: T) -> Result<()> {
+/// wraps `future`, which runs the queries of the generated method `operation`, so that it's
+/// reported to `ormx::observe`. With the `tracing` feature, `id` is recorded in its span.
+pub fn observe(
+    table: &str,
+    operation: &str,
+    id: Option<TokenStream>,
+    future: TokenStream,
+) -> TokenStream {
+    observe_with(
+        quote!(future),
+        table,
+        operation,
+        id,
+        Some(quote!(None)),
+        future,
+    )
+}
+
+/// like [observe], for a method reading rows by `argument`, e.g. `get` or a getter, whose calls
+/// are counted by `ormx::n_plus_one`. It must be evaluated in a `#[track_caller]` function
+/// outside of the returned future, so that the location of the call is known.
+pub fn observe_read(
+    table: &str,
+    operation: &str,
+    id: Option<TokenStream>,
+    argument: TokenStream,
+    future: TokenStream,
+) -> TokenStream {
+    // hashes the argument if it implements `Hash`, see `ormx::n_plus_one::Key`
+    let call = quote! {{
+        #[allow(unused_imports)]
+        use ormx::n_plus_one::{HashKey as _, NoKey as _};
+        Some(ormx::n_plus_one::Call::new((&ormx::n_plus_one::Key(&#argument)).key()))
+    }};
+    observe_with(quote!(future), table, operation, id, Some(call), future)
+}
+
+/// like [observe], for a method which modifies rows. `future` evaluates to
+/// `ormx::observe::Modified`, containing the number of modified rows, and is mapped to `()`.
+pub fn observe_modifying(
+    table: &str,
+    operation: &str,
+    id: Option<TokenStream>,
+    future: TokenStream,
+) -> TokenStream {
+    observe_with(quote!(modifying), table, operation, id, None, future)
+}
+
+/// `call` is the `Option<ormx::n_plus_one::Call>` passed to `ormx::observe::future`, and `None`
+/// for `ormx::observe::modifying`
+fn observe_with(
+    function: TokenStream,
+    table: &str,
+    operation: &str,
+    id: Option<TokenStream>,
+    call: Option<TokenStream>,
+    future: TokenStream,
+) -> TokenStream {
+    let id = match id {
+        Some(id) if cfg!(feature = "tracing") => quote!(Some(&#id as &dyn std::fmt::Debug)),
+        _ => quote!(None),
+    };
+    let call = call.map(|call| quote!(#call,));
+    quote! {
+        ormx::observe::#function(
+            ormx::observe::Query { table: #table, operation: #operation },
+            #id,
+            #call
+            #future,
+        )
+    }
+}
+
+/// wraps `stream`, which streams the rows queried by the generated method `operation`, so that
+/// it's reported to `ormx::observe`
+pub fn observe_stream(table: &str, operation: &str, stream: TokenStream) -> TokenStream {
+    quote! {
+        ormx::observe::stream(
+            ormx::observe::Query { table: #table, operation: #operation },
+            #stream,
+        )
+    }
+}
+
+/// prepends a comment like `/* ormx:table=users,op=get */` to `sql` if the table or patch has
+/// `#[ormx(tag)]`, attributing the statement to the generated method `operation`
+pub fn tag_sql(tag: Option<&str>, table: &str, operation: &str, sql: String) -> String {
+    match tag {
+        Some(tag) => format!("/* {}:table={},op={} */ {}", tag, table, operation, sql),
+        None => sql,
+    }
+}
+
+pub fn set_once<T>(opt: &mut Option<T>, v: T, attr: &Ident) -> Result<()> {
     match opt.replace(v) {
         None => Ok(()),
-        Some(_) => Err(Error::new(Span::call_site(), "duplicate attribute")),
+        Some(_) => Err(Error::new(
+            attr.span(),
+            format!("duplicate #[ormx({})] attribute", attr),
+        )),
     }
 }
 
-pub fn missing_attr(attr: &str) -> Error {
+/// Returns an error pointing at `b` if both of the attributes `a` and `b` are present.
+pub fn check_conflict(attrs: &[Ident], a: &str, b: &str) -> Result<()> {
+    let a_present = attrs.iter().any(|attr| attr == a);
+    match attrs.iter().find(|attr| *attr == b) {
+        Some(b_attr) if a_present => Err(Error::new(
+            b_attr.span(),
+            format!("#[ormx({})] can't be combined with #[ormx({})]", b, a),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// `item` is the struct the attribute is missing on
+pub fn missing_attr(attr: &str, item: &Ident) -> Error {
     Error::new(
-        Span::call_site(),
+        item.span(),
         format!(r#"missing #[ormx({})] attribute"#, attr),
     )
 }
+
+pub fn unknown_attr(attr: &Ident, valid: &[&str]) -> Error {
+    let name = attr.to_string();
+    let suggestion = valid
+        .iter()
+        .map(|candidate| (edit_distance(&name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| format!(" - did you mean `{}`?", candidate))
+        .unwrap_or_default();
+    Error::new(
+        attr.span(),
+        format!(
+            "unknown attribute `{}`{}\nexpected one of: {}",
+            name,
+            suggestion,
+            valid.join(", ")
+        ),
+    )
+}
+
+/// levenshtein distance between `a` and `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + (ca != *cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Returns the named fields of a struct, or an error if the input is not such a struct.
+pub fn named_fields(input: &DeriveInput) -> Result<&Punctuated<Field, Token![,]>> {
+    let message = "ormx only supports structs with named fields";
+    match &input.data {
+        Data::Struct(DataStruct {
+            fields: Fields::Named(fields),
+            ..
+        }) => Ok(&fields.named),
+        Data::Struct(DataStruct {
+            fields: Fields::Unnamed(fields),
+            ..
+        }) => Err(Error::new_spanned(fields, message)),
+        Data::Struct(DataStruct {
+            fields: Fields::Unit,
+            ..
+        }) => Err(Error::new(input.ident.span(), message)),
+        Data::Enum(data) => Err(Error::new(data.enum_token.span, message)),
+        Data::Union(data) => Err(Error::new(data.union_token.span, message)),
+    }
+}
+
+/// Checks if `ty` is a primitive type which is known to be `Copy`.
+pub fn is_primitive(ty: &Type) -> bool {
+    const PRIMITIVES: &[&str] = &[
+        "bool", "char", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64",
+        "u128", "usize", "f32", "f64",
+    ];
+    match ty {
+        Type::Path(ty) if ty.qself.is_none() => ty
+            .path
+            .get_ident()
+            .is_some_and(|ident| PRIMITIVES.contains(&&*ident.to_string())),
+        Type::Reference(ty) => ty.mutability.is_none(),
+        _ => false,
+    }
+}
+
+/// replaces the elided lifetimes in `ty`, e.g. of `&str` or `Cow<'_, str>`, with `lifetime`
+pub fn with_lifetime(ty: &Type, lifetime: &Lifetime) -> Type {
+    fn replace(ty: &mut Type, lifetime: &Lifetime) {
+        let elided = |l: &Option<Lifetime>| l.as_ref().is_none_or(|l| l.ident == "_");
+        match ty {
+            Type::Reference(ty) => {
+                if elided(&ty.lifetime) {
+                    ty.lifetime = Some(lifetime.clone());
+                }
+                replace(&mut ty.elem, lifetime);
+            }
+            Type::Path(ty) => {
+                for segment in ty.path.segments.iter_mut() {
+                    if let PathArguments::AngleBracketed(args) = &mut segment.arguments {
+                        for arg in args.args.iter_mut() {
+                            match arg {
+                                GenericArgument::Type(ty) => replace(ty, lifetime),
+                                GenericArgument::Lifetime(l) if l.ident == "_" => {
+                                    *l = lifetime.clone()
+                                }
+                                _ => (),
+                            }
+                        }
+                    }
+                }
+            }
+            Type::Slice(ty) => replace(&mut ty.elem, lifetime),
+            Type::Array(ty) => replace(&mut ty.elem, lifetime),
+            Type::Paren(ty) => replace(&mut ty.elem, lifetime),
+            Type::Group(ty) => replace(&mut ty.elem, lifetime),
+            Type::Tuple(ty) => ty.elems.iter_mut().for_each(|ty| replace(ty, lifetime)),
+            _ => (),
+        }
+    }
+
+    let mut ty = ty.clone();
+    replace(&mut ty, lifetime);
+    ty
+}
+
+/// if `ty` is an `Option<T>`, returns `T`
+pub fn option_inner(ty: &Type) -> Option<&Type> {
+    match type_name(ty)? {
+        (ident, Some(inner)) if ident == "Option" => Some(inner),
+        _ => None,
+    }
+}
+
+/// returns the name of the type `ty` and its generic argument, if it has exactly one.
+/// For `std::vec::Vec<u8>`, this is `(Vec, Some(u8))`.
+pub fn type_name(ty: &Type) -> Option<(&Ident, Option<&Type>)> {
+    let path = match ty {
+        Type::Path(ty) if ty.qself.is_none() => &ty.path,
+        _ => return None,
+    };
+    let last = path.segments.last()?;
+    let arg = match &last.arguments {
+        PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    };
+    Some((&last.ident, arg))
+}
+
+/// if `ty` is a `Cow<'_, T>`, returns `T`
+pub fn cow_inner(ty: &Type) -> Option<&Type> {
+    let last = match ty {
+        Type::Path(ty) if ty.qself.is_none() => ty.path.segments.last()?,
+        _ => return None,
+    };
+    match &last.arguments {
+        PathArguments::AngleBracketed(args) if last.ident == "Cow" => {
+            args.args.iter().find_map(|arg| match arg {
+                GenericArgument::Type(inner) => Some(inner),
+                _ => None,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// borrows the value of a `Cow<'_, T>` column of type `ty` as `&T`, or `Option<&T>` if the
+/// column is nullable
+pub fn cow_argument(ty: &Type, value: TokenStream) -> TokenStream {
+    match option_inner(ty) {
+        Some(_) => quote!((#value).as_deref()),
+        None => quote!(&*#value),
+    }
+}
+
+/// returns the first lifetime other than `'static` used by `tokens`, e.g. `'a` for `&'a str`
+pub fn borrowed_lifetime(tokens: impl ToTokens) -> Option<TokenStream> {
+    let mut tokens = tokens.into_token_stream().into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Punct(punct) if punct.as_char() == '\'' => match tokens.peek() {
+                Some(TokenTree::Ident(ident)) if ident != "static" => {
+                    return Some(quote!(#punct #ident));
+                }
+                _ => (),
+            },
+            TokenTree::Group(group) => {
+                if let Some(lifetime) = borrowed_lifetime(group.stream()) {
+                    return Some(lifetime);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Since rows are decoded into owned values, columns can't borrow from the database, except for
+/// `Cow<'a, T>` columns, which are decoded into `Cow::Owned`.
+pub fn check_owned(ty: &Type) -> Result<()> {
+    let ty = option_inner(ty).unwrap_or(ty);
+    match borrowed_lifetime(cow_inner(ty).unwrap_or(ty)) {
+        Some(lifetime) => Err(Error::new_spanned(
+            lifetime,
+            "columns can't borrow from the database, consider using `Cow<'_, T>` instead",
+        )),
+        None => Ok(()),
+    }
+}
+
+/// wraps a reference to the value of a JSON column of type `ty` in `sqlx::types::Json`
+pub fn json_argument(ty: &Type, value: TokenStream, unchecked: bool) -> TokenStream {
+    let cast = (!unchecked).then(|| quote!(as _));
+    match option_inner(ty) {
+        Some(_) => quote!((#value).as_ref().map(sqlx::types::Json) #cast),
+        None => quote!(sqlx::types::Json(#value) #cast),
+    }
+}
+
+/// Expands to `sqlx::query!`, or, if `unchecked` is set, to `sqlx::query` binding the arguments
+/// at runtime.
+pub fn query(
+    unchecked: bool,
+    sql: impl ToTokens,
+    args: impl IntoIterator<Item = TokenStream>,
+) -> TokenStream {
+    let args = args.into_iter().collect::<Vec<_>>();
+    let query = match unchecked {
+        true => quote!(sqlx::query(#sql) #( .bind(#args) )*),
+        false => quote!(sqlx::query!(#sql, #( #args, )*)),
+    };
+    with_arguments(&args, query)
+}
+
+/// wraps `query`, which binds `args`, so that the arguments are recorded by
+/// `ormx::testing::MockExecutor`
+pub fn with_arguments(args: &[TokenStream], query: TokenStream) -> TokenStream {
+    let args = args.iter().map(strip_type_override);
+    quote! {
+        ormx::testing::with_arguments(
+            ormx::testing::arguments(|| {
+                #[allow(unused_imports)]
+                use ormx::testing::{DebugArgument as _, NoDebug as _};
+                vec![#( (&ormx::testing::Argument(&#args)).debug() ),*]
+            }),
+            #query,
+        )
+    }
+}
+
+/// removes the type overrides of an argument of `sqlx::query!`, e.g. `self.id as _`, which are
+/// only understood by the macro
+fn strip_type_override(arg: &TokenStream) -> TokenStream {
+    fn strip(expr: Expr) -> Expr {
+        match expr {
+            Expr::Cast(cast) => strip(*cast.expr),
+            Expr::Paren(mut paren) => {
+                *paren.expr = strip(*paren.expr);
+                Expr::Paren(paren)
+            }
+            Expr::Reference(mut reference) => {
+                *reference.expr = strip(*reference.expr);
+                Expr::Reference(reference)
+            }
+            expr => expr,
+        }
+    }
+
+    match syn::parse2::<Expr>(arg.clone()) {
+        Ok(expr) => strip(expr).into_token_stream(),
+        Err(_) => arg.clone(),
+    }
+}
+
+/// Expands to `sqlx::query_as!`, or, if `unchecked` is set, to `sqlx::query_as` binding the
+/// arguments at runtime. In this case, `row_ty` has to implement `sqlx::FromRow`.
+pub fn query_as(
+    unchecked: bool,
+    row_ty: &TokenStream,
+    sql: &str,
+    args: impl IntoIterator<Item = TokenStream>,
+) -> TokenStream {
+    let args = args.into_iter().collect::<Vec<_>>();
+    let query = match unchecked {
+        true => quote!(sqlx::query_as::<_, #row_ty>(#sql) #( .bind(#args) )*),
+        false => quote!(sqlx::query_as!(#row_ty, #sql, #( #args, )*)),
+    };
+    with_arguments(&args, query)
+}
+
+#[cfg(test)]
+mod tests {
+    use proc_macro2::Span;
+    use syn::parse_quote;
+
+    use super::*;
+
+    #[test]
+    fn edit_distance_counts_edits() {
+        assert_eq!(edit_distance("table", "table"), 0);
+        assert_eq!(edit_distance("tabel", "table"), 2);
+        assert_eq!(edit_distance("tables", "table"), 1);
+        assert_eq!(edit_distance("able", "table"), 1);
+        assert_eq!(edit_distance("get_one", "get_many"), 3);
+        assert_eq!(edit_distance("", "id"), 2);
+        assert_eq!(edit_distance("id", ""), 2);
+    }
+
+    #[test]
+    fn unknown_attr_suggests_closest_attribute() {
+        let valid = ["table", "id", "insertable", "deletable"];
+        let message = |attr: &str| {
+            let attr = Ident::new(attr, Span::call_site());
+            unknown_attr(&attr, &valid).to_string()
+        };
+
+        assert!(message("tabel").contains("did you mean `table`?"));
+        assert!(message("insertible").contains("did you mean `insertable`?"));
+        assert!(!message("something").contains("did you mean"));
+        assert!(message("something").ends_with("expected one of: table, id, insertable, deletable"));
+    }
+
+    #[test]
+    fn tag_sql_prepends_comment() {
+        let sql = "SELECT id FROM users WHERE id = $1".to_owned();
+        assert_eq!(
+            tag_sql(Some("ormx"), "users", "get", sql.clone()),
+            "/* ormx:table=users,op=get */ SELECT id FROM users WHERE id = $1"
+        );
+        assert_eq!(
+            tag_sql(Some("billing"), "users", "by_email", sql.clone()),
+            "/* billing:table=users,op=by_email */ SELECT id FROM users WHERE id = $1"
+        );
+    }
+
+    #[test]
+    fn tag_sql_without_tag_is_unchanged() {
+        let sql = "DELETE FROM users WHERE id = $1".to_owned();
+        assert_eq!(tag_sql(None, "users", "delete_row", sql.clone()), sql);
+    }
+
+    #[test]
+    fn with_lifetime_replaces_elided_lifetimes() {
+        let lifetime = Lifetime::new("'__a", Span::call_site());
+        let replaced = |ty: Type| with_lifetime(&ty, &lifetime).to_token_stream().to_string();
+
+        assert_eq!(replaced(parse_quote!(&str)), "& '__a str");
+        assert_eq!(
+            replaced(parse_quote!(Option<&[u8]>)),
+            "Option < & '__a [u8] >"
+        );
+        assert_eq!(replaced(parse_quote!(Cow<'_, str>)), "Cow < '__a , str >");
+        assert_eq!(replaced(parse_quote!(&'b str)), "& 'b str");
+        assert_eq!(replaced(parse_quote!(i32)), "i32");
+    }
+}
@@ -0,0 +1,110 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Expr, Generics, Ident};
+
+use crate::attrs::Bounds;
+
+/// The constraints of a field, which are checked by `ormx::Validate`.
+#[derive(Clone, Default)]
+pub struct Validations {
+    pub length: Option<Bounds>,
+    pub range: Option<Bounds>,
+    pub regex: Option<Expr>,
+}
+
+impl Validations {
+    pub fn is_empty(&self) -> bool {
+        self.length.is_none() && self.range.is_none() && self.regex.is_none()
+    }
+
+    /// checks the value of a field, pushing the violated constraints into `errors`.
+    /// `access` is the field on `self`, and `name` the name used in the error.
+    pub fn fmt_checks(&self, name: &str, access: TokenStream, nullable: bool) -> TokenStream {
+        if self.is_empty() {
+            return quote!();
+        }
+
+        let mut checks = vec![];
+        if let Some(Bounds { min, max }) = &self.length {
+            let conditions = bound_conditions(
+                quote!(ormx::validate::Length::length(value)),
+                min.as_ref().map(|min| quote!(#min)),
+                max.as_ref().map(|max| quote!(#max)),
+            );
+            let (min, max) = (fmt_option(min), fmt_option(max));
+            checks.push(quote! {
+                if !(#conditions) {
+                    errors.push(#name, ormx::ValidationErrorKind::Length { min: #min, max: #max });
+                }
+            });
+        }
+        if let Some(Bounds { min, max }) = &self.range {
+            let conditions = bound_conditions(
+                quote!(value),
+                min.as_ref().map(|min| quote!(&(#min))),
+                max.as_ref().map(|max| quote!(&(#max))),
+            );
+            let min = fmt_option(&min.as_ref().map(fmt_expr));
+            let max = fmt_option(&max.as_ref().map(fmt_expr));
+            checks.push(quote! {
+                if !(#conditions) {
+                    errors.push(#name, ormx::ValidationErrorKind::Range { min: #min, max: #max });
+                }
+            });
+        }
+        if let Some(regex) = &self.regex {
+            checks.push(quote! {
+                if !(#regex).is_match(value) {
+                    errors.push(#name, ormx::ValidationErrorKind::Regex);
+                }
+            });
+        }
+
+        match nullable {
+            true => quote!(if let Some(value) = &#access { #(#checks)* }),
+            false => quote!({ let value = &#access; #(#checks)* }),
+        }
+    }
+}
+
+/// implements `ormx::Validate` for a struct, given the checks of its fields
+pub fn impl_validate(
+    ident: &Ident,
+    generics: &Generics,
+    checks: impl Iterator<Item = TokenStream>,
+) -> TokenStream {
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    quote! {
+        impl #impl_generics ormx::Validate for #ident #ty_generics #where_clause {
+            fn validate(&self) -> Result<(), ormx::ValidationErrors> {
+                #[allow(unused_mut)]
+                let mut errors = ormx::ValidationErrors::default();
+                #( #checks )*
+                errors.into_result()
+            }
+        }
+    }
+}
+
+fn bound_conditions(
+    value: TokenStream,
+    min: Option<TokenStream>,
+    max: Option<TokenStream>,
+) -> TokenStream {
+    let min = min.map(|min| quote!(#value >= #min));
+    let max = max.map(|max| quote!(#value <= #max));
+    let conditions = min.into_iter().chain(max);
+    quote!(#( #conditions )&&*)
+}
+
+fn fmt_option<T: quote::ToTokens>(value: &Option<T>) -> TokenStream {
+    match value {
+        Some(value) => quote!(Some(#value)),
+        None => quote!(None),
+    }
+}
+
+/// formats a bound as it was written, e.g. `-1` instead of `- 1`
+fn fmt_expr(expr: &Expr) -> String {
+    quote!(#expr).to_string().replace(' ', "")
+}
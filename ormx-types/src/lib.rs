@@ -0,0 +1,151 @@
+//! Mapping between rust and SQL types, shared by `ormx`, `ormx-macros` and `ormx-cli`.
+//!
+//! The types of columns are derived from rust types by `#[derive(ormx::Table)]` when generating
+//! `CREATE TABLE` statements, and by `ormx-cli` when writing migrations. Both `ormx::schema` and
+//! `ormx-cli` compare SQL types using [type_family].
+
+pub mod mysql;
+pub mod postgres;
+
+/// Keywords which end the type in a column definition like `TIMESTAMP NOT NULL DEFAULT now()`.
+const CONSTRAINTS: &[&str] = &[
+    " default",
+    " not null",
+    " null",
+    " primary",
+    " unique",
+    " references",
+    " check",
+    " generated",
+    " auto_increment",
+    " collate",
+    " character set",
+    " comment",
+    " constraint",
+    " on update",
+];
+
+/// Removes constraints from a column definition, e.g. `TIMESTAMP DEFAULT now()` becomes
+/// `TIMESTAMP`.
+pub fn strip_constraints(definition: &str) -> &str {
+    let lower = definition.to_lowercase();
+    let end = CONSTRAINTS
+        .iter()
+        .filter_map(|keyword| lower.find(keyword))
+        .min()
+        .unwrap_or(definition.len());
+    definition[..end].trim()
+}
+
+/// Normalizes a SQL type, so that aliases like `INT` and `integer` compare as equal.
+/// Constraints, like in `#[ormx(sql_type = "TEXT NOT NULL")]`, are ignored.
+pub fn type_family(ty: &str) -> String {
+    let ty = strip_constraints(ty).to_lowercase();
+
+    // mysql uses TINYINT(1) for booleans
+    if ty == "tinyint(1)" {
+        return "boolean".to_owned();
+    }
+
+    let (ty, array) = match ty.strip_suffix("[]") {
+        Some(ty) => (ty.trim(), "[]"),
+        None => (&*ty, ""),
+    };
+    let ty = match ty.find('(') {
+        Some(start) => {
+            let end = ty.rfind(')').map_or(ty.len(), |end| end + 1);
+            format!("{}{}", &ty[..start], &ty[end..])
+        }
+        None => ty.to_owned(),
+    };
+    let ty = ty.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let family = match &*ty {
+        "int" | "integer" | "int4" | "serial" | "serial4" | "mediumint" => "integer",
+        "bigint" | "int8" | "bigserial" | "serial8" => "bigint",
+        "smallint" | "int2" | "smallserial" | "serial2" => "smallint",
+        "int unsigned" | "integer unsigned" => "integer unsigned",
+        "text" | "varchar" | "character varying" | "char" | "character" | "bpchar" | "tinytext"
+        | "mediumtext" | "longtext" => "text",
+        "timestamp" | "timestamp without time zone" | "datetime" => "timestamp",
+        "timestamptz" | "timestamp with time zone" => "timestamptz",
+        "time" | "time without time zone" => "time",
+        "timetz" | "time with time zone" => "timetz",
+        "double precision" | "double" | "float8" => "double",
+        "real" | "float4" | "float" => "real",
+        "boolean" | "bool" => "boolean",
+        "json" | "jsonb" => "json",
+        "numeric" | "decimal" => "numeric",
+        "bytea" | "blob" | "binary" | "varbinary" | "tinyblob" | "mediumblob" | "longblob" => {
+            "binary"
+        }
+        other => other,
+    };
+    format!("{}{}", family, array)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_constraints() {
+        assert_eq!(strip_constraints("TIMESTAMP DEFAULT now()"), "TIMESTAMP");
+        assert_eq!(
+            strip_constraints(" INTEGER NOT NULL PRIMARY KEY"),
+            "INTEGER"
+        );
+        assert_eq!(strip_constraints("text null"), "text");
+        assert_eq!(
+            strip_constraints("INT UNSIGNED AUTO_INCREMENT"),
+            "INT UNSIGNED"
+        );
+        assert_eq!(
+            strip_constraints("varchar(128) CHARACTER SET utf8mb4 COLLATE utf8mb4_bin"),
+            "varchar(128)"
+        );
+        assert_eq!(strip_constraints("datetime ON UPDATE now()"), "datetime");
+        assert_eq!(strip_constraints("NUMERIC(10, 2)[]"), "NUMERIC(10, 2)[]");
+    }
+
+    #[test]
+    fn aliases_share_a_family() {
+        assert_eq!(type_family("INT"), type_family("integer"));
+        assert_eq!(type_family("SERIAL"), type_family("int4"));
+        assert_eq!(type_family("BIGSERIAL"), type_family("int8"));
+        assert_eq!(type_family("VARCHAR(255)"), type_family("text"));
+        assert_eq!(type_family("character varying"), type_family("TEXT"));
+        assert_eq!(type_family("TIMESTAMP"), type_family("datetime"));
+        assert_eq!(type_family("jsonb"), type_family("JSON"));
+        assert_eq!(type_family("BYTEA"), type_family("longblob"));
+        assert_eq!(type_family("tinyint(1)"), type_family("BOOLEAN"));
+    }
+
+    #[test]
+    fn different_types_differ() {
+        assert_ne!(type_family("INTEGER"), type_family("BIGINT"));
+        assert_ne!(type_family("TIMESTAMP"), type_family("TIMESTAMPTZ"));
+        assert_ne!(type_family("INTEGER[]"), type_family("INTEGER"));
+        assert_ne!(type_family("tinyint(4)"), type_family("boolean"));
+    }
+
+    #[test]
+    fn constraints_are_ignored() {
+        assert_eq!(type_family("TEXT NOT NULL"), "text");
+        assert_eq!(type_family("INTEGER PRIMARY KEY"), "integer");
+        assert_eq!(type_family("TIMESTAMP DEFAULT now()"), "timestamp");
+        assert_eq!(
+            type_family("INT UNSIGNED AUTO_INCREMENT"),
+            "integer unsigned"
+        );
+        assert_eq!(type_family("INTEGER REFERENCES users (id)"), "integer");
+    }
+
+    #[test]
+    fn arrays_and_parameters() {
+        assert_eq!(type_family("int4[]"), "integer[]");
+        assert_eq!(type_family("VARCHAR(32) []"), "text[]");
+        assert_eq!(type_family("NUMERIC(10, 2)"), "numeric");
+        assert_eq!(type_family("user_role"), "user_role");
+    }
+}
@@ -0,0 +1,39 @@
+//! Types of mysql columns.
+
+/// The type of columns annotated with `#[ormx(json)]`.
+pub const JSON_TYPE: &str = "JSON";
+
+/// Returns the SQL type for a rust type, given by the name of its last path segment and its
+/// generic argument, e.g. `("Vec", Some("u8"))`.
+pub fn sql_type(name: &str, arg: Option<&str>) -> Option<String> {
+    let ty = match (name, arg) {
+        ("bool", None) => "BOOLEAN",
+        ("i8", None) => "TINYINT",
+        ("i16", None) => "SMALLINT",
+        ("i32", None) => "INT",
+        ("i64", None) => "BIGINT",
+        ("u8", None) => "TINYINT UNSIGNED",
+        ("u16", None) => "SMALLINT UNSIGNED",
+        ("u32", None) => "INT UNSIGNED",
+        ("u64", None) => "BIGINT UNSIGNED",
+        ("f32", None) => "FLOAT",
+        ("f64", None) => "DOUBLE",
+        // TEXT columns can't be part of an index without specifying a prefix length
+        ("String", None) => "VARCHAR(255)",
+        ("Vec", Some("u8")) => "BLOB",
+        ("NaiveDateTime", None) | ("PrimitiveDateTime", None) => "DATETIME",
+        ("DateTime", _) | ("OffsetDateTime", None) => "TIMESTAMP",
+        ("NaiveDate", None) | ("Date", None) => "DATE",
+        ("NaiveTime", None) | ("Time", None) => "TIME",
+        ("Uuid", None) => "BINARY(16)",
+        ("Decimal", None) | ("BigDecimal", None) => "DECIMAL",
+        ("Value", None) | ("JsonValue", None) | ("Json", _) => JSON_TYPE,
+        _ => return None,
+    };
+    Some(ty.to_owned())
+}
+
+/// Returns the type of an ID column generated by the database, e.g. `INT AUTO_INCREMENT`.
+pub fn serial_type(sql_type: &str) -> String {
+    format!("{} AUTO_INCREMENT", sql_type)
+}
@@ -0,0 +1,42 @@
+//! Types of postgres columns.
+
+/// The type of columns annotated with `#[ormx(json)]`.
+pub const JSON_TYPE: &str = "JSONB";
+
+/// Returns the SQL type for a rust type, given by the name of its last path segment and its
+/// generic argument, e.g. `("Vec", Some("u8"))`.
+pub fn sql_type(name: &str, arg: Option<&str>) -> Option<String> {
+    let ty = match (name, arg) {
+        ("bool", None) => "BOOLEAN",
+        ("i8", None) => "\"char\"",
+        ("i16", None) => "SMALLINT",
+        ("i32", None) => "INTEGER",
+        ("i64", None) => "BIGINT",
+        ("u32", None) => "OID",
+        ("f32", None) => "REAL",
+        ("f64", None) => "DOUBLE PRECISION",
+        ("String", None) => "TEXT",
+        ("Vec", Some("u8")) => "BYTEA",
+        ("Vec", Some(inner)) => return sql_type(inner, None).map(|ty| ty + "[]"),
+        ("NaiveDateTime", None) | ("PrimitiveDateTime", None) => "TIMESTAMP",
+        ("DateTime", _) | ("OffsetDateTime", None) => "TIMESTAMPTZ",
+        ("NaiveDate", None) | ("Date", None) => "DATE",
+        ("NaiveTime", None) | ("Time", None) => "TIME",
+        ("Uuid", None) => "UUID",
+        ("Decimal", None) | ("BigDecimal", None) => "NUMERIC",
+        ("IpNetwork", None) | ("IpAddr", None) => "INET",
+        ("Value", None) | ("JsonValue", None) | ("Json", _) => JSON_TYPE,
+        _ => return None,
+    };
+    Some(ty.to_owned())
+}
+
+/// Returns the type of an ID column generated by the database, e.g. `SERIAL` for `INTEGER`.
+pub fn serial_type(sql_type: &str) -> String {
+    match sql_type {
+        "SMALLINT" => "SMALLSERIAL".to_owned(),
+        "INTEGER" => "SERIAL".to_owned(),
+        "BIGINT" => "BIGSERIAL".to_owned(),
+        other => other.to_owned(),
+    }
+}
@@ -0,0 +1,109 @@
+//! Logging of changes into an audit table.
+//!
+//! For tables annotated with `#[ormx(audit = "audit_log")]`, the generated `update`, `delete_row`
+//! and setters write a row into `audit_log`, containing the changed columns together with their
+//! old and new values. The same is done by patches annotated with `#[ormx(audit = "..")]`.
+//! The row is written by the same statement which modifies the table, so it's never logged if the
+//! change is rolled back.
+//!
+//! The actor, e.g. the user of the application on behalf of whom a change is made, is given by
+//! running the change within [with_actor]:
+//!
+//! ```rust,ignore
+//! ormx::audit::with_actor(user.name.clone(), async {
+//!     post.patch(&db, UpdateTitle { title }).await
+//! })
+//! .await?;
+//! ```
+//!
+//! The audit table can be created using [create_table_sql]:
+//!
+//! ```sql
+//! CREATE TABLE audit_log (
+//!     id BIGSERIAL PRIMARY KEY,
+//!     table_name TEXT NOT NULL,
+//!     row_id TEXT NOT NULL,
+//!     columns TEXT[] NOT NULL,
+//!     old_values JSONB,
+//!     new_values JSONB,
+//!     actor TEXT,
+//!     changed_at TIMESTAMPTZ NOT NULL
+//! )
+//! ```
+//!
+//! Audit logs are only supported on postgres.
+
+use std::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+thread_local! {
+    static ACTOR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Runs `future`, logging `actor` as the actor of all changes made by it.
+pub fn with_actor<F: Future>(actor: impl Into<String>, future: F) -> WithActor<F> {
+    WithActor {
+        actor: Some(actor.into()),
+        future: Box::pin(future),
+    }
+}
+
+/// Returns the current actor, as given by [with_actor].
+pub fn actor() -> Option<String> {
+    ACTOR.with(|actor| actor.borrow().clone())
+}
+
+/// Returns a `CREATE TABLE` statement for an audit table with the given name.
+pub fn create_table_sql(table: &str) -> String {
+    format!(
+        "CREATE TABLE {} (\n    \
+         id BIGSERIAL PRIMARY KEY,\n    \
+         table_name TEXT NOT NULL,\n    \
+         row_id TEXT NOT NULL,\n    \
+         columns TEXT[] NOT NULL,\n    \
+         old_values JSONB,\n    \
+         new_values JSONB,\n    \
+         actor TEXT,\n    \
+         changed_at TIMESTAMPTZ NOT NULL\n\
+         )",
+        table
+    )
+}
+
+/// A future running with an actor, see [with_actor].
+pub struct WithActor<F> {
+    actor: Option<String>,
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for WithActor<F> {
+    type Output = F::Output;
+
+    /// The actor is only set while the inner future is polled, since the task might be moved to
+    /// another thread in between.
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let previous = ACTOR.with(|actor| actor.replace(this.actor.take()));
+        // restores the previous actor even if polling panics
+        let _guard = Restore {
+            actor: &mut this.actor,
+            previous,
+        };
+        this.future.as_mut().poll(cx)
+    }
+}
+
+struct Restore<'a> {
+    actor: &'a mut Option<String>,
+    previous: Option<String>,
+}
+
+impl Drop for Restore<'_> {
+    fn drop(&mut self) {
+        *self.actor = ACTOR.with(|actor| actor.replace(self.previous.take()));
+    }
+}
@@ -0,0 +1,292 @@
+//! Read-through caching of rows.
+//!
+//! For tables annotated with `#[ormx(cache(ttl = "60s"))]`, rows queried by `Table::get` and by
+//! `get_one` and `get_optional` getters are cached for the given duration. The generated
+//! `update`, `patch`, `Patch::patch_row`, `delete_row` and setters, as well as
+//! [Tracked::save](crate::Tracked::save), invalidate the cached rows. `Table::reload` always queries the row from the database.
+//!
+//! ```rust,ignore
+//! #[derive(Clone, ormx::Table)]
+//! #[ormx(table = "feature_flags", id = id, cache(ttl = "5m", capacity = 1000))]
+//! struct FeatureFlag {
+//!     id: i32,
+//!     #[ormx(get_one(&str))]
+//!     name: String,
+//!     enabled: bool,
+//! }
+//! ```
+//!
+//! The table has to implement `Clone`, and generic tables can't be cached.
+//!
+//! Each table has a cache of rows by their ID, and one for every cached getter. By default,
+//! these are [LruCache]s with a capacity of [DEFAULT_CAPACITY] rows. A different implementation
+//! of [Cache] can be used with `cache(ttl = "..", with = path::to::MyCache)`, which has to take
+//! the type of the key and the row as generic parameters, e.g. `MyCache<K, V>`.
+//!
+//! Since the caches are local to the process, changes made by other processes or by custom
+//! queries are only visible once the cached row has expired. After changing rows using custom
+//! queries, [Cached::invalidate] or [Cached::clear] can be used.
+//! Rows which are modified or queried within a transaction are cached even if the transaction is
+//! rolled back.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::Table;
+
+/// The capacity of the caches of a table if none is given using `cache(capacity = ..)`.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// A cache mapping keys to rows, used by tables with `#[ormx(cache(..))]`.
+pub trait Cache<K, V>: Send + Sync + 'static {
+    /// Creates an empty cache, in which entries expire after `ttl` and which holds at most
+    /// `capacity` entries.
+    fn new(ttl: Duration, capacity: usize) -> Self
+    where
+        Self: Sized;
+
+    /// Returns the entry of the key, unless it has expired.
+    fn get(&self, key: &K) -> Option<V>;
+
+    /// Inserts an entry, replacing the previous entry of the key.
+    fn insert(&self, key: K, value: V);
+
+    /// Removes the entry of the key.
+    fn remove(&self, key: &K);
+
+    /// Removes all entries.
+    fn clear(&self);
+}
+
+/// A table whose rows are cached. This is implemented by `#[derive(ormx::Table)]` when using
+/// `#[ormx(cache(..))]`.
+pub trait Cached: Table + Clone {
+    /// The cache of rows by their ID, used by `Table::get`.
+    fn cache() -> &'static dyn Cache<Self::Id, Self>;
+
+    /// Removes the row with the given ID from the caches of the table. Since the caches of the
+    /// getters are keyed by a different column, they are cleared.
+    fn invalidate(id: Self::Id);
+
+    /// Clears all caches of the table.
+    fn clear();
+}
+
+/// An in-process [Cache] which evicts the least recently used entry once it's full.
+pub struct LruCache<K, V> {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<Entries<K, V>>,
+}
+
+struct Entries<K, V> {
+    values: HashMap<K, Entry<V>>,
+    /// keys by the tick at which they have been used last, least recently used first
+    recency: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+struct Entry<V> {
+    value: V,
+    expires: Instant,
+    used: u64,
+}
+
+impl<K, V> Cache<K, V> for LruCache<K, V>
+where
+    K: Hash + Eq + Clone + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    fn new(ttl: Duration, capacity: usize) -> Self {
+        LruCache {
+            ttl,
+            capacity,
+            entries: Mutex::new(Entries {
+                values: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+            }),
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let Entries {
+            values,
+            recency,
+            tick,
+        } = &mut *entries;
+
+        let entry = values.get_mut(key)?;
+        if entry.expires <= Instant::now() {
+            recency.remove(&entry.used);
+            values.remove(key);
+            return None;
+        }
+        *tick += 1;
+        recency.remove(&entry.used);
+        recency.insert(*tick, key.clone());
+        entry.used = *tick;
+        Some(entry.value.clone())
+    }
+
+    fn insert(&self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let Entries {
+            values,
+            recency,
+            tick,
+        } = &mut *entries;
+
+        match values.get(&key) {
+            Some(previous) => {
+                recency.remove(&previous.used);
+            }
+            None if values.len() >= self.capacity => {
+                let oldest = recency.keys().next().copied();
+                if let Some(evicted) = oldest.and_then(|oldest| recency.remove(&oldest)) {
+                    values.remove(&evicted);
+                }
+            }
+            None => (),
+        }
+        *tick += 1;
+        recency.insert(*tick, key.clone());
+        let entry = Entry {
+            value,
+            expires: Instant::now() + self.ttl,
+            used: *tick,
+        };
+        values.insert(key, entry);
+    }
+
+    fn remove(&self, key: &K) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(entry) = entries.values.remove(key) {
+            entries.recency.remove(&entry.used);
+        }
+    }
+
+    fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.values.clear();
+        entries.recency.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{Cached as CachedRow, RenameCached},
+        testing::MockExecutor,
+        Patch,
+    };
+
+    fn cache(capacity: usize) -> LruCache<u32, &'static str> {
+        Cache::new(Duration::from_secs(60), capacity)
+    }
+
+    fn len(cache: &LruCache<u32, &'static str>) -> usize {
+        let entries = cache.entries.lock().unwrap();
+        assert_eq!(entries.values.len(), entries.recency.len());
+        entries.values.len()
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = cache(2);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        assert_eq!(cache.get(&1), Some("a"));
+        cache.insert(3, "c");
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some("a"));
+        assert_eq!(cache.get(&3), Some("c"));
+        assert_eq!(len(&cache), 2);
+    }
+
+    #[test]
+    fn replacing_an_entry_does_not_evict() {
+        let cache = cache(2);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        cache.insert(1, "c");
+        assert_eq!(cache.get(&1), Some("c"));
+        assert_eq!(cache.get(&2), Some("b"));
+        assert_eq!(len(&cache), 2);
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let cache = cache(0);
+        cache.insert(1, "a");
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(len(&cache), 0);
+    }
+
+    #[test]
+    fn entries_expire() {
+        let cache = LruCache::new(Duration::ZERO, 2);
+        cache.insert(1, "a");
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(len(&cache), 0);
+
+        let cache = LruCache::new(Duration::from_millis(20), 2);
+        cache.insert(1, "a");
+        assert_eq!(cache.get(&1), Some("a"));
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(cache.get(&1), None);
+    }
+
+    #[test]
+    fn remove_and_clear() {
+        let cache = cache(3);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        cache.insert(3, "c");
+        cache.remove(&2);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(len(&cache), 2);
+        cache.clear();
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(len(&cache), 0);
+    }
+
+    fn cache_row(id: i32) {
+        let row = CachedRow {
+            id,
+            name: "a".into(),
+        };
+        Cache::insert(CachedRow::cache(), id, row);
+    }
+
+    #[tokio::test]
+    async fn patch_row_invalidates() {
+        cache_row(1);
+        let db = MockExecutor::new();
+        let patch = RenameCached { name: "b".into() };
+        patch.patch_row(&db, 1).await.unwrap();
+        assert_eq!(db.statements().len(), 1);
+        assert_eq!(Cache::get(CachedRow::cache(), &1), None);
+    }
+
+    #[tokio::test]
+    async fn hooked_delete_queries_the_row() {
+        cache_row(2);
+        let db = MockExecutor::new();
+        let result = CachedRow::delete_row(&db, 2).await;
+        // the row isn't taken from the cache, and the executor doesn't return it
+        assert!(matches!(result, Err(sqlx::Error::RowNotFound)));
+        let statements = db.statements();
+        assert_eq!(statements.len(), 1);
+        assert!(statements[0].starts_with("SELECT id, name FROM cached WHERE id = "));
+    }
+}
@@ -0,0 +1,187 @@
+//! Builders of rows for tests, generated by `#[ormx(factory)]`.
+//!
+//! For a table with `#[ormx(insertable, factory)]`, `InsertUser::factory()` returns a builder
+//! whose fields can be overridden using methods named after them. All other fields are generated
+//! when the row is inserted using [Create::create]:
+//!
+//! ```rust,ignore
+//! #[derive(ormx::Table)]
+//! #[ormx(table = "users", id = id, insertable, factory)]
+//! struct User {
+//!     #[ormx(default)]
+//!     id: i32,
+//!     #[ormx(factory = format!("user{}@example.com", n))]
+//!     email: String,
+//!     name: String,
+//!     #[ormx(factory(parent = Organization))]
+//!     organization_id: i32,
+//! }
+//!
+//! // creates an organization using its factory, and a user named "alice"
+//! let user = InsertUser::factory().name("alice".into()).create(&pool).await?;
+//! ```
+//!
+//! Each builder has a sequence number `n`, starting at 1 and incremented for every builder of the
+//! table. Since it starts over in each process, generated values are only unique if the tests
+//! start with empty tables or roll back their transactions. The value of a field is
+//!
+//! - the expression given using `#[ormx(factory = ..)]`, which may use `n`.
+//! - with `#[ormx(factory(parent = Table))]`, the ID of a row created using the factory of the
+//!   given table, which must have `#[ormx(factory)]` as well. A table can't be its own parent.
+//! - otherwise, [Generate::generate], which is implemented for strings, numbers, `bool`, `Option`
+//!   and `Vec`, and can be implemented for other types. Embedded structs and JSON columns of other
+//!   types need `#[ormx(factory = ..)]`, unless `Generate` is implemented for them.
+//!
+//! Rows are inserted using the generated `insert`, so hooks and validation run as usual. The parent
+//! rows and the row itself are inserted using the same connection, so `create` accepts a pool, a
+//! connection or a transaction.
+
+use std::{borrow::Cow, marker::PhantomData};
+
+use futures::future::BoxFuture;
+use sqlx::{Acquire, Result};
+
+use crate::{Db, Table};
+
+/// A table with `#[ormx(factory)]`, see the [module docs](self).
+pub trait Factory: Table {
+    type Builder: Create<Table = Self>;
+
+    /// Returns a builder of a row with the next sequence number.
+    fn factory() -> Self::Builder;
+}
+
+/// The builder returned by [Factory::factory].
+pub trait Create: Send {
+    type Table: Table;
+
+    /// Inserts the row, creating its parent rows first.
+    fn create<'c, A>(self, db: A) -> BoxFuture<'c, Result<Self::Table>>
+    where
+        A: Acquire<'c, Database = Db> + Send + 'c;
+}
+
+/// A value generated by a factory for fields without `#[ormx(factory = ..)]`.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be generated by a factory",
+    label = "set the value of this field using `#[ormx(factory = ..)]`, or implement \
+             `ormx::factory::Generate` for `{Self}`"
+)]
+pub trait Generate {
+    /// Generates the value of the field named `field`, given the sequence number `n`.
+    fn generate(field: &str, n: u64) -> Self;
+}
+
+/// `"{field}{n}"`, e.g. `"name1"`
+impl Generate for String {
+    fn generate(field: &str, n: u64) -> Self {
+        format!("{}{}", field, n)
+    }
+}
+
+macro_rules! impl_generate_number {
+    ($($t:ty),*) => {
+        $(
+            /// the sequence number
+            impl Generate for $t {
+                fn generate(_: &str, n: u64) -> Self {
+                    n as $t
+                }
+            }
+        )*
+    };
+}
+
+impl_generate_number!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64);
+
+/// the generated owned value, e.g. `"{field}{n}"` for `Cow<str>`
+impl<T> Generate for Cow<'_, T>
+where
+    T: ToOwned + ?Sized,
+    T::Owned: Generate,
+{
+    fn generate(field: &str, n: u64) -> Self {
+        Cow::Owned(T::Owned::generate(field, n))
+    }
+}
+
+/// `false`
+impl Generate for bool {
+    fn generate(_: &str, _: u64) -> Self {
+        false
+    }
+}
+
+/// `None`
+impl<T> Generate for Option<T> {
+    fn generate(_: &str, _: u64) -> Self {
+        None
+    }
+}
+
+/// an empty `Vec`
+impl<T> Generate for Vec<T> {
+    fn generate(_: &str, _: u64) -> Self {
+        vec![]
+    }
+}
+
+impl<T> Generate for PhantomData<T> {
+    fn generate(_: &str, _: u64) -> Self {
+        PhantomData
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_values() {
+        assert_eq!(String::generate("name", 3), "name3");
+        assert_eq!(i32::generate("id", 3), 3);
+        assert!(!bool::generate("active", 3));
+        assert_eq!(Option::<String>::generate("bio", 3), None);
+        assert!(Vec::<String>::generate("tags", 3).is_empty());
+    }
+
+    #[cfg(feature = "memory")]
+    #[tokio::test]
+    async fn create_inserts_the_row_and_its_parent() -> Result<()> {
+        use crate::{
+            fixtures::{InsertMemoryPlayer, MemoryPlayer, MemoryTeam},
+            memory::{self, Store},
+        };
+
+        let store = Store::new();
+        let db = memory::pool();
+        store
+            .run(async {
+                let first = InsertMemoryPlayer::factory().create(&db).await?;
+                let second = InsertMemoryPlayer::factory()
+                    .name("alice".into())
+                    .rating(Some(1200))
+                    .create(&db)
+                    .await?;
+                // builders are numbered in the order they were created
+                let n = first
+                    .name
+                    .trim_start_matches("name")
+                    .parse::<u64>()
+                    .unwrap();
+                assert_eq!(first.email, format!("player{}@example.com", n));
+                assert_eq!(first.rating, None);
+                assert_eq!(second.name, "alice");
+                assert_eq!(second.email, format!("player{}@example.com", n + 1));
+                assert_eq!(second.rating, Some(1200));
+
+                let teams = store.rows::<MemoryTeam>();
+                assert_eq!(teams.len(), 2);
+                assert_eq!(first.team_id, teams[0].id);
+                assert_eq!(second.team_id, teams[1].id);
+                assert_eq!(store.rows::<MemoryPlayer>(), vec![first, second]);
+                Ok(())
+            })
+            .await
+    }
+}
@@ -0,0 +1,248 @@
+//! Tables shared by the unit tests, derived like the tables of a user of ormx.
+
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::observe::{self, Event, Query, QueryObserver};
+
+#[derive(Debug, Clone, PartialEq, ormx::Table)]
+#[ormx(table = "rows", id = id, unchecked)]
+pub struct Row {
+    pub id: i32,
+    #[ormx(get_optional(&str), set)]
+    pub name: String,
+}
+
+/// A patch of [Row].
+#[derive(ormx::Patch)]
+#[ormx(table_name = "rows", table = Row, id = "id", unchecked)]
+pub struct RenameRow {
+    pub name: String,
+}
+
+/// Search criteria on [Row].
+#[derive(Default, ormx::Filter)]
+#[ormx(table = Row)]
+pub struct RowFilter {
+    #[ormx(field = id, op = "in")]
+    pub ids: Option<Vec<i32>>,
+    #[ormx(op = "like")]
+    pub name: Option<String>,
+    #[ormx(field = id, op = "gt")]
+    pub after: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, ormx::Table)]
+#[ormx(table = "others", id = id, unchecked)]
+pub struct Other {
+    pub id: i64,
+}
+
+/// A table with `#[ormx(tag)]`, whose changes can be tracked.
+#[derive(Debug, Clone, PartialEq, ormx::Table)]
+#[ormx(table = "tagged", id = id, unchecked, tracked, tag)]
+pub struct Tagged {
+    pub id: i32,
+    pub name: String,
+}
+
+/// A patch of [Tagged], inheriting its tag.
+#[derive(ormx::Patch)]
+#[ormx(table_name = "tagged", table = Tagged, id = "id", unchecked)]
+pub struct RenameTagged {
+    pub name: String,
+}
+
+/// A patch of [Tagged] with a tag of its own.
+#[derive(ormx::Patch)]
+#[ormx(table_name = "tagged", table = Tagged, id = "id", unchecked, tag = "billing")]
+pub struct BillTagged {
+    pub name: String,
+}
+
+/// A tracked table with hooks and validation.
+#[derive(Debug, Clone, PartialEq, ormx::Table)]
+#[ormx(table = "accounts", id = id, unchecked, tracked, hooks, validate)]
+pub struct Account {
+    pub id: i32,
+    #[ormx(length(min = 1))]
+    pub email: String,
+    pub age: i32,
+}
+
+impl ormx::Hooks for Account {
+    type Insert = ();
+
+    fn before_update<'a>(
+        &'a mut self,
+        _db: impl sqlx::Executor<'a, Database = ormx::Db> + 'a,
+    ) -> futures::future::BoxFuture<'a, sqlx::Result<()>> {
+        self.email = self.email.to_lowercase();
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// A cached table with hooks.
+#[derive(Debug, Clone, PartialEq, ormx::Table)]
+#[ormx(table = "cached", id = id, unchecked, deletable, hooks, cache(ttl = "1h"))]
+pub struct Cached {
+    pub id: i32,
+    #[ormx(get_one(&str))]
+    pub name: String,
+}
+
+impl ormx::Hooks for Cached {
+    type Insert = ();
+}
+
+/// A patch of [Cached].
+#[derive(ormx::Patch)]
+#[ormx(table_name = "cached", table = Cached, id = "id", unchecked)]
+pub struct RenameCached {
+    pub name: String,
+}
+
+/// Like [Row], but stored in the [Store](crate::memory::Store).
+#[cfg(feature = "memory")]
+#[derive(Debug, Clone, PartialEq, ormx::Table)]
+#[ormx(table = "memory_rows", id = id, unchecked, insertable, memory)]
+pub struct MemoryRow {
+    pub id: i32,
+    pub name: String,
+}
+
+/// A table stored in the [Store](crate::memory::Store) whose rows are created by a factory.
+#[cfg(feature = "memory")]
+#[derive(Debug, Clone, PartialEq, ormx::Table)]
+#[ormx(table = "memory_teams", id = id, unchecked, insertable, factory, memory)]
+pub struct MemoryTeam {
+    #[ormx(default)]
+    pub id: i32,
+    pub name: String,
+}
+
+/// A row of a [MemoryTeam], whose factory creates the team as well.
+#[cfg(feature = "memory")]
+#[derive(Debug, Clone, PartialEq, ormx::Table)]
+#[ormx(table = "memory_players", id = id, unchecked, insertable, factory, memory)]
+pub struct MemoryPlayer {
+    #[ormx(default)]
+    pub id: i32,
+    #[ormx(factory = format!("player{}@example.com", n))]
+    pub email: String,
+    pub name: String,
+    pub rating: Option<i64>,
+    #[ormx(factory(parent = MemoryTeam))]
+    pub team_id: i32,
+}
+
+/// Structs embedded into [Place], which is resolved through the path of this module.
+#[cfg(feature = "postgres")]
+pub mod geo {
+    #[derive(Debug, Clone, PartialEq, ormx::Columns)]
+    pub struct Coordinates {
+        pub lat: f64,
+        pub lng: f64,
+    }
+}
+
+/// An embedded struct, which embeds [geo::Coordinates] with a custom prefix.
+#[cfg(feature = "postgres")]
+#[derive(Debug, Clone, PartialEq, ormx::Columns)]
+pub struct Address {
+    pub street: String,
+    #[ormx(flatten = "geo_")]
+    pub location: geo::Coordinates,
+}
+
+/// A table embedding [Address] twice, once with the default prefix and once renamed.
+#[cfg(feature = "postgres")]
+#[derive(Debug, Clone, PartialEq, ormx::Table)]
+#[ormx(table = "places", id = id, unchecked, insertable)]
+pub struct Place {
+    #[ormx(default)]
+    pub id: i32,
+    pub name: String,
+    #[ormx(flatten)]
+    pub address: Address,
+    #[ormx(flatten = "billing_")]
+    pub billing: Address,
+}
+
+/// A table only queried by the tests of the [observer](crate::observe), so that they don't
+/// record the queries of other tests.
+#[derive(Debug, Clone, PartialEq, ormx::Table)]
+#[ormx(table = "observed_rows", id = id, unchecked, deletable)]
+pub struct ObservedRow {
+    pub id: i32,
+    #[ormx(get_optional(&str), set)]
+    pub name: String,
+}
+
+/// The queries of the generated methods, recorded since [Recording::start].
+pub struct Recording {
+    events: Arc<Mutex<Vec<(Query, Recorded)>>>,
+    _guard: MutexGuard<'static, ()>,
+}
+
+/// An [Event] recorded by a [Recording].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recorded {
+    pub operation: &'static str,
+    pub rows: u64,
+    pub failed: bool,
+}
+
+/// Only one test at a time can observe queries, since the observer is global.
+static OBSERVING: Mutex<()> = Mutex::new(());
+
+struct Recorder(Arc<Mutex<Vec<(Query, Recorded)>>>);
+
+impl QueryObserver for Recorder {
+    fn observe(&self, event: &Event<'_>) {
+        let recorded = Recorded {
+            operation: event.query.operation,
+            rows: event.rows,
+            failed: event.error.is_some(),
+        };
+        lock(&self.0).push((event.query, recorded));
+    }
+}
+
+impl Recording {
+    /// Sets an observer recording all queries, waiting for the recordings of other tests.
+    pub fn start() -> Self {
+        let guard = OBSERVING.lock().unwrap_or_else(PoisonError::into_inner);
+        let events = Arc::new(Mutex::new(vec![]));
+        observe::set_observer(Recorder(events.clone()));
+        Recording {
+            events,
+            _guard: guard,
+        }
+    }
+
+    /// Returns the operations of the queries on the given table, removing them.
+    pub fn take(&self, table: &str) -> Vec<&'static str> {
+        let events = self.take_events(table);
+        events.into_iter().map(|event| event.operation).collect()
+    }
+
+    /// Returns the events of the queries on the given table, removing them.
+    pub fn take_events(&self, table: &str) -> Vec<Recorded> {
+        let mut events = lock(&self.events);
+        let (taken, rest) = events
+            .drain(..)
+            .partition::<Vec<_>, _>(|(query, _)| query.table == table);
+        *events = rest;
+        taken.into_iter().map(|(_, event)| event).collect()
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        observe::remove_observer();
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -29,6 +29,22 @@
 //!
 //! # Documentation
 //! See the docs of [derive(Table)](derive.Table.html) and [Patch](trait.Patch.html).
+//! For running queries in a transaction, see [transaction()](fn.transaction.html).
+//! For validating rows before writing them, see [validate](validate/index.html), which also shows
+//! how to catch validation errors using [WriteError](enum.WriteError.html).
+//! For logging changes into an audit table, see [audit](audit/index.html).
+//! For updating only the columns which have changed, see [tracked](tracked/index.html).
+//! For caching rows which are rarely changed, see [cache](cache/index.html).
+//! For batching queries of rows by their ID, see [loader](loader/index.html).
+//! For metrics and tracing of the generated methods, see [observe](observe/index.html).
+//! For detecting N+1 queries in debug builds, see [n_plus_one](n_plus_one/index.html).
+//! For building rows in tests, see [factory](factory/index.html).
+//! For testing without a database, see [memory](memory/index.html).
+//! For asserting the SQL issued by the generated methods, see [testing](testing/index.html).
+
+// allows deriving the tables used by the unit tests within this crate
+#[cfg(test)]
+extern crate self as ormx;
 
 use futures::{future::BoxFuture, stream::BoxStream};
 pub use ormx_macros::*;
@@ -41,8 +57,33 @@ pub mod exports {
     pub use crate::query2::map::*;
 }
 
+#[cfg(feature = "postgres")]
+pub mod audit;
+pub mod cache;
+pub mod factory;
+#[cfg(test)]
+mod fixtures;
+pub mod loader;
+#[cfg(feature = "memory")]
+pub mod memory;
+pub mod n_plus_one;
+pub mod observe;
 #[cfg(any(feature = "mysql", feature = "postgres"))]
 mod query2;
+#[cfg(any(feature = "mysql", feature = "postgres"))]
+pub mod schema;
+pub mod select;
+pub mod testing;
+pub mod tracked;
+pub mod transaction;
+pub mod validate;
+
+#[cfg(any(feature = "mysql", feature = "postgres"))]
+pub use schema::{verify, SchemaIssue, SchemaReport};
+pub use select::{Column, Condition, Filter, Order, Select};
+pub use tracked::{Track, Tracked};
+pub use transaction::{transaction, transaction_with_retry, Retry, TransactionError};
+pub use validate::{Validate, ValidationError, ValidationErrorKind, ValidationErrors, WriteError};
 
 #[cfg(feature = "mysql")]
 pub type Db = sqlx::MySql;
@@ -54,7 +95,7 @@ pub type Db = sqlx::Sqlite;
 /// A database table in which each row is identified by a unique ID.
 pub trait Table
 where
-    Self: Sized + Send + Sync + 'static,
+    Self: Sized + Send + Sync,
 {
     /// Type of the ID column of this table.
     type Id: 'static + Copy + Send;
@@ -63,10 +104,13 @@ where
     fn id(&self) -> Self::Id;
 
     /// Insert a row into the database.
-    fn insert(
-        db: &mut <Db as Database>::Connection,
-        row: impl Insert<Table = Self>,
-    ) -> BoxFuture<Result<Self>> {
+    fn insert<'a>(
+        db: &'a mut <Db as Database>::Connection,
+        row: impl Insert<Table = Self> + 'a,
+    ) -> BoxFuture<'a, Result<Self>>
+    where
+        Self: 'a,
+    {
         row.insert(db)
     }
 
@@ -74,23 +118,32 @@ where
     fn get<'a, 'c: 'a>(
         db: impl Executor<'c, Database = Db> + 'a,
         id: Self::Id,
-    ) -> BoxFuture<'a, Result<Self>>;
+    ) -> BoxFuture<'a, Result<Self>>
+    where
+        Self: 'a;
 
     /// Stream all rows from this table.
     fn stream_all<'a, 'c: 'a>(
         db: impl Executor<'c, Database = Db> + 'a,
-    ) -> BoxStream<'a, Result<Self>>;
+    ) -> BoxStream<'a, Result<Self>>
+    where
+        Self: 'a;
 
     fn stream_all_paginated<'a, 'c: 'a>(
         db: impl Executor<'c, Database = Db> + 'a,
         offset: i64,
         limit: i64,
-    ) -> BoxStream<'a, Result<Self>>;
+    ) -> BoxStream<'a, Result<Self>>
+    where
+        Self: 'a;
 
     /// Load all rows from this table.
     fn all<'a, 'c: 'a>(
         db: impl Executor<'c, Database = Db> + 'a,
-    ) -> BoxFuture<'a, Result<Vec<Self>>> {
+    ) -> BoxFuture<'a, Result<Vec<Self>>>
+    where
+        Self: 'a,
+    {
         use futures::TryStreamExt;
 
         Box::pin(Self::stream_all(db).try_collect())
@@ -100,7 +153,10 @@ where
         db: impl Executor<'c, Database = Db> + 'a,
         offset: i64,
         limit: i64,
-    ) -> BoxFuture<'a, Result<Vec<Self>>> {
+    ) -> BoxFuture<'a, Result<Vec<Self>>>
+    where
+        Self: 'a,
+    {
         use futures::TryStreamExt;
 
         Box::pin(Self::stream_all_paginated(db, offset, limit).try_collect())
@@ -112,7 +168,7 @@ where
         patch: P,
     ) -> BoxFuture<'a, Result<()>>
     where
-        P: Patch<Table = Self>,
+        P: Patch<Table = Self> + 'a,
     {
         Box::pin(async move {
             let patch: P = patch;
@@ -128,6 +184,11 @@ where
         db: impl Executor<'c, Database = Db> + 'a,
     ) -> BoxFuture<'a, Result<()>>;
 
+    /// Removes the row from the caches of a table with `#[ormx(cache(..))]`, called by the
+    /// generated `Patch::patch_row`.
+    #[doc(hidden)]
+    fn __ormx_invalidate(_id: Self::Id) {}
+
     // Refresh this row, querying all columns from the database.
     fn reload<'a, 'c: 'a>(
         &'a mut self,
@@ -140,21 +201,132 @@ where
     }
 }
 
+/// Querying multiple rows of a table by their ID, implemented by `#[derive(ormx::Table)]`.
+pub trait GetByIds: Table {
+    /// Queries the rows of the given ids using a single query. IDs without a row are skipped,
+    /// and the rows are returned in no particular order. See [loader](loader/index.html) for
+    /// batching calls to `get`.
+    fn get_by_ids<'a, 'c: 'a>(
+        db: impl Executor<'c, Database = Db> + 'a,
+        ids: &'a [Self::Id],
+    ) -> BoxFuture<'a, Result<Vec<Self>>>
+    where
+        Self: 'a;
+}
+
+/// Static information about a table derived using `#[derive(ormx::Table)]`, which can be used to
+/// write custom queries.
+pub trait TableMeta
+where
+    Self: Sized,
+{
+    /// Name of the table.
+    const TABLE: &'static str;
+    /// Name of the ID column.
+    const ID_COLUMN: &'static str;
+    /// All columns of the table, in the order in which they are selected.
+    const COLUMNS: &'static [ColumnMeta];
+    /// The column list used by the generated `SELECT` queries, e.g. `id, name AS user_name`.
+    const SELECT_COLUMNS: &'static str;
+    /// The audit table of a table with `#[ormx(audit = "..")]`, see [audit]. Patches of the table
+    /// have to be audited as well.
+    #[cfg(feature = "postgres")]
+    const AUDIT: Option<&'static str> = None;
+    /// Whether the table has `#[ormx(memory)]`, see [memory]. Patches of the table have to repeat
+    /// the attribute.
+    #[cfg(feature = "memory")]
+    const MEMORY: bool = false;
+    /// The prefix of the comments prepended to the statements of a table with `#[ormx(tag)]`, see
+    /// [derive(Table)](derive.Table.html). Patches of the table, [Tracked::save] and [Select]
+    /// prepend it as well.
+    const TAG: Option<&'static str> = None;
+
+    /// A struct containing a [Column] for each column of this table, used for building queries.
+    type Columns;
+
+    /// Returns the columns of this table, see [TableMeta::Columns].
+    fn columns() -> Self::Columns;
+
+    /// Returns a `CREATE TABLE` statement for this table.
+    /// Since the schema is inferred from the struct, the statement might differ from the actual
+    /// schema, e.g. it doesn't contain any `DEFAULT` values or foreign keys.
+    fn create_table_sql() -> &'static str;
+
+    /// Decodes a row which has been selected using [TableMeta::SELECT_COLUMNS].
+    fn from_row(row: &<Db as Database>::Row) -> Result<Self>;
+
+    /// Starts building a `SELECT` query on this table, see [select](select/index.html).
+    fn select() -> Select<Self> {
+        Select::default()
+    }
+
+    /// Load all rows matching the filter.
+    fn find<'a, 'c: 'a>(
+        db: impl Executor<'c, Database = Db> + 'a,
+        filter: impl Filter<Table = Self>,
+    ) -> BoxFuture<'a, Result<Vec<Self>>>
+    where
+        Self: Send + 'a,
+    {
+        Box::pin(Self::select().filter_by(filter).fetch_all(db))
+    }
+}
+
+/// Prepends the comment `/* {tag}:table={table},op={operation} */` to `sql` if the table `T` has
+/// `#[ormx(tag)]`, like the statements of the generated methods.
+#[doc(hidden)]
+pub fn tag_sql<T: TableMeta>(operation: &str, sql: &str) -> String {
+    match T::TAG {
+        Some(tag) => format!("/* {}:table={},op={} */ {}", tag, T::TABLE, operation, sql),
+        None => sql.to_owned(),
+    }
+}
+
+/// A column of a table, see [TableMeta].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColumnMeta {
+    /// Name of the column.
+    pub name: &'static str,
+    /// The field this column is read into. For columns of embedded structs, this is the path to
+    /// the field, e.g. `address.city`.
+    pub field: &'static str,
+    /// The name under which this column is selected in [TableMeta::SELECT_COLUMNS].
+    /// For custom types and JSON columns, the selected name additionally contains a type override
+    /// of `sqlx::query!`, e.g. `role AS "role: _"`.
+    pub alias: &'static str,
+    /// The SQL type of this column, if it is known. This is either given by
+    /// `#[ormx(sql_type = "..")]`, or inferred from the type of the field.
+    pub sql_type: Option<&'static str>,
+    /// Whether this column may be `NULL`, which is the case for fields of type `Option<T>`.
+    pub nullable: bool,
+    /// Whether the value of this column is generated by the database (`#[ormx(default)]`).
+    pub default: bool,
+    /// Whether this column has a custom type (`#[ormx(custom_type)]`).
+    pub custom_type: bool,
+    /// Whether this column is stored as JSON (`#[ormx(json)]`).
+    pub json: bool,
+}
+
 pub trait Delete
 where
-    Self: Table + Sized + Send + Sync + 'static,
+    Self: Table + Sized + Send + Sync,
 {
     /// Delete a row from the database
     fn delete_row<'a, 'c: 'a>(
         db: impl Executor<'c, Database = Db> + 'a,
         id: Self::Id,
-    ) -> BoxFuture<'a, Result<()>>;
+    ) -> BoxFuture<'a, Result<()>>
+    where
+        Self: 'a;
 
     /// Deletes this row from the database
     fn delete<'a, 'c: 'a>(
         self,
         db: impl Executor<'c, Database = Db> + 'a,
-    ) -> BoxFuture<'a, Result<()>> {
+    ) -> BoxFuture<'a, Result<()>>
+    where
+        Self: 'a,
+    {
         Self::delete_row(db, self.id())
     }
 
@@ -162,7 +334,10 @@ where
     fn delete_ref<'a, 'c: 'a>(
         &self,
         db: impl Executor<'c, Database = Db> + 'a,
-    ) -> BoxFuture<'a, Result<()>> {
+    ) -> BoxFuture<'a, Result<()>>
+    where
+        Self: 'a,
+    {
         Self::delete_row(db, self.id())
     }
 }
@@ -170,7 +345,7 @@ where
 /// A type which can be used to "patch" a row, updating multiple fields at once.
 pub trait Patch
 where
-    Self: Sized + Send + Sync + 'static,
+    Self: Sized + Send + Sync,
 {
     type Table: Table;
 
@@ -189,7 +364,7 @@ where
 /// A type which can be inserted as a row into the database.
 pub trait Insert
 where
-    Self: Sized + Send + Sync + 'static,
+    Self: Sized + Send + Sync,
 {
     type Table: Table;
 
@@ -197,5 +372,319 @@ where
     fn insert<'a, 'c: 'a>(
         self,
         db: impl Executor<'c, Database = Db> + 'a,
-    ) -> BoxFuture<'a, Result<Self::Table>>;
+    ) -> BoxFuture<'a, Result<Self::Table>>
+    where
+        Self: 'a,
+        Self::Table: 'a;
+}
+
+/// An executor which can be used for more than one query, so that the generated methods of a
+/// table with `#[ormx(hooks)]` can pass it on to its [Hooks]. It is implemented for `&Pool`, `&mut`
+/// connections and transactions, and [testing::MockExecutor](testing/struct.MockExecutor.html).
+pub trait Reborrow<'c>: Executor<'c, Database = Db> {
+    /// The executor returned by [Reborrow::reborrow].
+    type Reborrowed<'r>: Executor<'r, Database = Db>
+    where
+        Self: 'r;
+
+    /// Borrows this executor for a single query.
+    fn reborrow(&mut self) -> Self::Reborrowed<'_>;
+}
+
+// the connection type of `Db`, which can't be named as `<Db as Database>::Connection` in
+// implementations of `Reborrow`, since they would overlap
+#[cfg(feature = "mysql")]
+type Connection = sqlx::MySqlConnection;
+#[cfg(feature = "postgres")]
+type Connection = sqlx::PgConnection;
+#[cfg(feature = "sqlite")]
+type Connection = sqlx::SqliteConnection;
+
+impl<'c> Reborrow<'c> for &'c sqlx::Pool<Db> {
+    type Reborrowed<'r>
+        = &'c sqlx::Pool<Db>
+    where
+        Self: 'r;
+
+    fn reborrow(&mut self) -> Self::Reborrowed<'_> {
+        self
+    }
+}
+
+impl<'c> Reborrow<'c> for &'c mut Connection {
+    type Reborrowed<'r>
+        = &'r mut Connection
+    where
+        Self: 'r;
+
+    fn reborrow(&mut self) -> Self::Reborrowed<'_> {
+        self
+    }
+}
+
+impl<'c> Reborrow<'c> for &'c mut sqlx::pool::PoolConnection<Db> {
+    type Reborrowed<'r>
+        = &'r mut Connection
+    where
+        Self: 'r;
+
+    fn reborrow(&mut self) -> Self::Reborrowed<'_> {
+        self
+    }
+}
+
+impl<'c, 't> Reborrow<'c> for &'c mut sqlx::Transaction<'t, Db> {
+    type Reborrowed<'r>
+        = &'r mut Connection
+    where
+        Self: 'r;
+
+    fn reborrow(&mut self) -> Self::Reborrowed<'_> {
+        self
+    }
+}
+
+/// Methods which are called before and after a row is modified by the generated methods of a
+/// table with `#[ormx(hooks)]`. All of them do nothing by default.
+///
+/// The traits [Insert], [Delete] and [Table] take any executor, which can only be used for a
+/// single query. Therefore, a table with hooks and its insert struct get inherent methods instead,
+/// which take a [Reborrow]:
+/// - `insert(self, db)` of the insert struct
+/// - `update(self, db)`, which returns the updated row, since the hooks may modify it
+/// - `patch(&mut self, db, patch)`, which requires the patch to implement [PatchHooks]
+/// - `delete_row(db, id)`, `delete(self, db)` and `delete_ref(&self, db)` if it's deletable
+///
+/// Neither [Insert] nor [Delete] are implemented, and [Table::update] and [Table::patch] return an
+/// error, so that the hooks can't be skipped by accident. Since `update` takes `self`, calling it
+/// on a reference, e.g. `(&user).update(db)`, resolves to [Table::update].
+///
+/// Returning an error from a `before_*` hook aborts the query, and the error is returned to the
+/// caller. An error from an `after_*` hook is returned as well, but the row has already been
+/// modified at that point, so run the query in a [transaction()] if it should be rolled back.
+///
+/// The hooks receive the executor which was passed to the generated method, so they can run
+/// queries of their own. Pass a transaction to run them in the same transaction as the
+/// modification; when passing a `&Pool`, each query may run on a different connection.
+///
+/// ```rust,ignore
+/// impl ormx::Hooks for User {
+///     type Insert = InsertUser;
+///
+///     fn before_update<'a>(
+///         &'a mut self,
+///         _db: impl sqlx::Executor<'a, Database = ormx::Db> + 'a,
+///     ) -> BoxFuture<'a, sqlx::Result<()>> {
+///         self.email = self.email.to_lowercase();
+///         Box::pin(async { Ok(()) })
+///     }
+///
+///     fn after_delete<'a>(
+///         &'a self,
+///         db: impl sqlx::Executor<'a, Database = ormx::Db> + 'a,
+///     ) -> BoxFuture<'a, sqlx::Result<()>> {
+///         Box::pin(async move {
+///             sqlx::query!("DELETE FROM sessions WHERE user_id = $1", self.id)
+///                 .execute(db)
+///                 .await?;
+///             Ok(())
+///         })
+///     }
+/// }
+/// ```
+pub trait Hooks
+where
+    Self: Table,
+{
+    /// The insert struct of this table, or `()` if the table isn't insertable.
+    type Insert: Send;
+
+    /// Called by the generated `insert` before the row is inserted.
+    fn before_insert<'a>(
+        _row: &'a mut Self::Insert,
+        _db: impl Executor<'a, Database = Db> + 'a,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(futures::future::ok(()))
+    }
+
+    /// Called by the generated `insert` with the inserted row.
+    fn after_insert<'a>(
+        &'a self,
+        _db: impl Executor<'a, Database = Db> + 'a,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(futures::future::ok(()))
+    }
+
+    /// Called by the generated `update` before the row is updated, which may modify it.
+    /// Patches call [PatchHooks::before_patch] instead.
+    fn before_update<'a>(
+        &'a mut self,
+        _db: impl Executor<'a, Database = Db> + 'a,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(futures::future::ok(()))
+    }
+
+    /// Called by the generated `update` and `patch` after the row has been updated.
+    fn after_update<'a>(
+        &'a self,
+        _db: impl Executor<'a, Database = Db> + 'a,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(futures::future::ok(()))
+    }
+
+    /// Called by the generated `delete_row` before the row is deleted. The row is queried before
+    /// calling this hook, which is an additional query compared to tables without hooks.
+    fn before_delete<'a>(
+        &'a self,
+        _db: impl Executor<'a, Database = Db> + 'a,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(futures::future::ok(()))
+    }
+
+    /// Called by the generated `delete_row` with the row after it has been deleted.
+    fn after_delete<'a>(
+        &'a self,
+        _db: impl Executor<'a, Database = Db> + 'a,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(futures::future::ok(()))
+    }
+}
+
+/// Methods which are called by the generated `patch` of a table with `#[ormx(hooks)]`, which can
+/// only be used with patches implementing this trait. The hook does nothing by default.
+///
+/// ```rust,ignore
+/// impl ormx::PatchHooks for UpdateEmail {
+///     fn before_patch<'a>(
+///         &'a mut self,
+///         _row: &'a User,
+///         _db: impl sqlx::Executor<'a, Database = ormx::Db> + 'a,
+///     ) -> BoxFuture<'a, sqlx::Result<()>> {
+///         self.email = self.email.to_lowercase();
+///         Box::pin(async { Ok(()) })
+///     }
+/// }
+/// ```
+pub trait PatchHooks
+where
+    Self: Patch,
+    Self::Table: Hooks,
+{
+    /// Called before the patch is written, which may modify it. `row` doesn't contain the changes
+    /// of the patch yet. After the patch has been applied to the row, [Hooks::after_update] is
+    /// called.
+    fn before_patch<'a>(
+        &'a mut self,
+        _row: &'a Self::Table,
+        _db: impl Executor<'a, Database = Db> + 'a,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(futures::future::ok(()))
+    }
+}
+
+#[cfg(all(test, feature = "postgres"))]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{geo::Coordinates, Address, InsertPlace, Place},
+        testing::MockExecutor,
+    };
+
+    fn place() -> Place {
+        let address = |street: &str, lat| Address {
+            street: street.into(),
+            location: Coordinates { lat, lng: 2.5 },
+        };
+        Place {
+            id: 1,
+            name: "office".into(),
+            address: address("main", 1.5),
+            billing: address("side", 3.5),
+        }
+    }
+
+    #[test]
+    fn embedded_columns_are_prefixed() {
+        let columns = Place::COLUMNS.iter().map(|column| column.name);
+        assert_eq!(
+            columns.collect::<Vec<_>>(),
+            [
+                "id",
+                "name",
+                "address_street",
+                "address_geo_lat",
+                "address_geo_lng",
+                "billing_street",
+                "billing_geo_lat",
+                "billing_geo_lng",
+            ]
+        );
+        // the aliases are the paths to the fields, which are decoded into the row struct
+        assert_eq!(
+            Place::SELECT_COLUMNS,
+            "id, name, address_street AS address__street, \
+             address_geo_lat AS address__location__lat, address_geo_lng AS address__location__lng, \
+             billing_street AS billing__street, \
+             billing_geo_lat AS billing__location__lat, billing_geo_lng AS billing__location__lng"
+        );
+        let columns = Place::columns();
+        assert_eq!(columns.address.location.lat.name(), "address_geo_lat");
+        assert_eq!(columns.billing.street.name(), "billing_street");
+    }
+
+    #[tokio::test]
+    async fn update_assigns_embedded_columns() {
+        let db = MockExecutor::new();
+        place().update(&db).await.unwrap();
+        let statement = db.executed().remove(0);
+        assert_eq!(
+            statement.sql,
+            "UPDATE places SET name = $1, address_street = $2, address_geo_lat = $3, \
+             address_geo_lng = $4, billing_street = $5, billing_geo_lat = $6, \
+             billing_geo_lng = $7 WHERE id = $8"
+        );
+        assert_eq!(
+            statement.arguments,
+            [
+                r#""office""#,
+                r#""main""#,
+                "1.5",
+                "2.5",
+                r#""side""#,
+                "3.5",
+                "2.5",
+                "1"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn insert_struct_embeds_the_structs() {
+        let insert = InsertPlace::from(place());
+        assert_eq!(insert.address, place().address);
+        assert_eq!(insert.billing.location.lat, 3.5);
+
+        let db = MockExecutor::new();
+        // the mock doesn't return the generated ID
+        assert!(insert.insert(&db).await.is_err());
+        let statement = db.executed().remove(0);
+        assert_eq!(
+            statement.sql,
+            "INSERT INTO places (name, address_street, address_geo_lat, address_geo_lng, \
+             billing_street, billing_geo_lat, billing_geo_lng) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id"
+        );
+        assert_eq!(
+            statement.arguments,
+            [
+                r#""office""#,
+                r#""main""#,
+                "1.5",
+                "2.5",
+                r#""side""#,
+                "3.5",
+                "2.5"
+            ]
+        );
+    }
 }
@@ -0,0 +1,437 @@
+//! Batching of queries by ID, e.g. for GraphQL resolvers.
+//!
+//! A [Loader] collects the IDs requested using [Loader::load] within one tick of the executor,
+//! and queries them using a single call to [GetByIds::get_by_ids]. Duplicate IDs are only queried
+//! once. If the query fails, all requests of the batch fail with its error, so e.g.
+//! [is_retryable](crate::transaction::is_retryable) sees the same database error for each of them.
+//!
+//! ```rust,ignore
+//! let loader = Loader::<User>::new(pool.clone());
+//! // SELECT .. FROM users WHERE id = ANY($1)
+//! let (a, b, c) = futures::try_join!(loader.load(1), loader.load(2), loader.load(1))?;
+//! ```
+//!
+//! Requests are batched if they are made before the query is sent, which is the case for futures
+//! joined within the same task, e.g. the resolvers of a GraphQL query. Requests made by other
+//! tasks only join the batch if they happen to be polled in between.
+//! Since the rows are not cached, a loader can be kept for the lifetime of the application, or
+//! created for each request.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    error::Error as StdError,
+    fmt::{self, Debug, Display, Formatter},
+    future::Future,
+    hash::Hash,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll},
+};
+
+use futures::channel::oneshot;
+use sqlx::{error::DatabaseError, Pool, Result};
+
+use crate::{Db, GetByIds, Table};
+
+/// Loads rows by their ID, batching the requests made within one tick, see the
+/// [module docs](self).
+pub struct Loader<T: Table> {
+    pool: Pool<Db>,
+    batch: Arc<Mutex<Option<Batch<T>>>>,
+}
+
+/// The IDs requested while a batch is being collected, together with the requests waiting for
+/// them. The request which started collecting the batch isn't contained.
+type Batch<T> = HashMap<<T as Table>::Id, Vec<oneshot::Sender<Result<T>>>>;
+
+impl<T> Loader<T>
+where
+    T: GetByIds + Clone,
+    T::Id: Hash + Eq,
+{
+    /// Creates a loader, which queries rows using the given pool.
+    pub fn new(pool: Pool<Db>) -> Self {
+        Loader {
+            pool,
+            batch: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Loads the row of the given ID, returning `sqlx::Error::RowNotFound` if there is none.
+    pub async fn load(&self, id: T::Id) -> Result<T> {
+        loop {
+            let waiting = {
+                let mut batch = self.batch.lock().unwrap_or_else(PoisonError::into_inner);
+                match &mut *batch {
+                    Some(batch) => {
+                        let (sender, receiver) = oneshot::channel();
+                        batch.entry(id).or_default().push(sender);
+                        Some(receiver)
+                    }
+                    None => {
+                        *batch = Some(HashMap::new());
+                        None
+                    }
+                }
+            };
+
+            match waiting {
+                Some(receiver) => match receiver.await {
+                    Ok(result) => return result,
+                    // the request querying the batch has been dropped, so try again
+                    Err(oneshot::Canceled) => continue,
+                },
+                None => return self.load_batch(id).await,
+            }
+        }
+    }
+
+    /// Loads the rows of the given IDs, skipping IDs without a row.
+    pub async fn load_many(&self, ids: impl IntoIterator<Item = T::Id>) -> Result<Vec<T>> {
+        let loads = ids.into_iter().map(|id| self.load(id));
+        let rows = futures::future::join_all(loads).await;
+        rows.into_iter()
+            .filter(|row| !matches!(row, Err(sqlx::Error::RowNotFound)))
+            .collect()
+    }
+
+    /// Collects the IDs requested until the next tick, and queries them.
+    async fn load_batch(&self, id: T::Id) -> Result<T> {
+        let collecting = Collecting(&self.batch);
+        YieldNow(false).await;
+        let batch = collecting.take();
+
+        let mut ids = batch.keys().copied().collect::<Vec<_>>();
+        if !batch.contains_key(&id) {
+            ids.push(id);
+        }
+        let mut rows = match T::get_by_ids(&self.pool, &ids).await {
+            Ok(rows) => rows
+                .into_iter()
+                .map(|row| (row.id(), row))
+                .collect::<HashMap<_, _>>(),
+            Err(error) => {
+                let error = BatchError::new(error);
+                for sender in batch.into_values().flatten() {
+                    let _ = sender.send(Err(error.copy()));
+                }
+                return Err(error.into_error());
+            }
+        };
+
+        for (id, senders) in batch {
+            for sender in senders {
+                let row = rows.get(&id).cloned().ok_or(sqlx::Error::RowNotFound);
+                let _ = sender.send(row);
+            }
+        }
+        rows.remove(&id).ok_or(sqlx::Error::RowNotFound)
+    }
+}
+
+impl<T: Table> Clone for Loader<T> {
+    fn clone(&self) -> Self {
+        Loader {
+            pool: self.pool.clone(),
+            batch: self.batch.clone(),
+        }
+    }
+}
+
+impl<T: Table> Debug for Loader<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Loader").field("pool", &self.pool).finish()
+    }
+}
+
+/// Guard of the batch being collected. If the request collecting it is dropped before the batch
+/// is queried, the waiting requests are cancelled, so that they try again.
+struct Collecting<'a, T: Table>(&'a Mutex<Option<Batch<T>>>);
+
+impl<T: Table> Collecting<'_, T> {
+    fn take(self) -> Batch<T> {
+        let mut batch = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let batch = batch.take().unwrap_or_default();
+        // a new batch might be collected from now on, which mustn't be dropped
+        std::mem::forget(self);
+        batch
+    }
+}
+
+impl<T: Table> Drop for Collecting<'_, T> {
+    fn drop(&mut self) {
+        let mut batch = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        batch.take();
+    }
+}
+
+/// The error of a batched query, copied for each request waiting for it, since `sqlx::Error`
+/// can't be cloned.
+enum BatchError {
+    /// shared by the copies, which keeps e.g. its SQLSTATE
+    Database(Arc<Box<dyn DatabaseError>>),
+    Other(sqlx::Error),
+}
+
+impl BatchError {
+    fn new(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::Database(error) => BatchError::Database(Arc::new(error)),
+            error => BatchError::Other(error),
+        }
+    }
+
+    fn copy(&self) -> sqlx::Error {
+        let error = match self {
+            BatchError::Database(error) => {
+                return sqlx::Error::Database(Box::new(SharedDatabaseError(error.clone())))
+            }
+            BatchError::Other(error) => error,
+        };
+        match error {
+            sqlx::Error::Io(error) => {
+                sqlx::Error::Io(io::Error::new(error.kind(), error.to_string()))
+            }
+            sqlx::Error::Protocol(message) => sqlx::Error::Protocol(message.clone()),
+            sqlx::Error::RowNotFound => sqlx::Error::RowNotFound,
+            sqlx::Error::TypeNotFound { type_name } => sqlx::Error::TypeNotFound {
+                type_name: type_name.clone(),
+            },
+            sqlx::Error::ColumnIndexOutOfBounds { index, len } => {
+                sqlx::Error::ColumnIndexOutOfBounds {
+                    index: *index,
+                    len: *len,
+                }
+            }
+            sqlx::Error::ColumnNotFound(column) => sqlx::Error::ColumnNotFound(column.clone()),
+            sqlx::Error::PoolTimedOut => sqlx::Error::PoolTimedOut,
+            sqlx::Error::PoolClosed => sqlx::Error::PoolClosed,
+            sqlx::Error::WorkerCrashed => sqlx::Error::WorkerCrashed,
+            // errors wrapping a `dyn Error`, which neither can be cloned nor shared
+            error => sqlx::Error::Protocol(format!("the batched query failed: {}", error)),
+        }
+    }
+
+    fn into_error(self) -> sqlx::Error {
+        match self {
+            BatchError::Database(error) => match Arc::try_unwrap(error) {
+                Ok(error) => sqlx::Error::Database(error),
+                Err(error) => sqlx::Error::Database(Box::new(SharedDatabaseError(error))),
+            },
+            BatchError::Other(error) => error,
+        }
+    }
+}
+
+/// A database error returned to more than one request of a batch. Downcasting it using
+/// `try_downcast_ref` yields the original error.
+#[derive(Debug)]
+struct SharedDatabaseError(Arc<Box<dyn DatabaseError>>);
+
+impl Display for SharedDatabaseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl StdError for SharedDatabaseError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.0.as_error().source()
+    }
+}
+
+impl DatabaseError for SharedDatabaseError {
+    fn message(&self) -> &str {
+        self.0.message()
+    }
+
+    fn code(&self) -> Option<Cow<'_, str>> {
+        self.0.code()
+    }
+
+    fn constraint(&self) -> Option<&str> {
+        self.0.constraint()
+    }
+
+    fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+        self.0.as_error()
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+        self
+    }
+}
+
+/// Returns `Pending` once, so that the other requests of the same tick can join the batch.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::pool::PoolOptions;
+
+    use super::*;
+    use crate::fixtures::{Recording, Row};
+
+    /// A loader whose queries fail with `sqlx::Error::PoolClosed`.
+    fn closed_loader() -> Loader<Row> {
+        let pool = PoolOptions::new().connect_lazy_with(Default::default());
+        drop(pool.close());
+        Loader::new(pool)
+    }
+
+    #[tokio::test]
+    async fn batches_requests() {
+        let recording = Recording::start();
+        let loader = closed_loader();
+        let (a, b, c) = futures::join!(loader.load(1), loader.load(2), loader.load(1));
+        for result in [a, b, c] {
+            assert!(matches!(result, Err(sqlx::Error::PoolClosed)));
+        }
+        assert_eq!(recording.take("rows"), ["get_by_ids"]);
+
+        assert!(loader.load_many(vec![3, 4, 3]).await.is_err());
+        assert_eq!(recording.take("rows"), ["get_by_ids"]);
+    }
+
+    #[tokio::test]
+    async fn sequential_loads_are_not_batched() {
+        let recording = Recording::start();
+        let loader = closed_loader();
+        assert!(loader.load(1).await.is_err());
+        assert!(loader.load(2).await.is_err());
+        assert_eq!(recording.take("rows"), ["get_by_ids", "get_by_ids"]);
+    }
+
+    #[derive(Debug)]
+    struct Deadlock;
+
+    impl Display for Deadlock {
+        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            f.write_str("deadlock detected")
+        }
+    }
+
+    impl StdError for Deadlock {}
+
+    impl DatabaseError for Deadlock {
+        fn message(&self) -> &str {
+            "deadlock detected"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed("40P01"))
+        }
+
+        fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+            self
+        }
+    }
+
+    #[test]
+    fn database_errors_are_shared() {
+        let error = BatchError::new(sqlx::Error::Database(Box::new(Deadlock)));
+        let copy = error.copy();
+        for error in [&copy, &error.into_error()] {
+            assert!(crate::transaction::is_retryable(error));
+            let error = error.as_database_error().unwrap();
+            assert!(error.try_downcast_ref::<Deadlock>().is_some());
+            assert_eq!(error.to_string(), "deadlock detected");
+        }
+
+        let error = BatchError::new(sqlx::Error::Database(Box::new(Deadlock)));
+        let error = error.into_error().into_database_error().unwrap();
+        assert!(error.try_downcast::<Deadlock>().is_ok());
+    }
+
+    #[test]
+    fn other_errors_are_copied() {
+        let error = BatchError::new(sqlx::Error::Io(io::ErrorKind::ConnectionReset.into()));
+        match error.copy() {
+            sqlx::Error::Io(copy) => assert_eq!(copy.kind(), io::ErrorKind::ConnectionReset),
+            copy => panic!("unexpected error {:?}", copy),
+        }
+        let error = BatchError::new(sqlx::Error::Decode("invalid".into()));
+        assert!(matches!(error.copy(), sqlx::Error::Protocol(_)));
+        assert!(matches!(error.into_error(), sqlx::Error::Decode(_)));
+    }
+
+    #[cfg(feature = "memory")]
+    mod memory {
+        use super::*;
+        use crate::{
+            fixtures::MemoryRow,
+            memory::{pool, Store},
+        };
+
+        fn row(id: i32) -> MemoryRow {
+            MemoryRow {
+                id,
+                name: id.to_string(),
+            }
+        }
+
+        #[tokio::test]
+        async fn loads_rows() {
+            let store = Store::new();
+            store.insert(row(1)).unwrap();
+            store.insert(row(2)).unwrap();
+            let loader = Loader::<MemoryRow>::new(pool());
+            store
+                .run(async {
+                    let (a, b, c) = futures::join!(loader.load(1), loader.load(2), loader.load(1));
+                    assert_eq!(
+                        (a.unwrap(), b.unwrap(), c.unwrap()),
+                        (row(1), row(2), row(1))
+                    );
+
+                    let rows = loader.load_many(vec![2, 1, 2]).await.unwrap();
+                    assert_eq!(rows, vec![row(2), row(1), row(2)]);
+                })
+                .await;
+        }
+
+        #[tokio::test]
+        async fn missing_rows() {
+            let store = Store::new();
+            store.insert(row(1)).unwrap();
+            let loader = Loader::<MemoryRow>::new(pool());
+            store
+                .run(async {
+                    let (a, b) = futures::join!(loader.load(1), loader.load(-1));
+                    assert_eq!(a.unwrap(), row(1));
+                    assert!(matches!(b, Err(sqlx::Error::RowNotFound)));
+
+                    let rows = loader.load_many(vec![-1, 1]).await.unwrap();
+                    assert_eq!(rows, vec![row(1)]);
+                })
+                .await;
+        }
+    }
+}
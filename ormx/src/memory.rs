@@ -0,0 +1,521 @@
+//! An in-memory store for unit tests, which doesn't require a database.
+//!
+//! With the `memory` feature of ormx, tables marked with `#[ormx(memory)]` read and write rows
+//! from a [Store] instead of querying the database. This covers `get`, `get_by_ids`, `all` and
+//! `stream_all`, `update`, `insert`, `delete_row`, getters and setters, as well as `patch_row` of
+//! patches marked with `#[ormx(memory)]`, with the same semantics as the queries:
+//!
+//! - `get`, `get_one` getters and `delete_row` return `sqlx::Error::RowNotFound` if there is no
+//!   matching row, while updating a row which doesn't exist does nothing.
+//! - IDs marked with `#[ormx(default)]` are generated from a sequence starting at 1, which is only
+//!   supported for integer IDs (see [GeneratedId]). Other fields marked with `#[ormx(default)]`
+//!   are set to `Default::default()`.
+//! - inserting a row whose ID already exists fails with a [UniqueViolation].
+//! - `get_many_contains` getters compare the JSON values of the field and the argument like the
+//!   `@>` operator of postgres, see [json_contains].
+//!
+//! Hooks and validation run just like with a database, but audit logs are not written.
+//! [select](crate::select) queries, [Tracked](crate::Tracked), custom queries and the queries of
+//! hooks run on the executor they are given, so they fail with `sqlx::Error::PoolClosed` when
+//! given the pool returned by [pool].
+//!
+//! The attribute is meant to be enabled for unit tests only, so that all other builds, including
+//! integration tests, keep querying the database:
+//!
+//! ```rust,ignore
+//! #[derive(ormx::Table, Clone)]
+//! #[ormx(table = "users", id = id, insertable)]
+//! #[cfg_attr(test, ormx(memory))]
+//! struct User { .. }
+//!
+//! #[derive(ormx::Patch)]
+//! #[ormx(table_name = "users", table = User, id = "id")]
+//! #[cfg_attr(test, ormx(memory))]
+//! struct UpdateName { .. }
+//! ```
+//!
+//! Patches have to repeat the attribute of their table, which is checked at compile time.
+//! Tables with `#[ormx(memory)]` have to implement `Clone` and must not have lifetime parameters.
+//! Since the generated methods still expect an executor, [pool()] returns a pool which never
+//! connects to a database.
+//!
+//! ```rust,ignore
+//! #[tokio::test]
+//! async fn rename_user() -> sqlx::Result<()> {
+//!     let db = ormx::memory::pool();
+//!     let user = InsertUser { name: "alice".into() }.insert(&db).await?;
+//!     rename(&db, user.id, "bob").await?;
+//!     assert_eq!(User::get(&db, user.id).await?.name, "bob");
+//!     Ok(())
+//! }
+//! ```
+//!
+//! By default, all threads share one store, so tests running in parallel should each use their
+//! own store by running within [Store::run]. The store given to [Store::run] is only used by the
+//! future passed to it, so tasks spawned within it have to be wrapped as well, e.g. using
+//! `tokio::spawn(Store::current().run(task))`.
+
+use std::{
+    any::{Any, TypeId},
+    borrow::Cow,
+    cell::RefCell,
+    collections::HashMap,
+    error::Error as StdError,
+    fmt::{self, Display, Formatter},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, OnceLock, PoisonError},
+    task::{Context, Poll},
+};
+
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{error::DatabaseError, pool::PoolOptions, Pool, Result};
+
+use crate::{Db, Table};
+
+thread_local! {
+    /// the store given to [Store::run], while its future is being polled
+    static CURRENT: RefCell<Option<Store>> = const { RefCell::new(None) };
+}
+
+/// the store used outside of [Store::run]
+static DEFAULT: OnceLock<Store> = OnceLock::new();
+
+/// Returns a pool which never connects to a database, to be passed to the generated methods.
+/// The pool is closed, so queries run on it fail with `sqlx::Error::PoolClosed`.
+pub fn pool() -> Pool<Db> {
+    let pool = PoolOptions::new()
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_lazy_with(Default::default());
+    // closing the pool takes effect immediately, the returned future only waits for connections
+    // to be released
+    drop(pool.close());
+    pool
+}
+
+/// An ID which can be generated by the [Store] for fields marked with `#[ormx(default)]`.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be generated by `ormx::memory`",
+    label = "`#[ormx(default)]` IDs must be integers when using `ormx::memory`"
+)]
+pub trait GeneratedId {
+    /// Converts the next value of the sequence of a table into an ID.
+    fn from_sequence(value: i64) -> Self;
+}
+
+macro_rules! impl_generated_id {
+    ($($ty:ty),*) => {
+        $(
+            impl GeneratedId for $ty {
+                fn from_sequence(value: i64) -> Self {
+                    value as $ty
+                }
+            }
+        )*
+    };
+}
+
+impl_generated_id!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// Whether the JSON representation of `value` contains the one of `contained`, following the
+/// `@>` operator of postgres: objects contain objects with a subset of their keys whose values
+/// they contain, arrays contain arrays whose elements are each contained in one of their
+/// elements, as well as primitive values which are one of their elements, and primitive values
+/// only contain themselves.
+pub fn json_contains<T, U>(value: &T, contained: &U) -> bool
+where
+    T: Serialize + ?Sized,
+    U: Serialize + ?Sized,
+{
+    match (serde_json::to_value(value), serde_json::to_value(contained)) {
+        (Ok(value), Ok(contained)) => contains(&value, &contained, true),
+        _ => false,
+    }
+}
+
+fn contains(value: &Value, contained: &Value, top_level: bool) -> bool {
+    match (value, contained) {
+        (Value::Object(value), Value::Object(contained)) => contained
+            .iter()
+            .all(|(key, c)| value.get(key).is_some_and(|v| contains(v, c, false))),
+        (Value::Array(value), Value::Array(contained)) => contained
+            .iter()
+            .all(|c| value.iter().any(|v| contains(v, c, false))),
+        (Value::Array(value), contained) if top_level && !contained.is_object() => {
+            value.contains(contained)
+        }
+        (value, contained) => !value.is_object() && !value.is_array() && value == contained,
+    }
+}
+
+/// The rows of all tables, see the [module docs](self). Clones of a store share the same rows.
+#[derive(Clone, Default)]
+pub struct Store {
+    tables: Arc<Mutex<HashMap<TypeId, Box<dyn Any + Send>>>>,
+}
+
+struct Rows<T> {
+    rows: Vec<T>,
+    /// the last ID generated for the table
+    sequence: i64,
+}
+
+impl Store {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Store::default()
+    }
+
+    /// Returns the store used by the generated methods, which is either the store given to
+    /// [Store::run], or the store shared by all threads.
+    pub fn current() -> Store {
+        CURRENT
+            .with(|current| current.borrow().clone())
+            .unwrap_or_else(|| DEFAULT.get_or_init(Store::new).clone())
+    }
+
+    /// Runs `future`, using this store within it.
+    pub fn run<F: Future>(&self, future: F) -> Run<F> {
+        Run {
+            store: Some(self.clone()),
+            future: Box::pin(future),
+        }
+    }
+
+    /// Returns all rows of the table.
+    pub fn rows<T: Table + Clone + 'static>(&self) -> Vec<T> {
+        self.with_rows(|rows: &mut Rows<T>| rows.rows.clone())
+    }
+
+    /// Inserts a row, failing with a [UniqueViolation] if a row with the same ID exists.
+    pub fn insert<T>(&self, row: T) -> Result<()>
+    where
+        T: Table + Clone + 'static,
+        T::Id: PartialEq,
+    {
+        self.with_rows(|rows: &mut Rows<T>| {
+            if rows.rows.iter().any(|existing| existing.id() == row.id()) {
+                return Err(sqlx::Error::Database(Box::new(UniqueViolation)));
+            }
+            rows.rows.push(row);
+            Ok(())
+        })
+    }
+
+    /// Removes all rows of all tables, and resets the sequences generating IDs.
+    pub fn clear(&self) {
+        self.tables
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    #[doc(hidden)]
+    pub fn get<T>(&self, id: T::Id) -> Result<T>
+    where
+        T: Table + Clone + 'static,
+        T::Id: PartialEq,
+    {
+        self.with_rows(|rows: &mut Rows<T>| {
+            let row = rows.rows.iter().find(|row| row.id() == id);
+            row.cloned().ok_or(sqlx::Error::RowNotFound)
+        })
+    }
+
+    #[doc(hidden)]
+    pub fn filter<T: Table + Clone + 'static>(&self, filter: impl Fn(&T) -> bool) -> Vec<T> {
+        self.with_rows(|rows: &mut Rows<T>| {
+            rows.rows
+                .iter()
+                .filter(|row| filter(row))
+                .cloned()
+                .collect()
+        })
+    }
+
+    /// Modifies the row with the given ID, returning whether it exists.
+    #[doc(hidden)]
+    pub fn update<T>(&self, id: T::Id, update: impl FnOnce(&mut T)) -> bool
+    where
+        T: Table + Clone + 'static,
+        T::Id: PartialEq,
+    {
+        self.with_rows(|rows: &mut Rows<T>| {
+            let row = rows.rows.iter_mut().find(|row| row.id() == id);
+            row.map(update).is_some()
+        })
+    }
+
+    /// Deletes the row with the given ID, returning whether it existed.
+    #[doc(hidden)]
+    pub fn delete<T>(&self, id: T::Id) -> bool
+    where
+        T: Table + Clone + 'static,
+        T::Id: PartialEq,
+    {
+        self.with_rows(|rows: &mut Rows<T>| {
+            let len = rows.rows.len();
+            rows.rows.retain(|row| row.id() != id);
+            rows.rows.len() < len
+        })
+    }
+
+    #[doc(hidden)]
+    pub fn next_id<T: Table + Clone + 'static>(&self) -> i64 {
+        self.with_rows(|rows: &mut Rows<T>| {
+            rows.sequence += 1;
+            rows.sequence
+        })
+    }
+
+    fn with_rows<T: Table + Clone + 'static, R>(&self, f: impl FnOnce(&mut Rows<T>) -> R) -> R {
+        let mut tables = self.tables.lock().unwrap_or_else(PoisonError::into_inner);
+        let rows = tables.entry(TypeId::of::<T>()).or_insert_with(|| {
+            Box::new(Rows::<T> {
+                rows: vec![],
+                sequence: 0,
+            })
+        });
+        f(rows.downcast_mut().unwrap())
+    }
+}
+
+/// A future running with a store, see [Store::run].
+pub struct Run<F> {
+    store: Option<Store>,
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for Run<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let previous = CURRENT.with(|current| current.replace(this.store.take()));
+        // restores the previous store even if polling panics
+        let _guard = Restore {
+            store: &mut this.store,
+            previous,
+        };
+        this.future.as_mut().poll(cx)
+    }
+}
+
+struct Restore<'a> {
+    store: &'a mut Option<Store>,
+    previous: Option<Store>,
+}
+
+impl Drop for Restore<'_> {
+    fn drop(&mut self) {
+        *self.store = CURRENT.with(|current| current.replace(self.previous.take()));
+    }
+}
+
+/// The error returned when inserting a row whose ID already exists, wrapped in
+/// `sqlx::Error::Database`.
+#[derive(Debug)]
+pub struct UniqueViolation;
+
+impl Display for UniqueViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("duplicate key value violates unique constraint")
+    }
+}
+
+impl StdError for UniqueViolation {}
+
+impl DatabaseError for UniqueViolation {
+    fn message(&self) -> &str {
+        "duplicate key value violates unique constraint"
+    }
+
+    /// The SQLSTATE of a unique violation.
+    fn code(&self) -> Option<Cow<'_, str>> {
+        match cfg!(feature = "postgres") {
+            true => Some(Cow::Borrowed("23505")),
+            false => Some(Cow::Borrowed("23000")),
+        }
+    }
+
+    fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        fixtures::{InsertMemoryRow, MemoryRow, Other, Row},
+        Insert,
+    };
+
+    fn row(id: i32, name: &str) -> Row {
+        Row {
+            id,
+            name: name.to_owned(),
+        }
+    }
+
+    #[test]
+    fn insert_and_get() {
+        let store = Store::new();
+        store.insert(row(1, "a")).unwrap();
+        store.insert(row(2, "b")).unwrap();
+        assert_eq!(store.get::<Row>(2).unwrap(), row(2, "b"));
+        assert!(matches!(store.get::<Row>(3), Err(sqlx::Error::RowNotFound)));
+        assert_eq!(store.rows::<Row>(), vec![row(1, "a"), row(2, "b")]);
+        assert_eq!(store.filter(|r: &Row| r.name == "b"), vec![row(2, "b")]);
+        assert!(store.rows::<Other>().is_empty());
+    }
+
+    #[test]
+    fn duplicate_ids() {
+        let store = Store::new();
+        store.insert(row(1, "a")).unwrap();
+        let err = store.insert(row(1, "b")).unwrap_err();
+        let err = err.as_database_error().unwrap();
+        assert!(err.try_downcast_ref::<UniqueViolation>().is_some());
+        assert_eq!(store.rows::<Row>(), vec![row(1, "a")]);
+    }
+
+    #[test]
+    fn update_and_delete() {
+        let store = Store::new();
+        store.insert(row(1, "a")).unwrap();
+        assert!(store.update(1, |r: &mut Row| r.name = "b".into()));
+        assert!(!store.update(2, |r: &mut Row| r.name = "c".into()));
+        assert_eq!(store.rows::<Row>(), vec![row(1, "b")]);
+        assert!(store.delete::<Row>(1));
+        assert!(!store.delete::<Row>(1));
+        assert!(store.rows::<Row>().is_empty());
+    }
+
+    #[test]
+    fn sequences() {
+        let store = Store::new();
+        assert_eq!(store.next_id::<Row>(), 1);
+        assert_eq!(store.next_id::<Row>(), 2);
+        assert_eq!(store.next_id::<Other>(), 1);
+        assert_eq!(i32::from_sequence(store.next_id::<Row>()), 3);
+        store.insert(row(3, "a")).unwrap();
+        store.clear();
+        assert!(store.rows::<Row>().is_empty());
+        assert_eq!(store.next_id::<Row>(), 1);
+    }
+
+    #[test]
+    fn clones_share_rows() {
+        let store = Store::new();
+        store.clone().insert(row(1, "a")).unwrap();
+        assert_eq!(store.rows::<Row>(), vec![row(1, "a")]);
+    }
+
+    #[tokio::test]
+    async fn run_sets_the_current_store() {
+        let store = Store::new();
+        // the default store is shared by all tests, so only `Other` is used with it
+        Store::current().insert(Other { id: 1 }).unwrap();
+        store
+            .run(async {
+                Store::current().insert(Other { id: 1 }).unwrap();
+                Store::current().insert(Other { id: 2 }).unwrap();
+                tokio::task::yield_now().await;
+                assert_eq!(Store::current().rows::<Other>().len(), 2);
+            })
+            .await;
+        assert_eq!(store.rows::<Other>().len(), 2);
+        assert!(matches!(
+            Store::current().get::<Other>(2),
+            Err(sqlx::Error::RowNotFound)
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn default_store_is_shared_by_threads() {
+        tokio::spawn(async { Store::current().insert(Other { id: 3 }) })
+            .await
+            .unwrap()
+            .unwrap();
+        let found = std::thread::spawn(|| Store::current().get::<Other>(3))
+            .join()
+            .unwrap();
+        assert_eq!(found.unwrap(), Other { id: 3 });
+    }
+
+    #[tokio::test]
+    async fn generated_methods_use_the_store() -> Result<()> {
+        let store = Store::new();
+        let db = pool();
+        store
+            .run(async {
+                let insert = InsertMemoryRow {
+                    id: 1,
+                    name: "a".into(),
+                };
+                let mut inserted = insert.insert(&db).await?;
+                inserted.name = "b".into();
+                inserted.update(&db).await?;
+                assert_eq!(MemoryRow::get(&db, 1).await?.name, "b");
+                assert!(matches!(
+                    MemoryRow::get(&db, 2).await,
+                    Err(sqlx::Error::RowNotFound)
+                ));
+                Ok::<_, sqlx::Error>(())
+            })
+            .await?;
+        assert_eq!(store.rows::<MemoryRow>().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pool_never_connects() {
+        let db = pool();
+        let err = Row::get(&db, 1).await.unwrap_err();
+        assert!(matches!(err, sqlx::Error::PoolClosed));
+    }
+
+    #[test]
+    fn json_containment() {
+        let cases = [
+            (json!(["foo", "bar"]), json!("foo"), true),
+            (json!(["foo", "bar"]), json!(["bar"]), true),
+            (json!({"a": [1, 2]}), json!({"a": 1}), false),
+            (json!([[1, 2]]), json!([1]), false),
+            (json!([1, [2, 3]]), json!([[3]]), true),
+            (json!(1), json!([1]), false),
+            (json!([{"a": 1, "b": 2}]), json!([{"a": 1}]), true),
+            (json!({"a": 1}), json!({"a": 1, "b": 2}), false),
+            (json!("a"), json!("a"), true),
+            (json!(null), json!(null), true),
+            (json!([1, 2]), json!([]), true),
+            (json!({"a": 1}), json!({}), true),
+            (json!([[1]]), json!([[]]), true),
+            (json!([1]), json!({}), false),
+        ];
+        for (value, contained, expected) in cases {
+            assert_eq!(
+                json_contains(&value, &contained),
+                expected,
+                "{} @> {}",
+                value,
+                contained
+            );
+        }
+        assert!(json_contains(&vec!["a", "b"], &["b"]));
+        assert!(!json_contains(&None::<Vec<i32>>, &[1]));
+    }
+}
@@ -0,0 +1,381 @@
+//! Detection of N+1 queries in debug builds.
+//!
+//! Running a generated method once for each row of a previous query, e.g. `User::get` for the
+//! author of each post, is a common cause of slow requests, and easily missed in review. Within a
+//! [scope], e.g. the handler of an HTTP request, a warning is logged once the same generated
+//! method ran with [DEFAULT_THRESHOLD] different arguments:
+//!
+//! ```rust,ignore
+//! let posts = ormx::n_plus_one::scope(async {
+//!     let posts = Post::all(&pool).await?;
+//!     for post in &posts {
+//!         // warns once this ran for 5 different authors, suggesting `GetByIds::get_by_ids`
+//!         let author = User::get(&pool, post.author_id).await?;
+//!     }
+//!     Ok(posts)
+//! })
+//! .await?;
+//! ```
+//!
+//! Only the methods reading rows by a single argument, `get` and the getters, are counted. The
+//! warning names the table, the method and the location of the call which reached the threshold.
+//! It is emitted using `tracing::warn!` with the `tracing` feature, and `log::warn!` otherwise.
+//!
+//! Calls are told apart by the hash of their argument, e.g. the ID passed to `get`, so that
+//! querying the same row repeatedly doesn't warn. Calls whose argument doesn't implement `Hash`,
+//! e.g. the IDs of generic tables, are all counted. Only the calls made
+//! while the future of the scope is polled are counted, so methods called by tasks spawned within
+//! it aren't.
+//!
+//! In release builds, [scope] just awaits the future.
+
+#[cfg(debug_assertions)]
+use std::{
+    cell::RefCell,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::Hasher,
+    panic::Location,
+    sync::{Arc, Mutex, PoisonError},
+};
+use std::{future::Future, hash::Hash};
+
+#[cfg(debug_assertions)]
+use crate::observe::Query;
+
+/// The number of calls of the same method with different arguments within a scope after which a
+/// warning is logged.
+pub const DEFAULT_THRESHOLD: usize = 5;
+
+/// Runs `future`, warning about N+1 queries made by it, see the [module docs](self).
+pub async fn scope<F: Future>(future: F) -> F::Output {
+    scope_with_threshold(DEFAULT_THRESHOLD, future).await
+}
+
+/// Like [scope], but warns once the same method ran with `threshold` different arguments.
+pub async fn scope_with_threshold<F: Future>(threshold: usize, future: F) -> F::Output {
+    #[cfg(debug_assertions)]
+    let output = {
+        let scope = Arc::new(Scope {
+            threshold,
+            calls: Mutex::new(HashMap::new()),
+        });
+        futures::pin_mut!(future);
+        futures::future::poll_fn(|cx| {
+            let _entered = Entered::new(scope.clone());
+            future.as_mut().poll(cx)
+        })
+        .await
+    };
+    #[cfg(not(debug_assertions))]
+    let output = {
+        let _ = threshold;
+        future.await
+    };
+    output
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    /// the scope being polled on this thread
+    static CURRENT: RefCell<Option<Arc<Scope>>> = const { RefCell::new(None) };
+}
+
+/// the scope being polled on this thread, if any
+#[cfg(debug_assertions)]
+fn current() -> Option<Arc<Scope>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+#[cfg(debug_assertions)]
+struct Scope {
+    threshold: usize,
+    calls: Mutex<HashMap<Query, Calls>>,
+}
+
+/// The calls of a method within a scope.
+#[cfg(debug_assertions)]
+#[derive(Default)]
+struct Calls {
+    /// hashes of the distinct arguments
+    keys: HashSet<u64>,
+    /// the number of calls whose argument couldn't be hashed
+    unkeyed: usize,
+}
+
+#[cfg(debug_assertions)]
+impl Calls {
+    /// Counts a call, returning whether its argument hasn't been seen before.
+    fn add(&mut self, key: Option<u64>) -> bool {
+        match key {
+            Some(key) => self.keys.insert(key),
+            None => {
+                self.unkeyed += 1;
+                true
+            }
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.keys.len() + self.unkeyed
+    }
+}
+
+/// The argument of a call of a generated method, which is hashed if it implements `Hash`.
+/// The hash is obtained using `(&Key(&arg)).key()` with [HashKey] and [NoKey] in scope.
+#[doc(hidden)]
+pub struct Key<'a, T: ?Sized>(pub &'a T);
+
+#[doc(hidden)]
+pub trait HashKey {
+    fn key(&self) -> Option<u64>;
+}
+
+impl<T: Hash + ?Sized> HashKey for Key<'_, T> {
+    fn key(&self) -> Option<u64> {
+        #[cfg(debug_assertions)]
+        {
+            let mut hasher = DefaultHasher::new();
+            self.0.hash(&mut hasher);
+            Some(hasher.finish())
+        }
+        #[cfg(not(debug_assertions))]
+        None
+    }
+}
+
+/// Chosen by `(&Key(&arg)).key()` if the argument doesn't implement `Hash`.
+#[doc(hidden)]
+pub trait NoKey {
+    fn key(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl<T: ?Sized> NoKey for &Key<'_, T> {}
+
+/// A call of a generated method reading rows, which is counted by the current scope once it
+/// finished.
+#[doc(hidden)]
+pub struct Call {
+    #[cfg(debug_assertions)]
+    key: Option<u64>,
+    #[cfg(debug_assertions)]
+    location: &'static Location<'static>,
+}
+
+impl Call {
+    /// A call with the hash `key` of its argument. The generated method is `#[track_caller]`, so
+    /// that this captures the location of its call.
+    #[track_caller]
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    pub fn new(key: Option<u64>) -> Self {
+        Call {
+            #[cfg(debug_assertions)]
+            key,
+            #[cfg(debug_assertions)]
+            location: Location::caller(),
+        }
+    }
+}
+
+/// Makes a scope the current one while it's polled, restoring the enclosing scope afterwards.
+#[cfg(debug_assertions)]
+struct Entered {
+    enclosing: Option<Arc<Scope>>,
+}
+
+#[cfg(debug_assertions)]
+impl Entered {
+    fn new(scope: Arc<Scope>) -> Self {
+        let enclosing = CURRENT.with(|current| current.replace(Some(scope)));
+        Entered { enclosing }
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Drop for Entered {
+    fn drop(&mut self) {
+        let enclosing = self.enclosing.take();
+        CURRENT.with(|current| *current.borrow_mut() = enclosing);
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Scope {
+    /// Counts a call of the method `query`, returning the number of its calls if it reached the
+    /// threshold with this call.
+    fn add(&self, query: Query, key: Option<u64>) -> Option<usize> {
+        let mut calls = self.calls.lock().unwrap_or_else(PoisonError::into_inner);
+        let calls = calls.entry(query).or_default();
+        let new = calls.add(key);
+        (new && calls.count() == self.threshold).then(|| calls.count())
+    }
+}
+
+/// Counts a finished call of a generated method, warning if it ran with too many different
+/// arguments in the current scope.
+#[cfg(debug_assertions)]
+pub(crate) fn record(query: Query, call: &Call) {
+    let scope = match current() {
+        Some(scope) => scope,
+        None => return,
+    };
+    if let Some(calls) = scope.add(query, call.key) {
+        let message = message(query, calls, call.location);
+        #[cfg(feature = "tracing")]
+        tracing::warn!(
+            table = query.table,
+            operation = query.operation,
+            "{}",
+            message
+        );
+        #[cfg(not(feature = "tracing"))]
+        log::warn!("{}", message);
+    }
+}
+
+#[cfg(debug_assertions)]
+fn message(query: Query, calls: usize, location: &Location<'_>) -> String {
+    let suggestion = match query.operation {
+        "get" => "`GetByIds::get_by_ids` or an `ormx::loader::Loader`",
+        _ => "a single query for all rows, e.g. `Table::select()` with `is_in`",
+    };
+    format!(
+        "possible N+1 query: `{}` on table {} ran with {} different arguments within one scope, \
+         called at {}; consider using {}",
+        query.operation, query.table, calls, location, suggestion
+    )
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use futures::TryStreamExt;
+    use sqlx::pool::PoolOptions;
+
+    use super::*;
+    use crate::{
+        fixtures::{Other, Row},
+        GetByIds, Table,
+    };
+
+    const GET: Query = Query {
+        table: "rows",
+        operation: "get",
+    };
+
+    fn count(scope: &Scope, query: Query) -> usize {
+        let calls = scope.calls.lock().unwrap();
+        calls.get(&query).map_or(0, Calls::count)
+    }
+
+    #[test]
+    fn calls_count_distinct_arguments() {
+        let mut calls = Calls::default();
+        assert!(calls.add(Some(1)));
+        assert!(!calls.add(Some(1)));
+        assert!(calls.add(Some(2)));
+        assert_eq!(calls.count(), 2);
+
+        assert!(calls.add(None));
+        assert!(calls.add(None));
+        assert_eq!(calls.count(), 4);
+    }
+
+    #[test]
+    fn threshold_is_reached_once() {
+        let scope = Scope {
+            threshold: 3,
+            calls: Mutex::new(HashMap::new()),
+        };
+        assert_eq!(scope.add(GET, Some(1)), None);
+        assert_eq!(scope.add(GET, Some(1)), None);
+        assert_eq!(scope.add(GET, Some(2)), None);
+        assert_eq!(scope.add(GET, Some(3)), Some(3));
+        assert_eq!(scope.add(GET, Some(4)), None);
+        assert_eq!(scope.add(GET, None), None);
+
+        let by_name = Query {
+            operation: "by_name",
+            ..GET
+        };
+        assert_eq!(scope.add(by_name, Some(1)), None);
+    }
+
+    #[tokio::test]
+    async fn nested_scopes_restore_the_enclosing_scope() {
+        assert!(current().is_none());
+        scope(async {
+            let outer = current().unwrap();
+            record(GET, &Call::new(Some(1)));
+
+            scope(async {
+                let inner = current().unwrap();
+                assert!(!Arc::ptr_eq(&inner, &outer));
+                record(GET, &Call::new(Some(2)));
+                record(GET, &Call::new(Some(3)));
+                assert_eq!(count(&inner, GET), 2);
+            })
+            .await;
+
+            assert!(Arc::ptr_eq(&current().unwrap(), &outer));
+            assert_eq!(count(&outer, GET), 1);
+        })
+        .await;
+        assert!(current().is_none());
+    }
+
+    #[tokio::test]
+    async fn only_reads_are_counted() {
+        let pool = PoolOptions::new().connect_lazy_with(Default::default());
+        drop(pool.close());
+        let row = Row {
+            id: 1,
+            name: "a".into(),
+        };
+
+        scope(async {
+            assert!(Row::get(&pool, 1).await.is_err());
+            assert!(Row::get(&pool, 1).await.is_err());
+            assert!(Row::by_name(&pool, "a").await.is_err());
+            assert!(Other::get(&pool, 1).await.is_err());
+            assert!(Row::get_by_ids(&pool, &[1, 2]).await.is_err());
+            assert!(Row::stream_all(&pool)
+                .try_collect::<Vec<_>>()
+                .await
+                .is_err());
+            assert!(row.update(&pool).await.is_err());
+
+            let scope = current().unwrap();
+            assert_eq!(count(&scope, GET), 1);
+            let by_name = Query {
+                operation: "by_name",
+                ..GET
+            };
+            assert_eq!(count(&scope, by_name), 1);
+            let others = Query {
+                table: "others",
+                ..GET
+            };
+            assert_eq!(count(&scope, others), 1);
+            assert_eq!(scope.calls.lock().unwrap().len(), 3);
+        })
+        .await;
+    }
+
+    #[test]
+    fn warning_names_the_caller() {
+        #[track_caller]
+        fn generated() -> Call {
+            Call::new(None)
+        }
+
+        let (call, line) = (generated(), line!());
+        assert_eq!(call.location.file(), file!());
+        assert_eq!(call.location.line(), line);
+
+        let message = message(GET, 5, call.location);
+        assert!(message.starts_with("possible N+1 query: `get` on table rows ran with 5"));
+        assert!(message.contains(&format!("called at {}:{}:", file!(), line)));
+        assert!(message.ends_with("`GetByIds::get_by_ids` or an `ormx::loader::Loader`"));
+    }
+}
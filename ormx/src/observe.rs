@@ -0,0 +1,387 @@
+//! Observing the queries of the generated methods, e.g. for metrics.
+//!
+//! The generated `get`, `get_by_ids`, `stream_all`, `update`, `insert`, `patch_row`,
+//! `delete_row`, getters and setters report each call to the [QueryObserver] set using
+//! [set_observer], together with the table, the operation, how long it took and how many rows it
+//! returned or modified:
+//!
+//! ```rust,ignore
+//! struct Metrics;
+//!
+//! impl QueryObserver for Metrics {
+//!     fn observe(&self, event: &Event<'_>) {
+//!         histogram!("query_seconds", event.duration, "table" => event.query.table,
+//!             "operation" => event.query.operation);
+//!     }
+//! }
+//!
+//! ormx::observe::set_observer(Metrics);
+//! ```
+//!
+//! The operation is the name of the generated method, e.g. `"get"` or `"by_email"` for a getter.
+//! `update`, `patch_row`, `delete_row` and setters report the number of rows they modified, also
+//! for tables with an audit log.
+//!
+//! With the `tracing` feature, each call additionally runs in a `tracing` span named `ormx`, with
+//! the fields `table`, `operation`, `id` (if the method is given one), `rows` and `duration_ms`.
+//! This requires the IDs of all tables to implement `Debug`.
+//!
+//! In debug builds, the calls of `get` and the getters are also counted to detect N+1 queries, see
+//! [n_plus_one](crate::n_plus_one).
+
+use std::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, PoisonError, RwLock,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures::{stream::BoxStream, Stream};
+use sqlx::Result;
+
+use crate::{n_plus_one::Call, Table};
+
+static OBSERVER: RwLock<Option<Arc<dyn QueryObserver>>> = RwLock::new(None);
+/// whether an observer is set, which avoids taking the lock otherwise
+static OBSERVED: AtomicBool = AtomicBool::new(false);
+
+/// Receives an [Event] for each call of a generated method, see the [module docs](self).
+pub trait QueryObserver: Send + Sync + 'static {
+    fn observe(&self, event: &Event<'_>);
+}
+
+/// The table and operation of a call of a generated method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Query {
+    pub table: &'static str,
+    pub operation: &'static str,
+}
+
+/// A finished call of a generated method.
+#[derive(Debug)]
+pub struct Event<'a> {
+    pub query: Query,
+    pub duration: Duration,
+    /// the number of rows returned or modified, which is 0 if the call failed
+    pub rows: u64,
+    pub error: Option<&'a sqlx::Error>,
+}
+
+/// Sets the observer receiving all following events, replacing the previous one.
+pub fn set_observer(observer: impl QueryObserver) {
+    *OBSERVER.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(observer));
+    OBSERVED.store(true, Ordering::Release);
+}
+
+/// Removes the observer set using [set_observer].
+pub fn remove_observer() {
+    OBSERVED.store(false, Ordering::Release);
+    *OBSERVER.write().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// The number of rows returned or modified by a generated method.
+pub trait Rows {
+    fn rows(&self) -> u64;
+}
+
+impl<T: Table> Rows for T {
+    fn rows(&self) -> u64 {
+        1
+    }
+}
+
+impl<T> Rows for Option<T> {
+    fn rows(&self) -> u64 {
+        self.is_some() as u64
+    }
+}
+
+impl<T> Rows for Vec<T> {
+    fn rows(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+/// The number of rows modified by `update`, `patch_row`, `delete_row` or a setter, see
+/// [modifying].
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+pub struct Modified(pub u64);
+
+impl Rows for Modified {
+    fn rows(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Observes `future`, which runs the query of a generated method. `call` is given for methods
+/// reading rows by a single argument, which are counted by [n_plus_one](crate::n_plus_one).
+#[doc(hidden)]
+pub fn future<F, T>(
+    query: Query,
+    id: Option<&dyn Debug>,
+    call: Option<Call>,
+    future: F,
+) -> impl Future<Output = Result<T>>
+where
+    F: Future<Output = Result<T>>,
+    T: Rows,
+{
+    let span = Span::new(query, id, call);
+    async move {
+        let start = Instant::now();
+        let result = span.instrument(future).await;
+        let rows = result.as_ref().map_or(0, Rows::rows);
+        span.finish(start.elapsed(), rows, result.as_ref().err());
+        result
+    }
+}
+
+/// Observes `future`, which runs the statement of a generated method modifying rows and returns
+/// the number of rows it modified.
+#[doc(hidden)]
+pub fn modifying<F>(
+    query: Query,
+    id: Option<&dyn Debug>,
+    future: F,
+) -> impl Future<Output = Result<()>>
+where
+    F: Future<Output = Result<Modified>>,
+{
+    let future = self::future(query, id, None, future);
+    async move { future.await.map(|_| ()) }
+}
+
+/// Observes `stream`, which streams the rows queried by a generated method. The call is finished
+/// once the stream ends, fails or is dropped.
+#[doc(hidden)]
+pub fn stream<'a, T: Send + 'a>(
+    query: Query,
+    stream: BoxStream<'a, Result<T>>,
+) -> BoxStream<'a, Result<T>> {
+    Box::pin(Observed {
+        span: Some(Span::new(query, None, None)),
+        stream,
+        start: Instant::now(),
+        rows: 0,
+    })
+}
+
+struct Observed<'a, T> {
+    /// `None` once the call has been reported
+    span: Option<Span>,
+    stream: BoxStream<'a, Result<T>>,
+    start: Instant,
+    rows: u64,
+}
+
+impl<T> Stream for Observed<'_, T> {
+    type Item = Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let span = match &this.span {
+            Some(span) => span,
+            None => return this.stream.as_mut().poll_next(cx),
+        };
+        let stream = &mut this.stream;
+        let next = span.in_scope(|| stream.as_mut().poll_next(cx));
+        match &next {
+            Poll::Ready(Some(Ok(_))) => this.rows += 1,
+            Poll::Ready(Some(Err(error))) => {
+                let span = this.span.take().unwrap();
+                span.finish(this.start.elapsed(), 0, Some(error));
+            }
+            Poll::Ready(None) => {
+                let span = this.span.take().unwrap();
+                span.finish(this.start.elapsed(), this.rows, None);
+            }
+            Poll::Pending => (),
+        }
+        next
+    }
+}
+
+impl<T> Drop for Observed<'_, T> {
+    fn drop(&mut self) {
+        if let Some(span) = self.span.take() {
+            span.finish(self.start.elapsed(), self.rows, None);
+        }
+    }
+}
+
+/// The query being observed, and its `tracing` span if enabled.
+struct Span {
+    query: Query,
+    #[cfg(debug_assertions)]
+    call: Option<Call>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl Span {
+    #[cfg_attr(
+        any(not(feature = "tracing"), not(debug_assertions)),
+        allow(unused_variables)
+    )]
+    fn new(query: Query, id: Option<&dyn Debug>, call: Option<Call>) -> Self {
+        Span {
+            query,
+            #[cfg(debug_assertions)]
+            call,
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!(
+                "ormx",
+                table = query.table,
+                operation = query.operation,
+                id = id.map(tracing::field::debug),
+                rows = tracing::field::Empty,
+                duration_ms = tracing::field::Empty,
+            ),
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    fn instrument<F: Future>(&self, future: F) -> impl Future<Output = F::Output> {
+        tracing::Instrument::instrument(future, self.span.clone())
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    fn instrument<F: Future>(&self, future: F) -> F {
+        future
+    }
+
+    fn in_scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        #[cfg(feature = "tracing")]
+        let _entered = self.span.enter();
+        f()
+    }
+
+    fn finish(&self, duration: Duration, rows: u64, error: Option<&sqlx::Error>) {
+        #[cfg(debug_assertions)]
+        if let Some(call) = &self.call {
+            crate::n_plus_one::record(self.query, call);
+        }
+        #[cfg(feature = "tracing")]
+        {
+            self.span.record("rows", rows);
+            self.span
+                .record("duration_ms", duration.as_secs_f64() * 1000.0);
+        }
+        if !OBSERVED.load(Ordering::Acquire) {
+            return;
+        }
+        let observer = OBSERVER
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        if let Some(observer) = observer {
+            observer.observe(&Event {
+                query: self.query,
+                duration,
+                rows,
+                error,
+            });
+        }
+    }
+}
+
+#[cfg(all(test, feature = "postgres"))]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::{
+        fixtures::{ObservedRow, Recorded, Recording},
+        testing::MockExecutor,
+        Delete,
+    };
+
+    fn recorded(operation: &'static str, rows: u64, failed: bool) -> Recorded {
+        Recorded {
+            operation,
+            rows,
+            failed,
+        }
+    }
+
+    fn row() -> ObservedRow {
+        ObservedRow {
+            id: 1,
+            name: "a".into(),
+        }
+    }
+
+    #[test]
+    fn rows() {
+        assert_eq!(row().rows(), 1);
+        assert_eq!(Some(row()).rows(), 1);
+        assert_eq!(None::<ObservedRow>.rows(), 0);
+        assert_eq!(vec![row(), row()].rows(), 2);
+        assert_eq!(Modified(3).rows(), 3);
+    }
+
+    #[tokio::test]
+    async fn reads_are_observed() {
+        let recording = Recording::start();
+        let db = MockExecutor::new();
+        assert!(ObservedRow::get(&db, 1).await.is_err());
+        assert_eq!(ObservedRow::by_name(&db, "a").await.unwrap(), None);
+        let rows = ObservedRow::stream_all(&db).try_collect::<Vec<_>>().await;
+        assert!(rows.unwrap().is_empty());
+        assert_eq!(
+            recording.take_events("observed_rows"),
+            [
+                recorded("get", 0, true),
+                recorded("by_name", 0, false),
+                recorded("stream_all", 0, false),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn modifications_are_observed() {
+        let recording = Recording::start();
+        let db = MockExecutor::new();
+        row().update(&db).await.unwrap();
+        row().set_name(&db, "b".into()).await.unwrap();
+        db.fail_next(sqlx::Error::PoolTimedOut);
+        assert!(ObservedRow::delete_row(&db, 1).await.is_err());
+        assert_eq!(
+            recording.take_events("observed_rows"),
+            [
+                recorded("update", 0, false),
+                recorded("set_name", 0, false),
+                recorded("delete_row", 0, true),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn dropped_streams_are_observed_once() {
+        let recording = Recording::start();
+        let db = MockExecutor::new();
+        drop(ObservedRow::stream_all(&db));
+        let mut rows = ObservedRow::stream_all(&db);
+        assert!(rows.try_next().await.unwrap().is_none());
+        drop(rows);
+        assert_eq!(
+            recording.take("observed_rows"),
+            ["stream_all", "stream_all"]
+        );
+    }
+
+    #[tokio::test]
+    async fn removed_observers_are_not_called() {
+        let recording = Recording::start();
+        remove_observer();
+        let db = MockExecutor::new();
+        row().update(&db).await.unwrap();
+        assert!(recording.take("observed_rows").is_empty());
+    }
+}
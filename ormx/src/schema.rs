@@ -0,0 +1,247 @@
+//! Verifies that a table in the database matches a type derived using `#[derive(ormx::Table)]`.
+//!
+//! ```rust,ignore
+//! let report = ormx::verify::<User>(&db).await?;
+//! if !report.is_ok() {
+//!     panic!("{}", report);
+//! }
+//! ```
+
+use std::fmt::{self, Display, Formatter};
+
+use ormx_types::type_family;
+use sqlx::{Executor, Result};
+
+use crate::{ColumnMeta, Db, TableMeta};
+
+/// The result of [verify], listing all differences between a table and its rust type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaReport {
+    /// Name of the verified table.
+    pub table: &'static str,
+    /// All issues found. If this is empty, the table matches its rust type.
+    pub issues: Vec<SchemaIssue>,
+}
+
+/// A difference between a table in the database and its rust type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaIssue {
+    /// The table does not exist.
+    MissingTable,
+    /// A column of the rust type does not exist in the table.
+    MissingColumn { column: &'static str },
+    /// The type of a column differs from the type expected by the rust type.
+    TypeMismatch {
+        column: &'static str,
+        expected: &'static str,
+        found: String,
+    },
+    /// The column is nullable, but the field is not an `Option<T>`, or the other way round.
+    NullabilityMismatch {
+        column: &'static str,
+        nullable: bool,
+        field_nullable: bool,
+    },
+    /// The column is left out when inserting, but has no default value.
+    MissingDefault { column: &'static str },
+}
+
+impl SchemaReport {
+    /// Returns `true` if no issues were found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl Display for SchemaReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_ok() {
+            return write!(f, "table {} matches its schema", self.table);
+        }
+        write!(f, "table {} does not match its schema:", self.table)?;
+        for issue in &self.issues {
+            write!(f, "\n  - {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for SchemaIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaIssue::MissingTable => write!(f, "table does not exist"),
+            SchemaIssue::MissingColumn { column } => write!(f, "column {} does not exist", column),
+            SchemaIssue::TypeMismatch {
+                column,
+                expected,
+                found,
+            } => write!(
+                f,
+                "column {} has type {}, but {} was expected",
+                column, found, expected
+            ),
+            SchemaIssue::NullabilityMismatch {
+                column, nullable, ..
+            } if *nullable => write!(
+                f,
+                "column {} is nullable, but the field is not an Option",
+                column
+            ),
+            SchemaIssue::NullabilityMismatch { column, .. } => write!(
+                f,
+                "column {} is NOT NULL, but the field is an Option",
+                column
+            ),
+            SchemaIssue::MissingDefault { column } => write!(
+                f,
+                "column {} is not inserted, but has no default value",
+                column
+            ),
+        }
+    }
+}
+
+/// A column as described by `information_schema.columns`.
+struct DbColumn {
+    name: String,
+    ty: String,
+    nullable: bool,
+    has_default: bool,
+}
+
+/// Checks that every column of `T` exists in its table, has a compatible type and nullability,
+/// and that columns which are left out when inserting have a default value.
+///
+/// Types of columns are only compared if their SQL type is known, see
+/// [ColumnMeta::sql_type].
+pub async fn verify<'c, T: TableMeta>(
+    db: impl Executor<'c, Database = Db>,
+) -> Result<SchemaReport> {
+    let (schema, table) = split_table_name(T::TABLE);
+    let columns = query_columns(db, schema.as_deref(), &table).await?;
+
+    let mut report = SchemaReport {
+        table: T::TABLE,
+        issues: vec![],
+    };
+    if columns.is_empty() {
+        report.issues.push(SchemaIssue::MissingTable);
+        return Ok(report);
+    }
+
+    for meta in T::COLUMNS {
+        let column = match columns.iter().find(|c| c.name == unquote(meta.name)) {
+            Some(column) => column,
+            None => {
+                report
+                    .issues
+                    .push(SchemaIssue::MissingColumn { column: meta.name });
+                continue;
+            }
+        };
+        verify_column(meta, column, &mut report.issues);
+    }
+
+    Ok(report)
+}
+
+fn verify_column(meta: &ColumnMeta, column: &DbColumn, issues: &mut Vec<SchemaIssue>) {
+    if let Some(expected) = meta.sql_type {
+        if type_family(expected) != type_family(&column.ty) {
+            issues.push(SchemaIssue::TypeMismatch {
+                column: meta.name,
+                expected,
+                found: column.ty.clone(),
+            });
+        }
+    }
+
+    if meta.nullable != column.nullable {
+        issues.push(SchemaIssue::NullabilityMismatch {
+            column: meta.name,
+            nullable: column.nullable,
+            field_nullable: meta.nullable,
+        });
+    }
+
+    if meta.default && !column.has_default && !column.nullable {
+        issues.push(SchemaIssue::MissingDefault { column: meta.name });
+    }
+}
+
+#[cfg(feature = "postgres")]
+async fn query_columns<'c>(
+    db: impl Executor<'c, Database = Db>,
+    schema: Option<&str>,
+    table: &str,
+) -> Result<Vec<DbColumn>> {
+    let rows: Vec<(String, String, String, String, Option<String>, String)> = sqlx::query_as(
+        "SELECT column_name::text, data_type::text, udt_name::text, is_nullable::text, \
+         column_default::text, is_identity::text \
+         FROM information_schema.columns \
+         WHERE table_schema = COALESCE($1, current_schema()) AND table_name = $2",
+    )
+    .bind(schema)
+    .bind(table)
+    .fetch_all(db)
+    .await?;
+
+    let columns = rows
+        .into_iter()
+        .map(|(name, data_type, udt_name, nullable, default, identity)| {
+            let ty = match &*data_type {
+                "ARRAY" => format!("{}[]", udt_name.trim_start_matches('_')),
+                "USER-DEFINED" => udt_name,
+                _ => data_type,
+            };
+            DbColumn {
+                name,
+                ty,
+                nullable: nullable == "YES",
+                has_default: default.is_some() || identity == "YES",
+            }
+        })
+        .collect();
+    Ok(columns)
+}
+
+#[cfg(feature = "mysql")]
+async fn query_columns<'c>(
+    db: impl Executor<'c, Database = Db>,
+    schema: Option<&str>,
+    table: &str,
+) -> Result<Vec<DbColumn>> {
+    let rows: Vec<(String, String, String, Option<String>, String)> = sqlx::query_as(
+        "SELECT CAST(COLUMN_NAME AS CHAR), CAST(COLUMN_TYPE AS CHAR), \
+         CAST(IS_NULLABLE AS CHAR), CAST(COLUMN_DEFAULT AS CHAR), CAST(EXTRA AS CHAR) \
+         FROM information_schema.COLUMNS \
+         WHERE TABLE_SCHEMA = COALESCE(?, DATABASE()) AND TABLE_NAME = ?",
+    )
+    .bind(schema)
+    .bind(table)
+    .fetch_all(db)
+    .await?;
+
+    let columns = rows
+        .into_iter()
+        .map(|(name, ty, nullable, default, extra)| DbColumn {
+            name,
+            ty,
+            nullable: nullable == "YES",
+            has_default: default.is_some() || extra.to_lowercase().contains("auto_increment"),
+        })
+        .collect();
+    Ok(columns)
+}
+
+/// Splits `schema.table` into its parts, removing identifier quotes.
+fn split_table_name(name: &str) -> (Option<String>, String) {
+    match name.rsplit_once('.') {
+        Some((schema, table)) => (Some(unquote(schema)), unquote(table)),
+        None => (None, unquote(name)),
+    }
+}
+
+fn unquote(ident: &str) -> String {
+    ident.trim_matches(|c| c == '"' || c == '`').to_owned()
+}
@@ -0,0 +1,552 @@
+//! A small query builder for tables derived using `#[derive(ormx::Table)]`.
+//!
+//! Unlike the generated CRUD functions, queries built here are not checked at compile time.
+//!
+//! ```rust,ignore
+//! use ormx::TableMeta;
+//!
+//! let col = User::columns();
+//! let users = User::select()
+//!     .filter(col.email.eq("john@example.com").or(col.first_name.like("J%")))
+//!     .order_by(col.last_login.desc())
+//!     .limit(10)
+//!     .fetch_all(&db)
+//!     .await?;
+//! ```
+
+use std::marker::PhantomData;
+
+use sqlx::{Encode, Executor, QueryBuilder, Result, Type};
+
+use crate::{Db, TableMeta};
+
+type Bind = Box<dyn FnOnce(&mut QueryBuilder<'static, Db>) + Send>;
+
+/// A column of the table `T` containing values of type `V`.
+/// Columns are obtained from [TableMeta::columns].
+pub struct Column<T, V> {
+    name: &'static str,
+    _marker: PhantomData<fn() -> (T, V)>,
+}
+
+/// A condition of a `WHERE` clause on the table `T`, created from a [Column].
+pub struct Condition<T> {
+    fragments: Vec<Fragment>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+enum Fragment {
+    Sql(String),
+    Bind(Bind),
+}
+
+/// An `ORDER BY` expression on the table `T`, created from a [Column].
+pub struct Order<T> {
+    sql: String,
+    _marker: PhantomData<fn() -> T>,
+}
+
+/// A set of conditions on a table, which can be derived using `#[derive(ormx::Filter)]`.
+pub trait Filter {
+    type Table: TableMeta;
+
+    /// Adds the conditions of this filter to the query.
+    fn apply(self, select: Select<Self::Table>) -> Select<Self::Table>;
+}
+
+/// A `SELECT` query on the table `T`, created using [TableMeta::select].
+pub struct Select<T> {
+    conditions: Vec<Condition<T>>,
+    order: Vec<Order<T>>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+impl<T, V> Column<T, V> {
+    #[doc(hidden)]
+    pub const fn new(name: &'static str) -> Self {
+        Column {
+            name,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Name of this column.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn compare(&self, op: &str, value: V) -> Condition<T>
+    where
+        V: 'static + Encode<'static, Db> + Type<Db> + Send,
+    {
+        Condition::new(vec![
+            Fragment::Sql(format!("{} {} ", self.name, op)),
+            Fragment::Bind(Box::new(move |query| {
+                query.push_bind(value);
+            })),
+        ])
+    }
+
+    /// `column = value`
+    pub fn eq(&self, value: impl Into<V>) -> Condition<T>
+    where
+        V: 'static + Encode<'static, Db> + Type<Db> + Send,
+    {
+        self.compare("=", value.into())
+    }
+
+    /// `column <> value`
+    pub fn ne(&self, value: impl Into<V>) -> Condition<T>
+    where
+        V: 'static + Encode<'static, Db> + Type<Db> + Send,
+    {
+        self.compare("<>", value.into())
+    }
+
+    /// `column < value`
+    pub fn lt(&self, value: impl Into<V>) -> Condition<T>
+    where
+        V: 'static + Encode<'static, Db> + Type<Db> + Send,
+    {
+        self.compare("<", value.into())
+    }
+
+    /// `column <= value`
+    pub fn le(&self, value: impl Into<V>) -> Condition<T>
+    where
+        V: 'static + Encode<'static, Db> + Type<Db> + Send,
+    {
+        self.compare("<=", value.into())
+    }
+
+    /// `column > value`
+    pub fn gt(&self, value: impl Into<V>) -> Condition<T>
+    where
+        V: 'static + Encode<'static, Db> + Type<Db> + Send,
+    {
+        self.compare(">", value.into())
+    }
+
+    /// `column >= value`
+    pub fn ge(&self, value: impl Into<V>) -> Condition<T>
+    where
+        V: 'static + Encode<'static, Db> + Type<Db> + Send,
+    {
+        self.compare(">=", value.into())
+    }
+
+    /// `column LIKE pattern`
+    pub fn like(&self, pattern: impl Into<String>) -> Condition<T> {
+        let pattern = pattern.into();
+        Condition::new(vec![
+            Fragment::Sql(format!("{} LIKE ", self.name)),
+            Fragment::Bind(Box::new(move |query| {
+                query.push_bind(pattern);
+            })),
+        ])
+    }
+
+    /// `column IN (values..)`. If `values` is empty, the condition is always false.
+    pub fn is_in<I>(&self, values: I) -> Condition<T>
+    where
+        I: IntoIterator,
+        I::Item: Into<V>,
+        V: 'static + Encode<'static, Db> + Type<Db> + Send,
+    {
+        let mut fragments = vec![Fragment::Sql(format!("{} IN (", self.name))];
+        for (idx, value) in values.into_iter().enumerate() {
+            if idx > 0 {
+                fragments.push(Fragment::Sql(", ".to_owned()));
+            }
+            let value = value.into();
+            fragments.push(Fragment::Bind(Box::new(move |query| {
+                query.push_bind(value);
+            })));
+        }
+        if fragments.len() == 1 {
+            return Condition::sql("1 = 0");
+        }
+        fragments.push(Fragment::Sql(")".to_owned()));
+        Condition::new(fragments)
+    }
+
+    /// `column IS NULL`
+    pub fn is_null(&self) -> Condition<T> {
+        Condition::sql(format!("{} IS NULL", self.name))
+    }
+
+    /// `column IS NOT NULL`
+    pub fn is_not_null(&self) -> Condition<T> {
+        Condition::sql(format!("{} IS NOT NULL", self.name))
+    }
+
+    /// Order by this column in ascending order.
+    pub fn asc(&self) -> Order<T> {
+        Order::new(format!("{} ASC", self.name))
+    }
+
+    /// Order by this column in descending order.
+    pub fn desc(&self) -> Order<T> {
+        Order::new(format!("{} DESC", self.name))
+    }
+}
+
+impl<T, V> Clone for Column<T, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, V> Copy for Column<T, V> {}
+
+impl<T> Condition<T> {
+    fn new(fragments: Vec<Fragment>) -> Self {
+        Condition {
+            fragments,
+            _marker: PhantomData,
+        }
+    }
+
+    fn sql(sql: impl Into<String>) -> Self {
+        Condition::new(vec![Fragment::Sql(sql.into())])
+    }
+
+    fn combine(self, op: &str, other: Condition<T>) -> Self {
+        let mut fragments = Vec::with_capacity(self.fragments.len() + other.fragments.len() + 3);
+        fragments.push(Fragment::Sql("(".to_owned()));
+        fragments.extend(self.fragments);
+        fragments.push(Fragment::Sql(format!(") {} (", op)));
+        fragments.extend(other.fragments);
+        fragments.push(Fragment::Sql(")".to_owned()));
+        Condition::new(fragments)
+    }
+
+    /// Both this and the `other` condition have to be true.
+    pub fn and(self, other: Condition<T>) -> Self {
+        self.combine("AND", other)
+    }
+
+    /// Either this or the `other` condition have to be true.
+    pub fn or(self, other: Condition<T>) -> Self {
+        self.combine("OR", other)
+    }
+
+    /// Negates this condition.
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Self {
+        let mut fragments = vec![Fragment::Sql("NOT (".to_owned())];
+        fragments.extend(self.fragments);
+        fragments.push(Fragment::Sql(")".to_owned()));
+        Condition::new(fragments)
+    }
+
+    /// Appends this condition to `query`, binding its arguments.
+    pub fn push_to(self, query: &mut QueryBuilder<'static, Db>) {
+        for fragment in self.fragments {
+            match fragment {
+                Fragment::Sql(sql) => {
+                    query.push(sql);
+                }
+                Fragment::Bind(bind) => bind(query),
+            }
+        }
+    }
+}
+
+impl<T> Order<T> {
+    fn new(sql: String) -> Self {
+        Order {
+            sql,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Default for Select<T> {
+    fn default() -> Self {
+        Select {
+            conditions: vec![],
+            order: vec![],
+            limit: None,
+            offset: None,
+        }
+    }
+}
+
+impl<T: TableMeta> Select<T> {
+    /// Only select rows matching the condition.
+    /// If called multiple times, all conditions have to match.
+    pub fn filter(mut self, condition: Condition<T>) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+
+    /// Only select rows matching the filter.
+    pub fn filter_by(self, filter: impl Filter<Table = T>) -> Self {
+        filter.apply(self)
+    }
+
+    /// Order the selected rows. If called multiple times, the rows are first ordered by the first
+    /// expression, then by the second one and so on.
+    pub fn order_by(mut self, order: Order<T>) -> Self {
+        self.order.push(order);
+        self
+    }
+
+    /// Select at most `limit` rows.
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Skip the first `offset` rows.
+    pub fn offset(mut self, offset: i64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Builds the query. The SQL can be inspected using `QueryBuilder::sql`.
+    pub fn build(self) -> QueryBuilder<'static, Db> {
+        let sql = format!("SELECT {} FROM {}", T::SELECT_COLUMNS, T::TABLE);
+        let mut query = QueryBuilder::new(crate::tag_sql::<T>("select", &sql));
+
+        for (idx, condition) in self.conditions.into_iter().enumerate() {
+            query.push(if idx == 0 { " WHERE " } else { " AND " });
+            condition.push_to(&mut query);
+        }
+
+        if !self.order.is_empty() {
+            let order = self.order.iter().map(|order| &*order.sql);
+            query.push(" ORDER BY ");
+            query.push(order.collect::<Vec<_>>().join(", "));
+        }
+
+        match (self.limit, self.offset) {
+            (Some(limit), offset) => {
+                query.push(" LIMIT ").push_bind(limit);
+                if let Some(offset) = offset {
+                    query.push(" OFFSET ").push_bind(offset);
+                }
+            }
+            #[cfg(feature = "mysql")]
+            (None, Some(offset)) => {
+                // mysql doesn't support OFFSET without LIMIT
+                query
+                    .push(" LIMIT 18446744073709551615 OFFSET ")
+                    .push_bind(offset);
+            }
+            #[cfg(not(feature = "mysql"))]
+            (None, Some(offset)) => {
+                query.push(" OFFSET ").push_bind(offset);
+            }
+            (None, None) => (),
+        }
+
+        query
+    }
+
+    /// Returns all matching rows.
+    pub async fn fetch_all<'c>(self, db: impl Executor<'c, Database = Db>) -> Result<Vec<T>> {
+        let mut query = self.build();
+        let rows = query.build().fetch_all(db).await?;
+        rows.iter().map(T::from_row).collect()
+    }
+
+    /// Returns the first matching row, failing with `sqlx::Error::RowNotFound` if there is none.
+    pub async fn fetch_one<'c>(self, db: impl Executor<'c, Database = Db>) -> Result<T> {
+        let mut query = self.build();
+        let row = query.build().fetch_one(db).await?;
+        T::from_row(&row)
+    }
+
+    /// Returns the first matching row, if any.
+    pub async fn fetch_optional<'c>(
+        self,
+        db: impl Executor<'c, Database = Db>,
+    ) -> Result<Option<T>> {
+        let mut query = self.build();
+        let row = query.build().fetch_optional(db).await?;
+        row.map(|row| T::from_row(&row)).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        fixtures::{Row, RowFilter, Tagged},
+        testing::MockExecutor,
+        TableMeta,
+    };
+
+    /// the `n`th placeholder of the database
+    fn bind(n: usize) -> String {
+        match cfg!(feature = "postgres") {
+            true => format!("${}", n),
+            false => "?".to_owned(),
+        }
+    }
+
+    #[test]
+    fn select_is_tagged() {
+        let query = Tagged::select().build();
+        assert_eq!(
+            query.sql(),
+            "/* ormx:table=tagged,op=select */ SELECT id, name FROM tagged"
+        );
+    }
+
+    #[test]
+    fn conditions_are_nested() {
+        let col = Row::columns();
+        let condition = col
+            .id
+            .gt(1)
+            .and(col.name.eq("a").or(col.name.is_null().not()));
+        let query = Row::select().filter(condition).build();
+        assert_eq!(
+            query.sql(),
+            format!(
+                "SELECT id, name FROM rows WHERE (id > {}) AND ((name = {}) OR (NOT (name IS NULL)))",
+                bind(1),
+                bind(2)
+            )
+        );
+    }
+
+    #[test]
+    fn filters_and_orders_are_joined() {
+        let col = Row::columns();
+        let query = Row::select()
+            .filter(col.id.ne(1))
+            .filter(col.name.like("a%"))
+            .order_by(col.name.asc())
+            .order_by(col.id.desc())
+            .build();
+        assert_eq!(
+            query.sql(),
+            format!(
+                "SELECT id, name FROM rows WHERE id <> {} AND name LIKE {} \
+                 ORDER BY name ASC, id DESC",
+                bind(1),
+                bind(2)
+            )
+        );
+    }
+
+    #[test]
+    fn is_in() {
+        let col = Row::columns();
+        let query = Row::select().filter(col.id.is_in([1, 2, 3])).build();
+        assert_eq!(
+            query.sql(),
+            format!(
+                "SELECT id, name FROM rows WHERE id IN ({}, {}, {})",
+                bind(1),
+                bind(2),
+                bind(3)
+            )
+        );
+    }
+
+    #[test]
+    fn empty_is_in_is_false() {
+        let col = Row::columns();
+        let query = Row::select()
+            .filter(col.id.is_in(Vec::<i32>::new()).or(col.name.eq("a")))
+            .build();
+        assert_eq!(
+            query.sql(),
+            format!(
+                "SELECT id, name FROM rows WHERE (1 = 0) OR (name = {})",
+                bind(1)
+            )
+        );
+    }
+
+    #[test]
+    fn limit_and_offset() {
+        let query = Row::select().limit(10).offset(20).build();
+        assert_eq!(
+            query.sql(),
+            format!(
+                "SELECT id, name FROM rows LIMIT {} OFFSET {}",
+                bind(1),
+                bind(2)
+            )
+        );
+        let query = Row::select().limit(10).build();
+        assert_eq!(
+            query.sql(),
+            format!("SELECT id, name FROM rows LIMIT {}", bind(1))
+        );
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn offset_without_limit() {
+        let query = Row::select().offset(20).build();
+        assert_eq!(query.sql(), "SELECT id, name FROM rows OFFSET $1");
+    }
+
+    #[cfg(feature = "mysql")]
+    #[test]
+    fn offset_without_limit() {
+        let query = Row::select().offset(20).build();
+        assert_eq!(
+            query.sql(),
+            "SELECT id, name FROM rows LIMIT 18446744073709551615 OFFSET ?"
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_runs_the_built_query() {
+        let db = MockExecutor::new();
+        let col = Row::columns();
+        let rows = Row::select()
+            .filter(col.name.eq("a"))
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert!(rows.is_empty());
+        let row = Row::select().fetch_optional(&db).await.unwrap();
+        assert!(row.is_none());
+        assert_eq!(
+            db.statements(),
+            [
+                format!("SELECT id, name FROM rows WHERE name = {}", bind(1)),
+                "SELECT id, name FROM rows".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_skip_missing_criteria() {
+        let query = Row::select().filter_by(RowFilter::default()).build();
+        assert_eq!(
+            query.sql(),
+            format!("SELECT id, name FROM rows WHERE id > {}", bind(1))
+        );
+    }
+
+    #[tokio::test]
+    async fn find_applies_the_filter() {
+        let db = MockExecutor::new();
+        let filter = RowFilter {
+            ids: Some(vec![1, 2]),
+            name: Some("a%".into()),
+            after: 0,
+        };
+        Row::find(&db, filter).await.unwrap();
+        assert_eq!(
+            db.statements(),
+            [format!(
+                "SELECT id, name FROM rows WHERE id IN ({}, {}) AND name LIKE {} AND id > {}",
+                bind(1),
+                bind(2),
+                bind(3),
+                bind(4)
+            )]
+        );
+    }
+}
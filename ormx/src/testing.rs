@@ -0,0 +1,383 @@
+//! Utilities for asserting the SQL issued by the generated methods.
+//!
+//! A [MockExecutor] can be passed wherever the generated methods expect an executor, and records
+//! the SQL of each statement executed through it, together with its arguments:
+//!
+//! ```rust,ignore
+//! let db = MockExecutor::new();
+//! user.patch(&db, UpdateName { name: "alice".into() }).await?;
+//! assert_eq!(db.statements(), ["UPDATE users SET name = $1 WHERE id = $2"]);
+//! assert_eq!(db.executed()[0].arguments, [r#""alice""#, "1"]);
+//! ```
+//!
+//! sqlx doesn't expose the arguments bound to a statement, so they are recorded by the generated
+//! methods while a [MockExecutor] exists, formatted using `Debug`. Arguments which don't implement
+//! `Debug` are recorded as `_`. The arguments of [Select](crate::Select) queries and of
+//! [Tracked::save](crate::Tracked::save) aren't recorded.
+//!
+//! By default, statements don't return any rows and affect no rows, so e.g. `Table::get` fails
+//! with `sqlx::Error::RowNotFound`. To get actual rows, statements can be forwarded to a database
+//! using [MockExecutor::forward_to]. Errors for the next statements can be given using
+//! [MockExecutor::fail_next]. sqlx doesn't allow constructing rows without a connection, so rows
+//! can't be mocked.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, PoisonError,
+    },
+};
+
+use futures::{future::BoxFuture, stream::BoxStream, FutureExt, StreamExt};
+use sqlx::{database::HasStatement, Database, Describe, Either, Execute, Executor, Pool, Result};
+
+use crate::{Db, Reborrow};
+
+/// The number of existing [MockExecutor]s. The generated methods only record their arguments while
+/// there is one.
+static EXECUTORS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// the arguments of the statement which is about to be executed on this thread
+    static ARGUMENTS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// An executor recording the SQL and the arguments of the statements executed through it, see
+/// the [module docs](self).
+#[derive(Debug)]
+pub struct MockExecutor {
+    pool: Option<Pool<Db>>,
+    statements: Mutex<Vec<Statement>>,
+    errors: Mutex<VecDeque<sqlx::Error>>,
+}
+
+/// A statement executed through a [MockExecutor].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub sql: String,
+    /// The `Debug` representations of the arguments bound to the statement, or `_` for arguments
+    /// which don't implement `Debug`. Empty if the statement wasn't executed by a generated method.
+    pub arguments: Vec<String>,
+}
+
+impl Default for MockExecutor {
+    fn default() -> Self {
+        EXECUTORS.fetch_add(1, Ordering::Relaxed);
+        MockExecutor {
+            pool: None,
+            statements: Mutex::default(),
+            errors: Mutex::default(),
+        }
+    }
+}
+
+impl Drop for MockExecutor {
+    fn drop(&mut self) {
+        EXECUTORS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl MockExecutor {
+    /// Creates an executor, for which statements return no rows.
+    pub fn new() -> Self {
+        MockExecutor::default()
+    }
+
+    /// Creates an executor which forwards the statements to the given pool after recording them.
+    pub fn forward_to(pool: Pool<Db>) -> Self {
+        let mut executor = MockExecutor::default();
+        executor.pool = Some(pool);
+        executor
+    }
+
+    /// Makes the next statement fail with the given error, after the errors given before.
+    pub fn fail_next(&self, error: sqlx::Error) {
+        lock(&self.errors).push_back(error);
+    }
+
+    /// Returns the SQL of the statements executed so far.
+    pub fn statements(&self) -> Vec<String> {
+        let statements = lock(&self.statements);
+        statements
+            .iter()
+            .map(|statement| statement.sql.clone())
+            .collect()
+    }
+
+    /// Returns the SQL of the statements executed so far, and forgets them.
+    pub fn take_statements(&self) -> Vec<String> {
+        let statements = std::mem::take(&mut *lock(&self.statements));
+        statements
+            .into_iter()
+            .map(|statement| statement.sql)
+            .collect()
+    }
+
+    /// Returns the statements executed so far, including their arguments.
+    pub fn executed(&self) -> Vec<Statement> {
+        lock(&self.statements).clone()
+    }
+
+    /// Records a statement, returning the error it should fail with, if any.
+    fn record(&self, sql: &str) -> Result<()> {
+        let arguments = ARGUMENTS.with(|arguments| arguments.borrow_mut().take());
+        lock(&self.statements).push(Statement {
+            sql: sql.to_owned(),
+            arguments: arguments.unwrap_or_default(),
+        });
+        match lock(&self.errors).pop_front() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    fn pool(&self) -> Result<&Pool<Db>> {
+        self.pool.as_ref().ok_or_else(|| {
+            sqlx::Error::Protocol("MockExecutor can't prepare statements without a pool".into())
+        })
+    }
+}
+
+/// Formats the arguments of a statement using `format`, if a [MockExecutor] exists.
+#[doc(hidden)]
+pub fn arguments(format: impl FnOnce() -> Vec<String>) -> Option<Vec<String>> {
+    match EXECUTORS.load(Ordering::Relaxed) {
+        0 => None,
+        _ => Some(format()),
+    }
+}
+
+/// Returns `query`, which is executed right away, so that a [MockExecutor] records `arguments`
+/// for it.
+#[doc(hidden)]
+pub fn with_arguments<Q>(arguments: Option<Vec<String>>, query: Q) -> Q {
+    if arguments.is_some() {
+        ARGUMENTS.with(|current| *current.borrow_mut() = arguments);
+    }
+    query
+}
+
+/// An argument of a statement, which is formatted using `Debug` if it implements it. The
+/// representation is obtained using `(&Argument(&arg)).debug()` with [DebugArgument] and
+/// [NoDebug] in scope.
+#[doc(hidden)]
+pub struct Argument<'a, T: ?Sized>(pub &'a T);
+
+#[doc(hidden)]
+pub trait DebugArgument {
+    fn debug(&self) -> String;
+}
+
+impl<T: Debug + ?Sized> DebugArgument for Argument<'_, T> {
+    fn debug(&self) -> String {
+        format!("{:?}", self.0)
+    }
+}
+
+/// Chosen by `(&Argument(&arg)).debug()` if the argument doesn't implement `Debug`.
+#[doc(hidden)]
+pub trait NoDebug {
+    fn debug(&self) -> String {
+        "_".to_owned()
+    }
+}
+
+impl<T: ?Sized> NoDebug for &Argument<'_, T> {}
+
+impl<'c> Executor<'c> for &'c MockExecutor {
+    type Database = Db;
+
+    fn fetch_many<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxStream<'e, Result<Either<<Db as Database>::QueryResult, <Db as Database>::Row>>>
+    where
+        'c: 'e,
+        E: Execute<'q, Db> + 'q,
+    {
+        if let Err(error) = self.record(query.sql()) {
+            return futures::stream::once(async { Err(error) }).boxed();
+        }
+        match &self.pool {
+            Some(pool) => pool.fetch_many(query),
+            None => futures::stream::empty().boxed(),
+        }
+    }
+
+    fn fetch_optional<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxFuture<'e, Result<Option<<Db as Database>::Row>>>
+    where
+        'c: 'e,
+        E: Execute<'q, Db> + 'q,
+    {
+        if let Err(error) = self.record(query.sql()) {
+            return async { Err(error) }.boxed();
+        }
+        match &self.pool {
+            Some(pool) => pool.fetch_optional(query),
+            None => async { Ok(None) }.boxed(),
+        }
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [<Db as Database>::TypeInfo],
+    ) -> BoxFuture<'e, Result<<Db as HasStatement<'q>>::Statement>>
+    where
+        'c: 'e,
+    {
+        match self.pool() {
+            Ok(pool) => pool.prepare_with(sql, parameters),
+            Err(error) => async { Err(error) }.boxed(),
+        }
+    }
+
+    fn describe<'e, 'q: 'e>(self, sql: &'q str) -> BoxFuture<'e, Result<Describe<Db>>>
+    where
+        'c: 'e,
+    {
+        match self.pool() {
+            Ok(pool) => pool.describe(sql),
+            Err(error) => async { Err(error) }.boxed(),
+        }
+    }
+}
+
+impl<'c> Reborrow<'c> for &'c MockExecutor {
+    type Reborrowed<'r>
+        = &'r MockExecutor
+    where
+        Self: 'r;
+
+    fn reborrow(&mut self) -> Self::Reborrowed<'_> {
+        self
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(all(test, feature = "postgres"))]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{BillTagged, RenameRow, RenameTagged, Row, Tagged},
+        Table,
+    };
+
+    const UPDATE: &str = "UPDATE rows SET name = $1 WHERE id = $2";
+
+    fn row() -> Row {
+        Row {
+            id: 1,
+            name: "a".into(),
+        }
+    }
+
+    fn statement(sql: &str, arguments: &[&str]) -> Statement {
+        Statement {
+            sql: sql.to_owned(),
+            arguments: arguments
+                .iter()
+                .map(|&argument| argument.to_owned())
+                .collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn update_binds_all_columns() {
+        let db = MockExecutor::new();
+        row().update(&db).await.unwrap();
+        assert_eq!(db.executed(), [statement(UPDATE, &[r#""a""#, "1"])]);
+    }
+
+    #[tokio::test]
+    async fn patch_binds_the_patched_columns() {
+        let db = MockExecutor::new();
+        let mut row = row();
+        row.patch(&db, RenameRow { name: "b".into() })
+            .await
+            .unwrap();
+        assert_eq!(row.name, "b");
+        assert_eq!(db.executed(), [statement(UPDATE, &[r#""b""#, "1"])]);
+    }
+
+    #[tokio::test]
+    async fn setter_binds_the_value() {
+        let db = MockExecutor::new();
+        let mut row = row();
+        row.set_name(&db, "c".into()).await.unwrap();
+        assert_eq!(row.name, "c");
+        assert_eq!(db.executed(), [statement(UPDATE, &[r#""c""#, "1"])]);
+    }
+
+    #[tokio::test]
+    async fn getters_bind_their_argument() {
+        let db = MockExecutor::new();
+        assert!(matches!(
+            Row::get(&db, 7).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+        assert_eq!(Row::by_name(&db, "d").await.unwrap(), None);
+        assert_eq!(
+            db.executed(),
+            [
+                statement("SELECT id, name FROM rows WHERE id = $1", &["7"]),
+                statement("SELECT id, name FROM rows WHERE name = $1", &[r#""d""#]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn fail_next_fails_the_next_statements() {
+        let db = MockExecutor::new();
+        db.fail_next(sqlx::Error::PoolTimedOut);
+        db.fail_next(sqlx::Error::WorkerCrashed);
+        let mut row = row();
+        assert!(matches!(
+            row.update(&db).await,
+            Err(sqlx::Error::PoolTimedOut)
+        ));
+        assert!(matches!(
+            row.set_name(&db, "e".into()).await,
+            Err(sqlx::Error::WorkerCrashed)
+        ));
+        // the value isn't assigned if the statement failed
+        assert_eq!(row.name, "a");
+        row.update(&db).await.unwrap();
+        assert_eq!(
+            db.take_statements(),
+            [UPDATE, UPDATE, UPDATE].map(str::to_owned)
+        );
+        assert!(db.statements().is_empty());
+    }
+
+    fn tagged() -> Tagged {
+        Tagged {
+            id: 1,
+            name: "a".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn patches_inherit_the_tag_of_the_table() {
+        let db = MockExecutor::new();
+        let mut row = tagged();
+        let _ = row.patch(&db, RenameTagged { name: "b".into() }).await;
+        let _ = row.patch(&db, BillTagged { name: "c".into() }).await;
+        assert_eq!(
+            db.statements(),
+            [
+                "/* ormx:table=tagged,op=patch_row */ UPDATE tagged SET name = $1 WHERE id = $2",
+                "/* billing:table=tagged,op=patch_row */ UPDATE tagged SET name = $1 WHERE id = $2",
+            ]
+        );
+    }
+}
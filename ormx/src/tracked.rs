@@ -0,0 +1,246 @@
+//! Tracking of changed fields, so that only changed columns are updated.
+//!
+//! For tables annotated with `#[ormx(tracked)]`, a row can be wrapped in [Tracked], which
+//! remembers the values it was loaded with. [Tracked::save] then only updates the columns which
+//! have changed since, which doesn't overwrite concurrent changes to other columns.
+//!
+//! ```rust,ignore
+//! let mut user = Tracked::new(User::get(&db, id).await?);
+//! user.email = "john@example.com".to_owned();
+//! // UPDATE users SET email = $1 WHERE id = $2
+//! user.save(&db).await?;
+//! ```
+//!
+//! Like the [select](crate::select) query builder, these queries are not checked at compile time.
+
+use std::ops::{Deref, DerefMut};
+
+use futures::future::BoxFuture;
+use sqlx::{Executor, QueryBuilder, Result};
+
+use crate::{Db, Reborrow, TableMeta};
+
+/// A table whose changed columns can be determined by comparing it to a previous version of the
+/// row. This is implemented by `#[derive(ormx::Table)]` when using `#[ormx(tracked)]`, which
+/// requires the table to implement `Clone` and all of its fields to implement `PartialEq`.
+pub trait Track: TableMeta + Clone + Send + Sync {
+    /// Returns the columns which differ between `self` and `original`, as indices into
+    /// [TableMeta::COLUMNS]. The ID is never included.
+    fn changed_columns(&self, original: &Self) -> Vec<usize>;
+
+    /// Pushes `{column} = {value}` for the column at `index` in [TableMeta::COLUMNS].
+    /// This is used by [Tracked::save] with the indices returned by [Track::changed_columns],
+    /// and panics if `index` isn't one of them.
+    #[doc(hidden)]
+    fn push_assignment<'a>(&'a self, index: usize, query: &mut QueryBuilder<'a, Db>);
+
+    /// Pushes `{id column} = {id}`.
+    #[doc(hidden)]
+    fn push_id<'a>(&'a self, query: &mut QueryBuilder<'a, Db>);
+
+    /// Called before the changes are saved, running the hooks of the table.
+    fn before_save<'a>(
+        &'a mut self,
+        _db: impl Executor<'a, Database = Db> + 'a,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(futures::future::ok(()))
+    }
+
+    /// Called after [Track::before_save], running the validation of the table.
+    fn validate_changes(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called after the changes have been saved.
+    fn after_save<'a>(
+        &'a self,
+        _db: impl Executor<'a, Database = Db> + 'a,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(futures::future::ok(()))
+    }
+}
+
+/// A row together with the values it was loaded with, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct Tracked<T> {
+    row: T,
+    original: T,
+}
+
+impl<T: Track> Tracked<T> {
+    /// Starts tracking changes to the row.
+    pub fn new(row: T) -> Self {
+        Tracked {
+            original: row.clone(),
+            row,
+        }
+    }
+
+    /// Whether any column has been changed.
+    pub fn is_dirty(&self) -> bool {
+        !self.row.changed_columns(&self.original).is_empty()
+    }
+
+    /// Returns the names of the columns which have been changed.
+    pub fn changed_columns(&self) -> Vec<&'static str> {
+        self.row
+            .changed_columns(&self.original)
+            .into_iter()
+            .map(|index| T::COLUMNS[index].name)
+            .collect()
+    }
+
+    /// Updates the changed columns, if any, and continues tracking changes from there.
+    /// Changes made by the hooks of the table are saved as well.
+    pub async fn save<'c>(&mut self, mut db: impl Reborrow<'c>) -> Result<()> {
+        if !self.is_dirty() {
+            return Ok(());
+        }
+
+        self.row.before_save(db.reborrow()).await?;
+        self.row.validate_changes()?;
+        let changed = self.row.changed_columns(&self.original);
+        if changed.is_empty() {
+            return Ok(());
+        }
+        let sql = format!("UPDATE {} SET ", T::TABLE);
+        let mut query = QueryBuilder::new(crate::tag_sql::<T>("save", &sql));
+        for (idx, column) in changed.into_iter().enumerate() {
+            if idx > 0 {
+                query.push(", ");
+            }
+            self.row.push_assignment(column, &mut query);
+        }
+        query.push(" WHERE ");
+        self.row.push_id(&mut query);
+        query.build().execute(db.reborrow()).await?;
+        self.row.after_save(db.reborrow()).await?;
+
+        self.original = self.row.clone();
+        Ok(())
+    }
+
+    /// Discards all changes, restoring the values the row was loaded with.
+    pub fn reset(&mut self) {
+        self.row = self.original.clone();
+    }
+
+    /// Stops tracking changes, returning the row.
+    pub fn into_inner(self) -> T {
+        self.row
+    }
+}
+
+impl<T> Deref for Tracked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.row
+    }
+}
+
+impl<T> DerefMut for Tracked<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.row
+    }
+}
+
+#[cfg(all(test, feature = "postgres"))]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{Account, Tagged},
+        testing::MockExecutor,
+        validate::ValidationErrors,
+    };
+
+    fn account() -> Tracked<Account> {
+        Tracked::new(Account {
+            id: 1,
+            email: "john@example.com".into(),
+            age: 30,
+        })
+    }
+
+    #[test]
+    fn changed_columns_exclude_the_id() {
+        let mut row = account();
+        assert!(!row.is_dirty());
+        assert!(row.changed_columns().is_empty());
+
+        row.id = 2;
+        row.age = 31;
+        assert_eq!(row.changed_columns(), ["age"]);
+
+        row.email = "jane@example.com".into();
+        assert_eq!(row.changed_columns(), ["email", "age"]);
+
+        row.reset();
+        assert!(!row.is_dirty());
+        assert_eq!(row.id, 1);
+    }
+
+    #[tokio::test]
+    async fn save_without_changes_does_nothing() {
+        let db = MockExecutor::new();
+        let mut row = account();
+        row.save(&db).await.unwrap();
+        assert!(db.statements().is_empty());
+    }
+
+    #[tokio::test]
+    async fn save_updates_only_the_changed_columns() {
+        let db = MockExecutor::new();
+        let mut row = account();
+        row.age = 31;
+        row.save(&db).await.unwrap();
+        assert_eq!(
+            db.take_statements(),
+            ["UPDATE accounts SET age = $1 WHERE id = $2"]
+        );
+
+        // the saved values are tracked from now on
+        assert!(!row.is_dirty());
+        row.save(&db).await.unwrap();
+        assert!(db.statements().is_empty());
+    }
+
+    #[tokio::test]
+    async fn save_keeps_changes_of_the_hooks() {
+        let db = MockExecutor::new();
+        let mut row = account();
+        row.email = "Jane@Example.com".into();
+        row.save(&db).await.unwrap();
+        assert_eq!(row.email, "jane@example.com");
+        assert_eq!(
+            db.statements(),
+            ["UPDATE accounts SET email = $1 WHERE id = $2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn save_validates_the_row() {
+        let db = MockExecutor::new();
+        let mut row = account();
+        row.email.clear();
+        let err = row.save(&db).await.unwrap_err();
+        assert!(ValidationErrors::from_error(&err).is_some());
+        assert!(db.statements().is_empty());
+        assert!(row.is_dirty());
+    }
+
+    #[tokio::test]
+    async fn save_is_tagged() {
+        let db = MockExecutor::new();
+        let mut row = Tracked::new(Tagged {
+            id: 1,
+            name: "a".into(),
+        });
+        row.name = "b".into();
+        row.save(&db).await.unwrap();
+        assert_eq!(
+            db.statements(),
+            ["/* ormx:table=tagged,op=save */ UPDATE tagged SET name = $1 WHERE id = $2"]
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! Runs closures in a transaction, which is committed if they succeed and rolled back otherwise.
+//!
+//! The future returned by the closure borrows the transaction, which can only be expressed using a
+//! boxed future, so its body has to be wrapped in `Box::pin(async move { .. })`:
+//!
+//! ```rust,ignore
+//! let user = ormx::transaction(&pool, |tx| {
+//!     Box::pin(async move {
+//!         let user = InsertUser { .. }.insert(&mut *tx).await?;
+//!         InsertAddress { user_id: user.id, .. }.insert(&mut *tx).await?;
+//!         Ok::<_, sqlx::Error>(user)
+//!     })
+//! })
+//! .await?;
+//! ```
+
+use std::time::{Duration, SystemTime};
+
+use futures::future::BoxFuture;
+use sqlx::{Pool, Transaction};
+
+use crate::Db;
+
+/// How often, and after which delay, a transaction is retried if it failed because of a
+/// serialization failure or a deadlock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+/// An error returned from a transaction, which might have been caused by the database.
+pub trait TransactionError: From<sqlx::Error> {
+    /// Returns the error of the database, if this error was caused by it.
+    fn database_error(&self) -> Option<&sqlx::Error>;
+}
+
+impl TransactionError for sqlx::Error {
+    fn database_error(&self) -> Option<&sqlx::Error> {
+        Some(self)
+    }
+}
+
+impl Retry {
+    /// Runs the transaction at most `attempts` times. Between two attempts, the delay starts at
+    /// 10ms and doubles each time, up to 1s.
+    pub fn new(attempts: u32) -> Self {
+        Retry {
+            attempts,
+            backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// Sets the delay before the first retry, which doubles with each following one up to `max`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// The delay before the retry after the given attempt, starting at 1.
+    /// A random jitter of up to half the delay is added, so that conflicting transactions don't
+    /// keep running into each other.
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self.backoff.saturating_mul(factor).min(self.max_backoff);
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |now| now.subsec_nanos());
+        delay + delay / 2 * (nanos % 1000) / 1000
+    }
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry::new(3)
+    }
+}
+
+/// Begins a transaction and passes it to `f`. If the returned future resolves to `Ok`, the
+/// transaction is committed, otherwise it's rolled back.
+///
+/// The future returned by `f` has to be boxed, see the [module docs](self):
+///
+/// ```rust,ignore
+/// ormx::transaction(&pool, |tx| {
+///     Box::pin(async move {
+///         user.set_email(&mut *tx, email).await?;
+///         Ok::<_, sqlx::Error>(())
+///     })
+/// })
+/// .await?;
+/// ```
+pub async fn transaction<T, E, F>(pool: &Pool<Db>, f: F) -> Result<T, E>
+where
+    F: for<'c> FnOnce(&'c mut Transaction<'static, Db>) -> BoxFuture<'c, Result<T, E>>,
+    E: From<sqlx::Error>,
+{
+    let mut tx = pool.begin().await?;
+    match f(&mut tx).await {
+        Ok(value) => {
+            tx.commit().await?;
+            Ok(value)
+        }
+        Err(err) => {
+            // the error of the transaction is more relevant than one during the rollback, and
+            // dropping the transaction rolls it back anyway
+            let _ = tx.rollback().await;
+            Err(err)
+        }
+    }
+}
+
+/// Like [transaction], but retries the transaction if it failed because of a serialization
+/// failure or a deadlock, see [is_retryable]. Since `f` may be called multiple times, it's an
+/// `FnMut`, and everything moved into the returned future has to be cloned:
+///
+/// ```rust,ignore
+/// ormx::transaction_with_retry(&pool, Retry::new(5), |tx| {
+///     let email = email.clone();
+///     Box::pin(async move {
+///         let mut user = User::get(&mut *tx, user_id).await?;
+///         user.set_email(&mut *tx, email).await?;
+///         Ok::<_, sqlx::Error>(user)
+///     })
+/// })
+/// .await?;
+/// ```
+///
+/// Retries are delayed using the timer of the async runtime chosen by the `runtime-*` feature of
+/// sqlx.
+pub async fn transaction_with_retry<T, E, F>(
+    pool: &Pool<Db>,
+    retry: Retry,
+    mut f: F,
+) -> Result<T, E>
+where
+    F: for<'c> FnMut(&'c mut Transaction<'static, Db>) -> BoxFuture<'c, Result<T, E>>,
+    E: TransactionError,
+{
+    let mut attempt = 1;
+    loop {
+        let result = transaction(pool, &mut f).await;
+        match result {
+            Err(err)
+                if attempt < retry.attempts && err.database_error().is_some_and(is_retryable) =>
+            {
+                sqlx_rt::sleep(retry.delay(attempt)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Returns `true` if the error is a serialization failure or a deadlock, after which the
+/// transaction can be retried.
+pub fn is_retryable(err: &sqlx::Error) -> bool {
+    let err = match err {
+        sqlx::Error::Database(err) => err,
+        _ => return false,
+    };
+
+    #[cfg(feature = "mysql")]
+    if let Some(err) = err.try_downcast_ref::<sqlx::mysql::MySqlDatabaseError>() {
+        // ER_LOCK_DEADLOCK
+        return err.number() == 1213;
+    }
+
+    matches!(err.code().as_deref(), Some("40001") | Some("40P01"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        borrow::Cow,
+        error::Error as StdError,
+        fmt::{self, Display, Formatter},
+    };
+
+    use sqlx::error::DatabaseError;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct CodeError(&'static str);
+
+    impl Display for CodeError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            write!(f, "error {}", self.0)
+        }
+    }
+
+    impl StdError for CodeError {}
+
+    impl DatabaseError for CodeError {
+        fn message(&self) -> &str {
+            "error"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(self.0.into())
+        }
+
+        fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+            self
+        }
+    }
+
+    fn database_error(code: &'static str) -> sqlx::Error {
+        sqlx::Error::Database(Box::new(CodeError(code)))
+    }
+
+    #[test]
+    fn retries_serialization_failures_and_deadlocks() {
+        assert!(is_retryable(&database_error("40001")));
+        assert!(is_retryable(&database_error("40P01")));
+        assert!(!is_retryable(&database_error("23505")));
+        assert!(!is_retryable(&sqlx::Error::RowNotFound));
+        assert!(!is_retryable(&sqlx::Error::PoolTimedOut));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let retry = Retry::new(10).backoff(Duration::from_millis(10), Duration::from_millis(50));
+        for (attempt, expected) in [(1, 10), (2, 20), (3, 40), (4, 50), (9, 50)] {
+            let expected = Duration::from_millis(expected);
+            let delay = retry.delay(attempt);
+            assert!(
+                delay >= expected && delay <= expected + expected / 2,
+                "delay {:?} after attempt {} should be within 50% above {:?}",
+                delay,
+                attempt,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn backoff_does_not_overflow() {
+        let retry = Retry::new(u32::MAX);
+        assert!(retry.delay(u32::MAX) <= Duration::from_millis(1500));
+    }
+}
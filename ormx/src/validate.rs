@@ -0,0 +1,272 @@
+//! Validation of rows before they are written to the database.
+//!
+//! With `#[ormx(validate)]`, the fields of a table or patch can be annotated with
+//! `#[ormx(length(min = 1, max = 255))]`, `#[ormx(range(min = 0))]` or
+//! `#[ormx(regex = EMAIL_REGEX)]`. The generated insert, update and patch methods then check
+//! these constraints before running any SQL, and return an error containing all
+//! [ValidationErrors] if they are violated.
+//!
+//! # Catching validation errors
+//! Since the generated methods return a `sqlx::Error`, validation errors are wrapped into one.
+//! Converting it into a [WriteError] separates them from errors of the database:
+//!
+//! ```rust,ignore
+//! use ormx::WriteError;
+//!
+//! match user.update(&db).await.map_err(WriteError::from) {
+//!     Ok(()) => ..,
+//!     Err(WriteError::Invalid(errors)) => return Err(BadRequest(errors.to_string())),
+//!     Err(WriteError::Sqlx(err)) => return Err(err.into()),
+//! }
+//! ```
+//!
+//! Only the validation errors can be retrieved using [ValidationErrors::from_error] as well.
+
+use std::{borrow::Cow, error::Error, fmt};
+
+use crate::TransactionError;
+
+/// A type whose constraints can be checked before it is written to the database.
+/// This is implemented by `#[derive(ormx::Table)]` and `#[derive(ormx::Patch)]` when using
+/// `#[ormx(validate)]`.
+pub trait Validate {
+    /// Checks all constraints, returning every one which is violated.
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+/// All constraints violated by a row.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationErrors {
+    pub errors: Vec<ValidationError>,
+}
+
+/// A constraint violated by a field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// The field violating the constraint. For fields of embedded structs, this is the path to
+    /// the field, e.g. `address.city`.
+    pub field: &'static str,
+    pub kind: ValidationErrorKind,
+}
+
+/// The constraint violated by a field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationErrorKind {
+    /// The length of the field is out of bounds (`#[ormx(length(..))]`).
+    Length {
+        min: Option<usize>,
+        max: Option<usize>,
+    },
+    /// The field is out of bounds (`#[ormx(range(..))]`). The bounds are given as they were
+    /// written in the attribute.
+    Range {
+        min: Option<&'static str>,
+        max: Option<&'static str>,
+    },
+    /// The field doesn't match the regular expression (`#[ormx(regex = ..)]`).
+    Regex,
+}
+
+impl ValidationErrors {
+    /// Returns the validation errors contained in an error returned by a generated method.
+    /// To handle all other errors as well, convert it into a [WriteError] instead.
+    pub fn from_error(err: &sqlx::Error) -> Option<&Self> {
+        match err {
+            sqlx::Error::Decode(err) => err.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn push(&mut self, field: &'static str, kind: ValidationErrorKind) {
+        self.errors.push(ValidationError { field, kind });
+    }
+
+    #[doc(hidden)]
+    pub fn into_result(self) -> Result<(), Self> {
+        match self.errors.is_empty() {
+            true => Ok(()),
+            false => Err(self),
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, error) in self.errors.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ", self.field)?;
+        match &self.kind {
+            ValidationErrorKind::Length { min, max } => match (min, max) {
+                (Some(min), Some(max)) => write!(f, "must have a length of {} to {}", min, max),
+                (Some(min), None) => write!(f, "must have a length of at least {}", min),
+                (None, Some(max)) => write!(f, "must have a length of at most {}", max),
+                (None, None) => write!(f, "has an invalid length"),
+            },
+            ValidationErrorKind::Range { min, max } => match (min, max) {
+                (Some(min), Some(max)) => write!(f, "must be between {} and {}", min, max),
+                (Some(min), None) => write!(f, "must be at least {}", min),
+                (None, Some(max)) => write!(f, "must be at most {}", max),
+                (None, None) => write!(f, "is out of range"),
+            },
+            ValidationErrorKind::Regex => write!(f, "has an invalid format"),
+        }
+    }
+}
+
+impl Error for ValidationErrors {}
+
+/// Since the generated methods return a `sqlx::Error`, validation errors are wrapped in
+/// `sqlx::Error::Decode`. Convert the error into a [WriteError] to retrieve them.
+impl From<ValidationErrors> for sqlx::Error {
+    fn from(errors: ValidationErrors) -> Self {
+        sqlx::Error::Decode(Box::new(errors))
+    }
+}
+
+/// An error returned by a generated method which writes rows, separating validation errors from
+/// errors of the database, see the [module docs](self).
+#[derive(Debug)]
+pub enum WriteError {
+    /// The row violates the constraints of its table, and no SQL has been run.
+    Invalid(ValidationErrors),
+    /// The query failed.
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for WriteError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::Decode(err) if err.is::<ValidationErrors>() => {
+                WriteError::Invalid(*err.downcast().unwrap())
+            }
+            err => WriteError::Sqlx(err),
+        }
+    }
+}
+
+impl From<ValidationErrors> for WriteError {
+    fn from(errors: ValidationErrors) -> Self {
+        WriteError::Invalid(errors)
+    }
+}
+
+impl From<WriteError> for sqlx::Error {
+    fn from(err: WriteError) -> Self {
+        match err {
+            WriteError::Invalid(errors) => errors.into(),
+            WriteError::Sqlx(err) => err,
+        }
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::Invalid(errors) => write!(f, "invalid row: {}", errors),
+            WriteError::Sqlx(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for WriteError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WriteError::Invalid(errors) => Some(errors),
+            WriteError::Sqlx(err) => Some(err),
+        }
+    }
+}
+
+impl TransactionError for WriteError {
+    fn database_error(&self) -> Option<&sqlx::Error> {
+        match self {
+            WriteError::Invalid(_) => None,
+            WriteError::Sqlx(err) => Some(err),
+        }
+    }
+}
+
+/// A value whose length can be checked using `#[ormx(length(..))]`.
+/// The length of strings is the number of characters, not bytes.
+pub trait Length {
+    fn length(&self) -> usize;
+}
+
+impl Length for str {
+    fn length(&self) -> usize {
+        self.chars().count()
+    }
+}
+
+impl Length for String {
+    fn length(&self) -> usize {
+        self.as_str().length()
+    }
+}
+
+impl Length for Cow<'_, str> {
+    fn length(&self) -> usize {
+        self.as_ref().length()
+    }
+}
+
+impl<T> Length for [T] {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T> Length for Vec<T> {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T: Length + ?Sized> Length for &T {
+    fn length(&self) -> usize {
+        (**self).length()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors() -> ValidationErrors {
+        let mut errors = ValidationErrors::default();
+        errors.push("name", ValidationErrorKind::Regex);
+        errors
+    }
+
+    #[test]
+    fn write_error_separates_validation_errors() {
+        let err = sqlx::Error::from(errors());
+        assert_eq!(ValidationErrors::from_error(&err), Some(&errors()));
+        match WriteError::from(err) {
+            WriteError::Invalid(invalid) => assert_eq!(invalid, errors()),
+            WriteError::Sqlx(err) => panic!("unexpected error {}", err),
+        }
+
+        let err = WriteError::from(sqlx::Error::RowNotFound);
+        assert!(matches!(err, WriteError::Sqlx(sqlx::Error::RowNotFound)));
+        assert!(err.database_error().is_some());
+    }
+
+    #[test]
+    fn write_error_converts_back() {
+        let err = sqlx::Error::from(WriteError::Invalid(errors()));
+        assert_eq!(ValidationErrors::from_error(&err), Some(&errors()));
+        assert!(WriteError::from(errors()).database_error().is_none());
+    }
+}